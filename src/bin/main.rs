@@ -7,8 +7,9 @@ fn main() {
     let mut builder = Builder::new();
     builder.add_field("author".to_string());
     builder.add_field("isbn".to_string());
+    builder.add_field("title".to_string());
 
-    let data = vec![
+    let data = [
         ("1234567890", "Lunr.js in Action", "John Smith"),
         ("5678901234", "Management Petabytes", "Nur Major"),
         ("0022446688", "The Art of Full-test search", "Shreya Gamble"),
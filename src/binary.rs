@@ -0,0 +1,1036 @@
+use crate::document::{DocId, DocRefs, Document};
+use crate::field::{FieldId, FieldRef};
+use crate::fst::Fst;
+use crate::index::{
+    evaluate, plan, FieldVectors, Index, IndexReader, InvertedIndex, MatchResult, Metadata,
+};
+use crate::postings::{PostingList, PostingsIter};
+use crate::query::Query;
+use crate::regex::Regex;
+use crate::store::{self, DocumentStore};
+use crate::token::TokenSet;
+use crate::vector::Vector;
+
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Write};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::OnceLock;
+
+// The binary index is a header followed by sections.  All integers are
+// little-endian and positions are byte offsets from the start of the file.
+//
+//   header:        magic, format version, offset of each section (u64)
+//   fields:        string table, sorted
+//   doc refs:      string table, sorted; a doc id is a position in this table
+//   terms:         string table, sorted
+//   postings:      per term (term index u64, blob offset u32), then a blob with
+//                  a varint length and a serialized PostingList per field
+//   field vectors: (doc id, field id, offset, len) sorted by doc id and field id,
+//                  then packed (u32 index, f64 value) elements
+//   token set:     the token set encoded as by TokenSet::to_bytes
+//   pipeline:      string table in pipeline order
+//   store:         (doc id, offset, len) per stored document sorted by doc id,
+//                  then a blob of the deflated fields
+//   metadata:      (term id, offset) per term with token metadata sorted by term
+//                  id, then a blob with the metadata per field and doc id
+//   boosts:        (doc id, boost f64) per boosted document sorted by doc id
+//
+// A string table is a count, count + 1 offsets (u32) and the UTF-8 bytes.
+const MAGIC: &[u8; 4] = b"SGMI";
+const FORMAT_VERSION: u32 = 6;
+
+const FIELDS: usize = 0;
+const DOC_REFS: usize = 1;
+const TERMS: usize = 2;
+const POSTINGS: usize = 3;
+const FIELD_VECTORS: usize = 4;
+const TOKEN_SET: usize = 5;
+const PIPELINE: usize = 6;
+const STORE: usize = 7;
+const METADATA: usize = 8;
+const BOOSTS: usize = 9;
+const SECTION_COUNT: usize = 10;
+
+const HEADER_SIZE: usize = 8 + 8 * SECTION_COUNT;
+const POSTING_ENTRY_SIZE: usize = 12;
+const FIELD_VECTOR_ENTRY_SIZE: usize = 16;
+const ELEMENT_SIZE: usize = 12;
+const STORE_ENTRY_SIZE: usize = 12;
+const METADATA_ENTRY_SIZE: usize = 8;
+const BOOST_ENTRY_SIZE: usize = 12;
+
+pub(crate) fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+// try_read_varint reads a varint like read_varint, but fails on a truncated
+// or overlong varint instead of panicking
+pub(crate) fn try_read_varint(data: &[u8], pos: &mut usize) -> io::Result<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let b = *data
+            .get(*pos)
+            .ok_or_else(|| invalid_data("truncated varint"))?;
+        *pos += 1;
+        if shift > 63 {
+            return Err(invalid_data("varint too long"));
+        }
+        value |= ((b & 0x7f) as u64) << shift;
+        if b < 0x80 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub(crate) fn read_varint(data: &[u8], pos: &mut usize) -> u64 {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let b = data[*pos];
+        *pos += 1;
+        value |= ((b & 0x7f) as u64) << shift;
+        if b < 0x80 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+fn write_string_table(buf: &mut Vec<u8>, strings: &[&str]) {
+    buf.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+    let mut offset = 0u32;
+    buf.extend_from_slice(&offset.to_le_bytes());
+    for s in strings {
+        offset += s.len() as u32;
+        buf.extend_from_slice(&offset.to_le_bytes());
+    }
+    for s in strings {
+        buf.extend_from_slice(s.as_bytes());
+    }
+}
+
+impl Index {
+    // to_bytes encodes the index in the binary format.  Offsets and counts
+    // within a section and term indexes are u32, so it fails if a section
+    // would exceed 4 GiB or a term index does not fit.
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut fields: Vec<&str> = self.field_names().iter().map(|f| f.as_str()).collect();
+        fields.sort();
+
+        // doc ids in the file are positions in the sorted doc refs
+        let mut doc_refs: Vec<(&str, DocId)> = self
+            .doc_refs()
+            .iter()
+            .map(|(doc_id, doc_ref)| (doc_ref, doc_id))
+            .collect();
+        doc_refs.sort();
+        let doc_ids: HashMap<DocId, u32> = doc_refs
+            .iter()
+            .enumerate()
+            .map(|(id, (_, doc_id))| (*doc_id, id as u32))
+            .collect();
+        let doc_refs: Vec<&str> = doc_refs.into_iter().map(|(doc_ref, _)| doc_ref).collect();
+
+        let mut terms: Vec<&str> = self.inverted_index().keys().map(|t| t.as_str()).collect();
+        terms.sort();
+        // field vector elements store term indexes as u32
+        if self
+            .inverted_index()
+            .values()
+            .any(|ri| ri.index > u32::MAX as u64)
+        {
+            return Err(io::Error::other("term index exceeds u32"));
+        }
+
+        let mut sections: Vec<Vec<u8>> = vec![Vec::new(); SECTION_COUNT];
+        write_string_table(&mut sections[FIELDS], &fields);
+        write_string_table(&mut sections[DOC_REFS], &doc_refs);
+        write_string_table(&mut sections[TERMS], &terms);
+
+        let mut blob: Vec<u8> = Vec::new();
+        let buf = &mut sections[POSTINGS];
+        buf.extend_from_slice(&(terms.len() as u32).to_le_bytes());
+        for term in terms.iter() {
+            let ri = self.inverted_index().get(*term).unwrap();
+            buf.extend_from_slice(&ri.index.to_le_bytes());
+            buf.extend_from_slice(&(blob.len() as u32).to_le_bytes());
+            for field in fields.iter() {
+                let docs = match ri.documents.get(*field) {
+                    Some(docs) if !docs.is_empty() => docs,
+                    _ => {
+                        write_varint(&mut blob, 0);
+                        continue;
+                    }
+                };
+                let mut postings: Vec<_> = docs.postings().collect();
+                for posting in postings.iter_mut() {
+                    posting.doc_id = doc_ids[&posting.doc_id];
+                }
+                postings.sort_by_key(|posting| posting.doc_id);
+                let mut list = PostingList::new(docs.format());
+                for posting in postings {
+                    list.insert_posting(posting);
+                }
+                let bytes = list.to_bytes();
+                write_varint(&mut blob, bytes.len() as u64);
+                blob.extend_from_slice(&bytes);
+            }
+        }
+        buf.extend_from_slice(&blob);
+
+        // field ids of an index are already positions in the sorted fields
+        let mut entries: Vec<(u32, u32, &Vector)> = self
+            .field_vectors()
+            .iter()
+            .map(|((doc_id, field_id), vector)| (doc_ids[doc_id], *field_id, vector))
+            .collect();
+        entries.sort_by_key(|(doc_id, field_id, _)| (*doc_id, *field_id));
+        let mut blob: Vec<u8> = Vec::new();
+        let buf = &mut sections[FIELD_VECTORS];
+        buf.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        let mut offset = 0u32;
+        for (doc_id, field_id, vector) in entries {
+            let flat = vector.to_flat_vec();
+            let len = (flat.len() / 2) as u32;
+            buf.extend_from_slice(&doc_id.to_le_bytes());
+            buf.extend_from_slice(&field_id.to_le_bytes());
+            buf.extend_from_slice(&offset.to_le_bytes());
+            buf.extend_from_slice(&len.to_le_bytes());
+            for pair in flat.chunks(2) {
+                blob.extend_from_slice(&(pair[0] as u32).to_le_bytes());
+                blob.extend_from_slice(&pair[1].to_le_bytes());
+            }
+            offset += len;
+        }
+        buf.extend_from_slice(&blob);
+
+        sections[TOKEN_SET] = self.token_set().to_bytes();
+
+        let pipeline: Vec<&str> = self.pipeline().iter().map(|l| l.as_str()).collect();
+        write_string_table(&mut sections[PIPELINE], &pipeline);
+
+        let stored: Vec<(u32, &[u8])> = doc_refs
+            .iter()
+            .enumerate()
+            .filter_map(|(id, d)| Some((id as u32, self.document_store().compressed(d)?)))
+            .collect();
+        let mut blob: Vec<u8> = Vec::new();
+        let buf = &mut sections[STORE];
+        buf.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+        for (doc_id, compressed) in stored {
+            buf.extend_from_slice(&doc_id.to_le_bytes());
+            buf.extend_from_slice(&(blob.len() as u32).to_le_bytes());
+            buf.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            blob.extend_from_slice(compressed);
+        }
+        buf.extend_from_slice(&blob);
+
+        let mut entries: Vec<(u32, u32)> = Vec::new();
+        let mut blob: Vec<u8> = Vec::new();
+        for (term_id, term) in terms.iter().enumerate() {
+            let ri = self.inverted_index().get(*term).unwrap();
+            if ri.metadata.is_empty() {
+                continue;
+            }
+            entries.push((term_id as u32, blob.len() as u32));
+            let mut field_ids: Vec<(usize, &str)> = ri
+                .metadata
+                .keys()
+                .filter_map(|f| Some((fields.binary_search(&f.as_str()).ok()?, f.as_str())))
+                .collect();
+            field_ids.sort_unstable();
+            write_varint(&mut blob, field_ids.len() as u64);
+            for (field_id, field) in field_ids {
+                let docs = &ri.metadata[field];
+                let mut docs: Vec<(u32, &Metadata)> =
+                    docs.iter().map(|(d, m)| (doc_ids[d], m)).collect();
+                docs.sort_by_key(|(doc_id, _)| *doc_id);
+                write_varint(&mut blob, field_id as u64);
+                write_varint(&mut blob, docs.len() as u64);
+                for (doc_id, metadata) in docs {
+                    let mut keys: Vec<&String> = metadata.keys().collect();
+                    keys.sort();
+                    write_varint(&mut blob, doc_id as u64);
+                    write_varint(&mut blob, keys.len() as u64);
+                    for key in keys {
+                        store::write_str(&mut blob, key);
+                        write_varint(&mut blob, metadata[key].len() as u64);
+                        for value in metadata[key].iter() {
+                            store::write_value(&mut blob, value);
+                        }
+                    }
+                }
+            }
+        }
+        let buf = &mut sections[METADATA];
+        buf.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        for (term_id, offset) in entries {
+            buf.extend_from_slice(&term_id.to_le_bytes());
+            buf.extend_from_slice(&offset.to_le_bytes());
+        }
+        buf.extend_from_slice(&blob);
+
+        let mut boosts: Vec<(u32, f64)> = self
+            .document_boosts()
+            .iter()
+            .map(|(doc_id, boost)| (doc_ids[doc_id], *boost))
+            .collect();
+        boosts.sort_by_key(|(doc_id, _)| *doc_id);
+        let buf = &mut sections[BOOSTS];
+        buf.extend_from_slice(&(boosts.len() as u32).to_le_bytes());
+        for (doc_id, boost) in boosts {
+            buf.extend_from_slice(&doc_id.to_le_bytes());
+            buf.extend_from_slice(&boost.to_le_bytes());
+        }
+
+        if sections
+            .iter()
+            .any(|section| section.len() > u32::MAX as usize)
+        {
+            return Err(io::Error::other("index section exceeds 4 GiB"));
+        }
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        let mut offset = HEADER_SIZE as u64;
+        for section in sections.iter() {
+            bytes.extend_from_slice(&offset.to_le_bytes());
+            offset += section.len() as u64;
+        }
+        for section in sections {
+            bytes.extend_from_slice(&section);
+        }
+        Ok(bytes)
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.to_bytes()?)
+    }
+}
+
+enum Data {
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+impl Deref for Data {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Data::Mapped(m) => m,
+            Data::Owned(v) => v,
+        }
+    }
+}
+
+// MappedIndex reads an index in the binary format in place.  Opening it only
+// checks the header and the section table, so it takes the same time for any
+// file size.  Everything else is read with checked reads when accessed, so a
+// corrupted file gives empty results instead of panics; check validates the
+// whole file up front.
+pub struct MappedIndex {
+    data: Data,
+    sections: [usize; SECTION_COUNT],
+    // whether the token set is valid, checked when it is first walked
+    token_set: OnceLock<bool>,
+    complete_doc_ids: OnceLock<HashSet<DocId>>,
+}
+
+// EMPTY_TOKEN_SET is the encoding of a token set without terms, read in place
+// of a corrupted one
+const EMPTY_TOKEN_SET: &[u8] = b"SGTS\x00";
+
+impl MappedIndex {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<MappedIndex> {
+        let file = File::open(path)?;
+        // The file must not be modified while it is mapped.
+        let mmap = unsafe { Mmap::map(&file)? };
+        MappedIndex::new(Data::Mapped(mmap))
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> io::Result<MappedIndex> {
+        MappedIndex::new(Data::Owned(bytes))
+    }
+
+    fn new(data: Data) -> io::Result<MappedIndex> {
+        if data.len() < HEADER_SIZE || &data[0..4] != MAGIC {
+            return Err(invalid_data("not a sagume index"));
+        }
+        let mut index = MappedIndex {
+            data,
+            sections: [0; SECTION_COUNT],
+            token_set: OnceLock::new(),
+            complete_doc_ids: OnceLock::new(),
+        };
+        let version = index.u32_at(4);
+        if version != FORMAT_VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported format version {}",
+                version
+            )));
+        }
+        let mut prev = HEADER_SIZE as u64;
+        for i in 0..SECTION_COUNT {
+            let offset = index.u64_at(8 + 8 * i);
+            if offset < prev || offset > index.data.len() as u64 {
+                return Err(invalid_data("section out of range"));
+            }
+            index.sections[i] = offset as usize;
+            prev = offset;
+        }
+
+        // the tables at the start of the sections are read by position, so
+        // their sizes are checked here
+        for section in [FIELDS, DOC_REFS, TERMS, PIPELINE] {
+            index.string_table(section)?;
+        }
+        let (n, _) = index.check_table(POSTINGS, POSTING_ENTRY_SIZE)?;
+        if n != index.term_count() {
+            return Err(invalid_data("corrupted postings"));
+        }
+        index.check_table(FIELD_VECTORS, FIELD_VECTOR_ENTRY_SIZE)?;
+        index.check_table(STORE, STORE_ENTRY_SIZE)?;
+        index.check_table(METADATA, METADATA_ENTRY_SIZE)?;
+        index.check_table(BOOSTS, BOOST_ENTRY_SIZE)?;
+        Ok(index)
+    }
+
+    // check reads the whole file with checked reads, and fails if any part of
+    // it is corrupted
+    pub fn check(&self) -> io::Result<()> {
+        self.check_string_table(FIELDS, true)?;
+        self.check_string_table(DOC_REFS, true)?;
+        self.check_string_table(TERMS, true)?;
+        self.check_string_table(PIPELINE, false)?;
+        self.check_postings()?;
+        self.check_field_vectors()?;
+        Fst::new(self.section(TOKEN_SET))?;
+        self.check_store()?;
+        self.check_metadata()?;
+        self.check_boosts()?;
+        Ok(())
+    }
+
+    // section returns the bytes of the section, which ends where the next
+    // one starts
+    fn section(&self, section: usize) -> &[u8] {
+        let end = match self.sections.get(section + 1) {
+            Some(end) => *end,
+            None => self.data.len(),
+        };
+        &self.data[self.sections[section]..end]
+    }
+
+    // check_table checks that a section starts with a count of entries of the
+    // given size, and returns the count and the position following them
+    fn check_table(&self, section: usize, entry_size: usize) -> io::Result<(usize, usize)> {
+        let bytes = self.section(section);
+        if bytes.len() < 4 {
+            return Err(invalid_data("truncated section"));
+        }
+        let n = self.u32_at(self.sections[section]) as usize;
+        if (bytes.len() - 4) / entry_size < n {
+            return Err(invalid_data("truncated section"));
+        }
+        Ok((n, self.sections[section] + 4 + entry_size * n))
+    }
+
+    // string_table checks that a section starts with a string table whose
+    // offsets fit in it, and returns the count of strings
+    fn string_table(&self, section: usize) -> io::Result<usize> {
+        let (n, _) = self.check_table(section, 4)?;
+        let bytes = self.sections[section] + 4 + 4 * (n + 1);
+        if bytes > self.sections[section] + self.section(section).len() {
+            return Err(invalid_data("corrupted string table"));
+        }
+        Ok(n)
+    }
+
+    fn check_string_table(&self, section: usize, sorted: bool) -> io::Result<()> {
+        let invalid = || invalid_data("corrupted string table");
+        let n = self.string_table(section)?;
+        let mut prev: Option<&str> = None;
+        for i in 0..n {
+            let s = self.string_at(section, i).ok_or_else(invalid)?;
+            if sorted && prev.is_some_and(|prev| prev >= s) {
+                return Err(invalid());
+            }
+            prev = Some(s);
+        }
+        Ok(())
+    }
+
+    // check_postings checks the postings of every term and field
+    fn check_postings(&self) -> io::Result<()> {
+        let invalid = || invalid_data("corrupted postings");
+        let (n, blob) = self.check_table(POSTINGS, POSTING_ENTRY_SIZE)?;
+        if n != self.term_count() {
+            return Err(invalid());
+        }
+        let end = self.sections[POSTINGS] + self.section(POSTINGS).len();
+        let data = &self.data[..end];
+        let doc_count = self.doc_count();
+        for term_id in 0..n {
+            let entry = self.sections[POSTINGS] + 4 + POSTING_ENTRY_SIZE * term_id;
+            let mut pos = blob + self.u32_at(entry + 8) as usize;
+            for _ in 0..self.table_len(FIELDS) {
+                let len = try_read_varint(data, &mut pos)? as usize;
+                if len == 0 {
+                    continue;
+                }
+                let bytes = data
+                    .get(pos..)
+                    .and_then(|rest| rest.get(..len))
+                    .ok_or_else(invalid)?;
+                for doc_id in PostingsIter::from_bytes(bytes)? {
+                    if doc_id as usize >= doc_count {
+                        return Err(invalid());
+                    }
+                }
+                pos += len;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_field_vectors(&self) -> io::Result<()> {
+        let invalid = || invalid_data("corrupted field vectors");
+        let (n, elements) = self.check_table(FIELD_VECTORS, FIELD_VECTOR_ENTRY_SIZE)?;
+        let end = self.sections[FIELD_VECTORS] + self.section(FIELD_VECTORS).len();
+        let element_count = ((end - elements) / ELEMENT_SIZE) as u64;
+        let mut prev: Option<(u32, u32)> = None;
+        for entry in 0..n {
+            let pos = self.sections[FIELD_VECTORS] + 4 + FIELD_VECTOR_ENTRY_SIZE * entry;
+            let key = (self.u32_at(pos), self.u32_at(pos + 4));
+            let offset = self.u32_at(pos + 8) as u64;
+            let len = self.u32_at(pos + 12) as u64;
+            if key.0 as usize >= self.doc_count()
+                || key.1 as usize >= self.table_len(FIELDS)
+                || prev.is_some_and(|prev| prev >= key)
+                || offset + len > element_count
+            {
+                return Err(invalid());
+            }
+            prev = Some(key);
+        }
+        Ok(())
+    }
+
+    fn check_store(&self) -> io::Result<()> {
+        let invalid = || invalid_data("corrupted document store");
+        let (n, _) = self.check_table(STORE, STORE_ENTRY_SIZE)?;
+        let end = self.sections[STORE] + self.section(STORE).len();
+        let mut prev: Option<usize> = None;
+        for entry in 0..n {
+            let pos = self.sections[STORE] + 4 + STORE_ENTRY_SIZE * entry;
+            let doc_id = self.u32_at(pos) as usize;
+            let start = self.sections[STORE] + 4 + STORE_ENTRY_SIZE * n;
+            let start = start + self.u32_at(pos + 4) as usize;
+            let len = self.u32_at(pos + 8) as usize;
+            if doc_id >= self.doc_count()
+                || prev.is_some_and(|prev| prev >= doc_id)
+                || start + len > end
+            {
+                return Err(invalid());
+            }
+            prev = Some(doc_id);
+            let (_, compressed) = self.stored_at(entry).ok_or_else(invalid)?;
+            store::decode(self.doc_ref(doc_id), compressed)?;
+        }
+        Ok(())
+    }
+
+    fn check_boosts(&self) -> io::Result<()> {
+        let (n, _) = self.check_table(BOOSTS, BOOST_ENTRY_SIZE)?;
+        let mut prev: Option<usize> = None;
+        for entry in 0..n {
+            let (doc_id, _) = self.boost_at(entry);
+            if doc_id >= self.doc_count() || prev.is_some_and(|prev| prev >= doc_id) {
+                return Err(invalid_data("corrupted boosts"));
+            }
+            prev = Some(doc_id);
+        }
+        Ok(())
+    }
+
+    fn check_metadata(&self) -> io::Result<()> {
+        let (n, _) = self.check_table(METADATA, METADATA_ENTRY_SIZE)?;
+        let mut prev: Option<usize> = None;
+        for entry in 0..n {
+            let pos = self.sections[METADATA] + 4 + METADATA_ENTRY_SIZE * entry;
+            let term_id = self.u32_at(pos) as usize;
+            if term_id >= self.term_count() || prev.is_some_and(|prev| prev >= term_id) {
+                return Err(invalid_data("corrupted metadata"));
+            }
+            prev = Some(term_id);
+            self.read_metadata(self.u32_at(pos + 4) as usize)?;
+        }
+        Ok(())
+    }
+
+    fn u32_at(&self, pos: usize) -> u32 {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(&self.data[pos..pos + 4]);
+        u32::from_le_bytes(buf)
+    }
+
+    fn u64_at(&self, pos: usize) -> u64 {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(&self.data[pos..pos + 8]);
+        u64::from_le_bytes(buf)
+    }
+
+    fn f64_at(&self, pos: usize) -> f64 {
+        f64::from_bits(self.u64_at(pos))
+    }
+
+    fn table_len(&self, section: usize) -> usize {
+        self.u32_at(self.sections[section]) as usize
+    }
+
+    // string_at returns the i-th string of the table, or None if it is out of
+    // range or corrupted.  The offsets of the table are checked when opened.
+    fn string_at(&self, section: usize, i: usize) -> Option<&str> {
+        let base = self.sections[section];
+        let n = self.u32_at(base) as usize;
+        if i >= n {
+            return None;
+        }
+        let bytes = base + 4 + 4 * (n + 1);
+        let start = bytes + self.u32_at(base + 4 + 4 * i) as usize;
+        let end = bytes + self.u32_at(base + 4 + 4 * (i + 1)) as usize;
+        let section_end = base + self.section(section).len();
+        if start > end || end > section_end {
+            return None;
+        }
+        std::str::from_utf8(&self.data[start..end]).ok()
+    }
+
+    fn table_get(&self, section: usize, i: usize) -> &str {
+        self.string_at(section, i).unwrap_or_default()
+    }
+
+    fn table_find(&self, section: usize, s: &str) -> Option<usize> {
+        let (mut lo, mut hi) = (0, self.table_len(section));
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.table_get(section, mid).cmp(s) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    pub fn field_names(&self) -> Vec<&str> {
+        (0..self.table_len(FIELDS))
+            .map(|i| self.table_get(FIELDS, i))
+            .collect()
+    }
+
+    pub fn pipeline(&self) -> Vec<&str> {
+        (0..self.table_len(PIPELINE))
+            .map(|i| self.table_get(PIPELINE, i))
+            .collect()
+    }
+
+    pub fn doc_count(&self) -> usize {
+        self.table_len(DOC_REFS)
+    }
+
+    pub fn doc_ref(&self, id: usize) -> &str {
+        self.table_get(DOC_REFS, id)
+    }
+
+    pub fn term_count(&self) -> usize {
+        self.table_len(TERMS)
+    }
+
+    pub fn term(&self, id: usize) -> &str {
+        self.table_get(TERMS, id)
+    }
+
+    pub fn term_index(&self, term: &str) -> Option<u64> {
+        let id = self.table_find(TERMS, term)?;
+        Some(self.u64_at(self.sections[POSTINGS] + 4 + POSTING_ENTRY_SIZE * id))
+    }
+
+    // posting_list returns the serialized postings of the term in the field,
+    // which is empty if no document contains it, or None if it is out of
+    // range
+    fn posting_list(&self, term_id: usize, field_id: usize) -> Option<&[u8]> {
+        let base = self.sections[POSTINGS];
+        let n = self.u32_at(base) as usize;
+        if term_id >= n {
+            return None;
+        }
+        let data = &self.data[..base + self.section(POSTINGS).len()];
+        let blob = base + 4 + POSTING_ENTRY_SIZE * n;
+        let mut pos = blob + self.u32_at(base + 4 + POSTING_ENTRY_SIZE * term_id + 8) as usize;
+        for _ in 0..field_id {
+            let len = try_read_varint(data, &mut pos).ok()?;
+            pos = pos.checked_add(len as usize)?;
+        }
+        let len = try_read_varint(data, &mut pos).ok()? as usize;
+        data.get(pos..)?.get(..len)
+    }
+
+    // postings_at iterates the postings of the term in the field, which are
+    // checked as they are read
+    fn postings_at(&self, term_id: usize, field_id: usize) -> Option<PostingsIter<'_>> {
+        match self.posting_list(term_id, field_id)? {
+            [] => None,
+            bytes => PostingsIter::from_bytes(bytes).ok(),
+        }
+    }
+
+    fn doc_ids(&self, term_id: usize, field_id: usize) -> Vec<usize> {
+        self.postings_at(term_id, field_id)
+            .into_iter()
+            .flatten()
+            .map(|id| id as usize)
+            .collect()
+    }
+
+    // postings_iter iterates the doc ids of the term in the field in place
+    pub fn postings_iter(&self, term: &str, field: &str) -> Option<PostingsIter<'_>> {
+        let term_id = self.table_find(TERMS, term)?;
+        let field_id = self.table_find(FIELDS, field)?;
+        self.postings_at(term_id, field_id)
+    }
+
+    pub fn postings(&self, term: &str, field: &str) -> Vec<&str> {
+        match (self.table_find(TERMS, term), self.table_find(FIELDS, field)) {
+            (Some(term_id), Some(field_id)) => self
+                .doc_ids(term_id, field_id)
+                .into_iter()
+                .map(|id| self.doc_ref(id))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn vector_at(&self, entry: usize) -> (usize, usize, Vector) {
+        let base = self.sections[FIELD_VECTORS];
+        let n = self.u32_at(base) as usize;
+        let pos = base + 4 + FIELD_VECTOR_ENTRY_SIZE * entry;
+        let doc_id = self.u32_at(pos) as usize;
+        let field_id = self.u32_at(pos + 4) as usize;
+        let offset = self.u32_at(pos + 8) as usize;
+        let len = self.u32_at(pos + 12) as usize;
+
+        let elements_start = base + 4 + FIELD_VECTOR_ENTRY_SIZE * n;
+        let element_count =
+            (base + self.section(FIELD_VECTORS).len() - elements_start) / ELEMENT_SIZE;
+        if offset + len > element_count {
+            return (doc_id, field_id, Vector::new());
+        }
+        let mut elements = Vec::with_capacity(len * 2);
+        let mut pos = elements_start + ELEMENT_SIZE * offset;
+        for _ in 0..len {
+            elements.push(self.u32_at(pos) as f64);
+            elements.push(self.f64_at(pos + 4));
+            pos += ELEMENT_SIZE;
+        }
+        (doc_id, field_id, Vector::from_flat_vec(elements))
+    }
+
+    fn find_vector(&self, doc_id: usize, field_id: usize) -> Option<Vector> {
+        let base = self.sections[FIELD_VECTORS];
+        let (mut lo, mut hi) = (0, self.u32_at(base) as usize);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let pos = base + 4 + FIELD_VECTOR_ENTRY_SIZE * mid;
+            let key = (self.u32_at(pos) as usize, self.u32_at(pos + 4) as usize);
+            match key.cmp(&(doc_id, field_id)) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(self.vector_at(mid).2),
+            }
+        }
+        None
+    }
+
+    pub fn field_vector(&self, field_ref: &FieldRef) -> Option<Vector> {
+        let doc_id = self.table_find(DOC_REFS, field_ref.doc_ref())?;
+        let field_id = self.table_find(FIELDS, field_ref.field_name())?;
+        self.find_vector(doc_id, field_id)
+    }
+
+    // token_set returns the term dictionary, read in place.  It is checked
+    // the first time, and read as empty if it is corrupted.
+    pub fn token_set(&self) -> Fst<'_> {
+        let bytes = self.section(TOKEN_SET);
+        if *self.token_set.get_or_init(|| Fst::new(bytes).is_ok()) {
+            Fst::new_unchecked(bytes)
+        } else {
+            Fst::new_unchecked(EMPTY_TOKEN_SET)
+        }
+    }
+
+    // expand returns the terms in the index accepted by the given token set
+    pub fn expand(&self, token_set: &TokenSet) -> Vec<String> {
+        self.token_set().intersect(token_set)
+    }
+
+    pub fn query(&self, query: &Query) -> Vec<MatchResult> {
+        let plan = plan(self, query);
+        evaluate(self, query, &plan, |field_name, field_vector| {
+            plan.query_vectors[field_name].score(field_vector)
+        })
+    }
+
+    // read_metadata reads the token metadata of a term at the offset in the
+    // metadata blob, by field id and doc id
+    fn read_metadata(&self, offset: usize) -> io::Result<HashMap<(usize, usize), Metadata>> {
+        let invalid = || invalid_data("corrupted metadata");
+        let (_, blob) = self.check_table(METADATA, METADATA_ENTRY_SIZE)?;
+        let data = &self.data[..self.sections[METADATA] + self.section(METADATA).len()];
+        let mut pos = blob + offset;
+        let mut result = HashMap::new();
+        for _ in 0..try_read_varint(data, &mut pos)? {
+            let field_id = try_read_varint(data, &mut pos)? as usize;
+            if field_id >= self.table_len(FIELDS) {
+                return Err(invalid());
+            }
+            for _ in 0..try_read_varint(data, &mut pos)? {
+                let doc_id = try_read_varint(data, &mut pos)? as usize;
+                if doc_id >= self.doc_count() {
+                    return Err(invalid());
+                }
+                let mut metadata = Metadata::new();
+                for _ in 0..try_read_varint(data, &mut pos)? {
+                    let key = store::read_str(data, &mut pos)?;
+                    let mut values = Vec::new();
+                    for _ in 0..try_read_varint(data, &mut pos)? {
+                        values.push(store::read_value(data, &mut pos)?);
+                    }
+                    metadata.insert(key, values);
+                }
+                result.insert((field_id, doc_id), metadata);
+            }
+        }
+        Ok(result)
+    }
+
+    // term_metadata returns the token metadata of the term by field id and doc id
+    fn term_metadata(&self, term_id: usize) -> HashMap<(usize, usize), Metadata> {
+        let base = self.sections[METADATA];
+        let (mut lo, mut hi) = (0, self.table_len(METADATA));
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let pos = base + 4 + METADATA_ENTRY_SIZE * mid;
+            match (self.u32_at(pos) as usize).cmp(&term_id) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => {
+                    return self
+                        .read_metadata(self.u32_at(pos + 4) as usize)
+                        .unwrap_or_default();
+                }
+            }
+        }
+        HashMap::new()
+    }
+
+    // stored_at returns the doc id and the compressed fields of a stored
+    // document, or None if they are out of range
+    fn stored_at(&self, entry: usize) -> Option<(usize, &[u8])> {
+        let base = self.sections[STORE];
+        let n = self.u32_at(base) as usize;
+        let pos = base + 4 + STORE_ENTRY_SIZE * entry;
+        let doc_id = self.u32_at(pos) as usize;
+        let start = base + 4 + STORE_ENTRY_SIZE * n + self.u32_at(pos + 4) as usize;
+        let len = self.u32_at(pos + 8) as usize;
+        let compressed = self.data[..base + self.section(STORE).len()].get(start..start + len)?;
+        Some((doc_id, compressed))
+    }
+
+    fn boost_at(&self, entry: usize) -> (usize, f64) {
+        let pos = self.sections[BOOSTS] + 4 + BOOST_ENTRY_SIZE * entry;
+        (self.u32_at(pos) as usize, self.f64_at(pos + 4))
+    }
+
+    // doc returns the stored fields of the document
+    pub fn doc(&self, doc_ref: &str) -> Option<Document> {
+        let doc_id = self.table_find(DOC_REFS, doc_ref)?;
+        let base = self.sections[STORE];
+        let (mut lo, mut hi) = (0, self.u32_at(base) as usize);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let (id, compressed) = self.stored_at(mid)?;
+            match id.cmp(&doc_id) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return store::decode(doc_ref, compressed).ok(),
+            }
+        }
+        None
+    }
+
+    // to_index decodes the whole file into an Index, and fails if it is
+    // corrupted
+    pub fn to_index(&self) -> io::Result<Index> {
+        self.check()?;
+        let field_names = self.field_names();
+        let mut doc_refs = DocRefs::new();
+        for id in 0..self.doc_count() {
+            doc_refs.intern(self.doc_ref(id));
+        }
+
+        let mut inverted_index: HashMap<String, InvertedIndex> = HashMap::new();
+        let mut terms: Vec<String> = Vec::with_capacity(self.term_count());
+        for term_id in 0..self.term_count() {
+            let term = self.term(term_id).to_string();
+            let index = self.u64_at(self.sections[POSTINGS] + 4 + POSTING_ENTRY_SIZE * term_id);
+            let mut ri = InvertedIndex::new(index);
+            for (field_id, field) in field_names.iter().enumerate() {
+                match self.posting_list(term_id, field_id) {
+                    Some([]) | None => {}
+                    Some(bytes) => {
+                        ri.documents
+                            .insert(field.to_string(), PostingList::from_bytes(bytes)?);
+                    }
+                }
+            }
+            for ((field_id, doc_id), metadata) in self.term_metadata(term_id) {
+                ri.metadata
+                    .entry(field_names[field_id].to_string())
+                    .or_default()
+                    .insert(doc_id as DocId, metadata);
+            }
+            inverted_index.insert(term.to_string(), ri);
+            terms.push(term);
+        }
+
+        let mut field_vectors: FieldVectors = HashMap::new();
+        for entry in 0..self.u32_at(self.sections[FIELD_VECTORS]) as usize {
+            let (doc_id, field_id, vector) = self.vector_at(entry);
+            field_vectors.insert((doc_id as DocId, field_id as FieldId), vector);
+        }
+
+        let mut store = DocumentStore::new();
+        for entry in 0..self.u32_at(self.sections[STORE]) as usize {
+            if let Some((doc_id, compressed)) = self.stored_at(entry) {
+                store.insert_compressed(self.doc_ref(doc_id), compressed.to_vec());
+            }
+        }
+
+        let mut index = Index::new(
+            inverted_index,
+            field_vectors,
+            TokenSet::from_array(&terms),
+            field_names.iter().map(|f| f.to_string()).collect(),
+            self.pipeline().iter().map(|l| l.to_string()).collect(),
+            doc_refs,
+        );
+        index.set_document_store(store);
+        index.set_document_boosts(
+            (0..self.table_len(BOOSTS))
+                .map(|entry| {
+                    let (doc_id, boost) = self.boost_at(entry);
+                    (doc_id as DocId, boost)
+                })
+                .collect(),
+        );
+        Ok(index)
+    }
+}
+
+impl IndexReader for MappedIndex {
+    fn field_names(&self) -> Vec<&str> {
+        MappedIndex::field_names(self)
+    }
+
+    fn terms(&self, token_set: &TokenSet) -> Vec<String> {
+        self.expand(token_set)
+    }
+
+    fn regex_terms(&self, regex: &Regex, limit: usize) -> Vec<String> {
+        self.token_set().intersect_regex(regex, limit)
+    }
+
+    fn term_index(&self, term: &str) -> Option<u64> {
+        MappedIndex::term_index(self, term)
+    }
+
+    fn documents_with_term(&self, term: &str) -> usize {
+        match self.table_find(TERMS, term) {
+            Some(term_id) => (0..self.table_len(FIELDS))
+                .map(|field_id| self.doc_ids(term_id, field_id).len())
+                .sum(),
+            None => 0,
+        }
+    }
+
+    fn postings(&self, term: &str, field_name: &str) -> Option<PostingsIter<'_>> {
+        self.postings_iter(term, field_name)
+    }
+
+    fn metadata(&self, term: &str, field_name: &str) -> Cow<'_, HashMap<DocId, Metadata>> {
+        let (term_id, field_id) = match (
+            self.table_find(TERMS, term),
+            self.table_find(FIELDS, field_name),
+        ) {
+            (Some(term_id), Some(field_id)) => (term_id, field_id),
+            _ => return Cow::Owned(HashMap::new()),
+        };
+        Cow::Owned(
+            self.term_metadata(term_id)
+                .into_iter()
+                .filter(|((f, _), _)| *f == field_id)
+                .map(|((_, doc_id), metadata)| (doc_id as DocId, metadata))
+                .collect(),
+        )
+    }
+
+    fn field_vector(&self, doc_id: DocId, field_name: &str) -> Option<Cow<'_, Vector>> {
+        let field_id = self.table_find(FIELDS, field_name)?;
+        self.find_vector(doc_id as usize, field_id).map(Cow::Owned)
+    }
+
+    fn field_vector_keys(&self) -> Vec<(DocId, &str)> {
+        let base = self.sections[FIELD_VECTORS];
+        (0..self.table_len(FIELD_VECTORS))
+            .map(|entry| {
+                let pos = base + 4 + FIELD_VECTOR_ENTRY_SIZE * entry;
+                let field_id = self.u32_at(pos + 4) as usize;
+                (self.u32_at(pos), self.table_get(FIELDS, field_id))
+            })
+            .collect()
+    }
+
+    fn complete_doc_ids(&self) -> &HashSet<DocId> {
+        self.complete_doc_ids.get_or_init(|| {
+            let base = self.sections[FIELD_VECTORS];
+            (0..self.table_len(FIELD_VECTORS))
+                .filter_map(|entry| {
+                    let pos = base + 4 + FIELD_VECTOR_ENTRY_SIZE * entry;
+                    let doc_id = self.u32_at(pos);
+                    let non_empty = self.u32_at(pos + 12) > 0;
+                    (non_empty && (doc_id as usize) < self.doc_count()).then_some(doc_id)
+                })
+                .collect()
+        })
+    }
+
+    fn doc_ref(&self, doc_id: DocId) -> &str {
+        MappedIndex::doc_ref(self, doc_id as usize)
+    }
+}
@@ -1,34 +1,217 @@
-use crate::document::Document;
-use crate::field::FieldRef;
-use crate::index::{Index, InvertedIndex};
+use crate::document::{DocId, DocRefs, Document};
+use crate::field::{Field, FieldValue};
+use crate::index::{field_ids, FieldVectors, Index, InvertedIndex, Metadata};
 use crate::pipeline::Pipeline;
-use crate::token::TokenSet;
+use crate::postings::{Posting, PostingList, PostingsFormat};
+use crate::similarity::{Bm25, Similarity, TermStats};
+use crate::store::DocumentStore;
+use crate::token::{Token, TokenSet};
 use crate::tokenizer::Tokenizer;
 use crate::vector::Vector;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+type Extractor = dyn Fn(&Document) -> Option<String> + Send + Sync;
+
+// FieldOptions configures how a field is indexed.  The boost scales the
+// field's scores, the extractor derives the field value from the document
+// instead of reading the field of the same name, and the tokenizer and
+// pipeline replace the builder's ones for the field.  A stored field keeps
+// its value in the index's document store to be returned with results.
+#[derive(Clone)]
+pub struct FieldOptions {
+    boost: f64,
+    stored: bool,
+    extractor: Option<Arc<Extractor>>,
+    tokenizer: Option<Tokenizer>,
+    pipeline: Option<Pipeline>,
+}
+
+impl Default for FieldOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FieldOptions {
+    pub fn new() -> FieldOptions {
+        FieldOptions {
+            boost: 1.0,
+            stored: false,
+            extractor: None,
+            tokenizer: None,
+            pipeline: None,
+        }
+    }
+
+    pub fn set_boost(&mut self, boost: f64) {
+        self.boost = boost;
+    }
+
+    pub fn set_stored(&mut self, stored: bool) {
+        self.stored = stored;
+    }
+
+    pub fn set_extractor<T>(&mut self, extractor: T)
+    where
+        T: Fn(&Document) -> Option<String> + Send + Sync + 'static,
+    {
+        self.extractor = Some(Arc::new(extractor));
+    }
+
+    pub fn set_tokenizer(&mut self, tokenizer: Tokenizer) {
+        self.tokenizer = Some(tokenizer);
+    }
+
+    pub fn set_pipeline(&mut self, pipeline: Pipeline) {
+        self.pipeline = Some(pipeline);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MergeError {
+    DuplicateDocuments(Vec<String>),
+    // MissingTermFrequencies is returned for an index whose postings do not
+    // record the term frequencies needed to rescore its documents
+    MissingTermFrequencies,
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergeError::DuplicateDocuments(doc_refs) => {
+                write!(f, "documents in both indexes: {}", doc_refs.join(", "))
+            }
+            MergeError::MissingTermFrequencies => {
+                write!(f, "postings of the index record no term frequencies")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+// AnalyzedDocument is a document tokenized and counted by a builder, ready
+// to be added to the postings
+struct AnalyzedDocument {
+    doc_ref: String,
+    fields: HashMap<String, AnalyzedField>,
+    stored_fields: Vec<Field>,
+}
+
+struct AnalyzedField {
+    length: usize,
+    terms: Vec<(String, Vec<u32>, Metadata)>, // distinct terms in order of appearance, with positions and metadata
+}
+
+// AbsorbedField is a field of a document taken from another builder or index
+#[derive(Default)]
+struct AbsorbedField<'a> {
+    length: usize,
+    postings: Vec<(&'a str, u64, Posting, Option<&'a Metadata>)>, // term, term index and posting in the other, metadata
+}
+
+// absorbed_documents groups the postings of an inverted index by document and
+// field, reading each posting list once.  The length of a field is the sum of
+// the term frequencies of its postings.
+fn absorbed_documents(
+    inverted_index: &HashMap<String, InvertedIndex>,
+) -> HashMap<DocId, HashMap<&str, AbsorbedField<'_>>> {
+    let mut documents: HashMap<DocId, HashMap<&str, AbsorbedField>> = HashMap::new();
+    for (term, ri) in inverted_index {
+        for (field_name, postings) in ri.documents.iter() {
+            for posting in postings.postings() {
+                let metadata = ri.get_metadata(field_name, posting.doc_id);
+                let field = documents
+                    .entry(posting.doc_id)
+                    .or_default()
+                    .entry(field_name)
+                    .or_default();
+                field.length += posting.term_freq as usize;
+                field.postings.push((term, ri.index, posting, metadata));
+            }
+        }
+    }
+    documents
+}
+
+// CollectionStats are the statistics of a collection of documents that the
+// weights of their terms depend on
+#[derive(Clone, Default)]
+pub(crate) struct CollectionStats {
+    pub doc_count: usize,
+    pub field_totals: HashMap<String, (usize, usize)>, // field_name -> total length, number of documents
+    pub documents_with_term: HashMap<String, usize>,
+    pub collection_term_freqs: HashMap<(String, String), usize>, // (term, field_name) -> total term frequency
+}
+
+impl CollectionStats {
+    // add adds the statistics of another collection of documents
+    pub fn add(&mut self, other: CollectionStats) {
+        self.doc_count += other.doc_count;
+        for (field_name, (total, count)) in other.field_totals {
+            let totals = self.field_totals.entry(field_name).or_default();
+            totals.0 += total;
+            totals.1 += count;
+        }
+        for (term, count) in other.documents_with_term {
+            *self.documents_with_term.entry(term).or_default() += count;
+        }
+        for (key, freq) in other.collection_term_freqs {
+            *self.collection_term_freqs.entry(key).or_default() += freq;
+        }
+    }
+}
 
 pub struct Builder {
     field_names: HashSet<String>,
+    field_options: Vec<(String, FieldOptions)>, // in order of declaration
     inverted_index: HashMap<String, InvertedIndex>,
-    field_term_frequencies: HashMap<FieldRef, HashMap<String, usize>>,
-    field_lengths: HashMap<FieldRef, usize>,
+    field_term_frequencies: HashMap<(DocId, String), HashMap<String, usize>>,
+    field_lengths: HashMap<(DocId, String), usize>,
+    field_totals: HashMap<String, (usize, usize)>, // field_name -> total length, number of documents
+    collection_term_freqs: HashMap<(String, String), usize>, // (term, field_name) -> total term frequency
+    document_fields: HashMap<DocId, HashSet<String>>,        // doc_id -> []field_name
+    document_boosts: HashMap<DocId, f64>,
+    doc_refs: DocRefs,
+    store: DocumentStore,
+    metadata_whitelist: Vec<String>,
+    postings_format: PostingsFormat,
     tokenizer: Tokenizer,
     pipeline: Pipeline,
+    similarity: Option<Box<dyn Similarity>>,
     b: f64,
     k1: f64,
     term_index: u64,
     document_count: usize,
 }
 
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Builder {
     pub fn new() -> Builder {
         Builder {
             field_names: HashSet::new(),
+            field_options: Vec::new(),
             inverted_index: HashMap::new(),
             field_term_frequencies: HashMap::new(),
             field_lengths: HashMap::new(),
+            field_totals: HashMap::new(),
+            collection_term_freqs: HashMap::new(),
+            document_fields: HashMap::new(),
+            document_boosts: HashMap::new(),
+            doc_refs: DocRefs::new(),
+            store: DocumentStore::new(),
+            metadata_whitelist: Vec::new(),
+            postings_format: PostingsFormat::Freqs,
             tokenizer: Tokenizer::new(),
             pipeline: Pipeline::new(),
+            similarity: None,
             b: 0.75,
             k1: 1.2,
             term_index: 0,
@@ -37,124 +220,536 @@ impl Builder {
     }
 
     pub fn add_field(&mut self, name: String) {
-        self.field_names.insert(name);
+        self.add_field_with(name, FieldOptions::new());
+    }
+
+    pub fn add_field_with(&mut self, name: String, options: FieldOptions) {
+        self.field_names.insert(name.to_string());
+        match self.field_options.iter_mut().find(|(n, _)| *n == name) {
+            Some(entry) => entry.1 = options,
+            None => self.field_options.push((name, options)),
+        }
+    }
+
+    pub(crate) fn field_boost(&self, name: &str) -> f64 {
+        self.field_options
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, o)| o.boost)
+            .unwrap_or(1.0)
+    }
+
+    pub(crate) fn document_boost(&self, doc_id: DocId) -> f64 {
+        *self.document_boosts.get(&doc_id).unwrap_or(&1.0)
     }
 
+    // add_document indexes the declared fields of the document, replacing a
+    // document already added with the same doc_ref
     pub fn add_document(&mut self, doc: Document) {
-        let doc_ref = doc.doc_ref();
+        self.add_document_with_boost(doc, 1.0);
+    }
 
-        self.document_count += 1;
+    // add_document_with_boost adds the document with its scores scaled by the
+    // boost, to rank the document higher or lower for the same match
+    pub fn add_document_with_boost(&mut self, doc: Document, boost: f64) {
+        let analyzed = self.analyze(&doc);
+        self.apply(analyzed, boost);
+    }
+
+    // add_documents_parallel adds the documents in order as add_document does,
+    // on the given number of threads
+    pub fn add_documents_parallel(&mut self, docs: Vec<Document>, threads: usize) {
+        let docs = docs.into_iter().map(|doc| (doc, 1.0)).collect();
+        self.add_documents_parallel_with_boosts(docs, threads);
+    }
 
-        for field in doc.get_all_fields().iter() {
-            let field_value = &field.value().to_string();
-            let tokens = self.tokenizer.tokenize(field_value);
-            let terms = self.pipeline.run(tokens);
-            let field_ref = FieldRef::new(doc_ref.into(), field.name().into());
+    // add_documents_parallel_with_boosts adds the documents with their boosts
+    // in order as add_document_with_boost does.  The documents are split into
+    // one run per thread, each thread adds its run to an empty builder with
+    // the fields, tokenizer and pipeline of this one, and the postings of the
+    // runs are then merged into this builder in order.
+    pub fn add_documents_parallel_with_boosts(
+        &mut self,
+        docs: Vec<(Document, f64)>,
+        threads: usize,
+    ) {
+        let threads = threads.max(1);
+        let chunk_size = docs.len().div_ceil(threads).max(1);
+        let mut chunks: Vec<Vec<(Document, f64)>> = Vec::new();
+        let mut docs = docs.into_iter().peekable();
+        while docs.peek().is_some() {
+            chunks.push(docs.by_ref().take(chunk_size).collect());
+        }
 
-            self.field_lengths.insert(
-                field_ref.clone(),
-                self.field_lengths.get(&field_ref).unwrap_or(&0) + terms.len(),
-            );
+        let partials: Vec<Builder> = std::thread::scope(|scope| {
+            let workers: Vec<_> = chunks
+                .into_iter()
+                .map(|chunk| {
+                    let mut builder = self.empty_clone();
+                    scope.spawn(move || {
+                        for (doc, boost) in chunk {
+                            builder.add_document_with_boost(doc, boost);
+                        }
+                        builder
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        });
 
-            let mut field_terms: HashMap<String, usize> = HashMap::new();
-            for term in terms.iter() {
-                field_terms.insert(
-                    term.value().to_string(),
-                    field_terms.get(term.value()).unwrap_or(&0) + 1,
-                );
+        for partial in partials {
+            self.absorb_except(&partial, &HashSet::new());
+        }
+    }
+
+    // empty_clone returns a builder without documents that analyzes
+    // documents as this one does
+    fn empty_clone(&self) -> Builder {
+        Builder {
+            field_names: self.field_names.clone(),
+            field_options: self.field_options.clone(),
+            metadata_whitelist: self.metadata_whitelist.clone(),
+            postings_format: self.postings_format,
+            tokenizer: self.tokenizer.clone(),
+            pipeline: self.pipeline.clone(),
+            ..Builder::new()
+        }
+    }
+
+    // analyze tokenizes the declared fields of the document and counts the
+    // terms, without changing the builder
+    fn analyze(&self, doc: &Document) -> AnalyzedDocument {
+        let mut fields: HashMap<String, AnalyzedField> = HashMap::new();
+        let mut stored_fields: Vec<Field> = Vec::new();
+        for (field_name, options) in self.field_options.iter() {
+            let resolved: Vec<FieldValue> = match &options.extractor {
+                Some(extractor) => extractor(doc).into_iter().map(FieldValue::Text).collect(),
+                None => doc.resolve(field_name).into_iter().cloned().collect(),
+            };
+            if resolved.is_empty() {
+                continue;
+            }
+            let values: Vec<String> = resolved.iter().map(|v| v.to_string()).collect();
+            if options.stored {
+                let value = if resolved.len() == 1 {
+                    resolved.into_iter().next().unwrap()
+                } else {
+                    FieldValue::Array(resolved)
+                };
+                stored_fields.push(Field::new(field_name.to_string(), value));
+            }
+            let tokenizer = options.tokenizer.as_ref().unwrap_or(&self.tokenizer);
+            let pipeline = options.pipeline.as_ref().unwrap_or(&self.pipeline);
+            let mut tokens: Vec<Token> = Vec::new();
+            for value in values {
+                tokens.extend(pipeline.run(tokenizer.tokenize(&value)));
+            }
+
+            let mut field = AnalyzedField {
+                length: tokens.len(),
+                terms: Vec::new(),
+            };
+            let mut term_ids: HashMap<String, usize> = HashMap::new();
+            for (position, token) in tokens.into_iter().enumerate() {
+                let i = *term_ids
+                    .entry(token.value().to_string())
+                    .or_insert_with(|| {
+                        field
+                            .terms
+                            .push((token.value().to_string(), Vec::new(), Metadata::new()));
+                        field.terms.len() - 1
+                    });
+                let (_, positions, metadata) = &mut field.terms[i];
+                positions.push(position as u32);
+                for key in self.metadata_whitelist.iter() {
+                    if let Some(value) = token.metadata(key) {
+                        metadata
+                            .entry(key.to_string())
+                            .or_default()
+                            .push(value.clone());
+                    }
+                }
+            }
+            fields.insert(field_name.to_string(), field);
+        }
+        stored_fields.sort_by(|a, b| a.name().cmp(b.name()));
+        AnalyzedDocument {
+            doc_ref: doc.doc_ref().to_string(),
+            fields,
+            stored_fields,
+        }
+    }
+
+    // apply adds an analyzed document to the postings and statistics, replacing
+    // a document with the same doc_ref.  New terms get indexes in the order
+    // they appear in the document.
+    fn apply(&mut self, mut analyzed: AnalyzedDocument, boost: f64) {
+        let doc_ref = analyzed.doc_ref.as_str();
+        self.remove_document(doc_ref);
+        let doc_id = self.doc_refs.intern(doc_ref);
+        self.document_count += 1;
+        let mut field_names: HashSet<String> = HashSet::new();
+
+        let declared: Vec<String> = self
+            .field_options
+            .iter()
+            .map(|(n, _)| n.to_string())
+            .collect();
+        for field_name in declared {
+            let field = match analyzed.fields.remove(&field_name) {
+                Some(field) => field,
+                None => continue,
+            };
+            let field_ref = (doc_id, field_name.to_string());
+            self.field_lengths.insert(field_ref.clone(), field.length);
 
-                if !self.inverted_index.contains_key(term.value()) {
+            let mut field_terms: HashMap<String, usize> = HashMap::new();
+            for (term, positions, metadata) in field.terms {
+                if !self.inverted_index.contains_key(&term) {
                     self.term_index += 1;
                 }
-                let mut ridx = self
+                let term_index = self.term_index;
+                let term_freq = positions.len();
+                let posting = Posting {
+                    doc_id,
+                    term_freq: term_freq as u32,
+                    positions,
+                };
+                let format = self.postings_format;
+                let ridx = self
                     .inverted_index
-                    .remove(term.value())
-                    .unwrap_or(InvertedIndex {
-                        index: self.term_index,
-                        documents: HashMap::new(),
-                    });
-                let mut doc_set = ridx
-                    .documents
-                    .remove(field.name())
-                    .unwrap_or(HashSet::new());
-                doc_set.insert(doc_ref.to_string());
-                ridx.documents.insert(field.name().to_string(), doc_set);
-                self.inverted_index.insert(term.value().to_string(), ridx);
+                    .entry(term.to_string())
+                    .or_insert_with(|| InvertedIndex::new(term_index));
+                ridx.documents
+                    .entry(field_name.to_string())
+                    .or_insert_with(|| PostingList::new(format))
+                    .insert_posting(posting);
+                if !metadata.is_empty() {
+                    ridx.metadata
+                        .entry(field_name.to_string())
+                        .or_default()
+                        .insert(doc_id, metadata);
+                }
+                field_terms.insert(term, term_freq);
             }
+            self.count_field(&field_name, field.length, &field_terms, true);
             self.field_term_frequencies.insert(field_ref, field_terms);
+            field_names.insert(field_name);
+        }
+        self.document_fields.insert(doc_id, field_names);
+        if boost != 1.0 {
+            self.document_boosts.insert(doc_id, boost);
+        }
+        if !analyzed.stored_fields.is_empty() {
+            self.store.insert(doc_ref, &analyzed.stored_fields);
+        }
+    }
+
+    // remove_document removes the document from the postings and statistics,
+    // and releases its doc id.  Terms no longer contained in any document are
+    // dropped from the index.
+    pub fn remove_document(&mut self, doc_ref: &str) -> bool {
+        let doc_id = match self.doc_refs.release(doc_ref) {
+            Some(doc_id) => doc_id,
+            None => return false,
+        };
+        let field_names = self.document_fields.remove(&doc_id).unwrap_or_default();
+        self.document_count -= 1;
+        self.document_boosts.remove(&doc_id);
+        self.store.remove(doc_ref);
+
+        for field_name in field_names {
+            let field_ref = (doc_id, field_name.to_string());
+            let length = self.field_lengths.remove(&field_ref).unwrap_or(0);
+            let field_terms = self
+                .field_term_frequencies
+                .remove(&field_ref)
+                .unwrap_or_default();
+            self.count_field(&field_name, length, &field_terms, false);
+            for term in field_terms.keys() {
+                let ridx = match self.inverted_index.get_mut(term) {
+                    Some(ridx) => ridx,
+                    None => continue,
+                };
+                if let Some(doc_set) = ridx.documents.get_mut(&field_name) {
+                    doc_set.remove(doc_id);
+                    if doc_set.is_empty() {
+                        ridx.documents.remove(&field_name);
+                    }
+                }
+                if let Some(docs) = ridx.metadata.get_mut(&field_name) {
+                    docs.remove(&doc_id);
+                    if docs.is_empty() {
+                        ridx.metadata.remove(&field_name);
+                    }
+                }
+                if ridx.documents.is_empty() {
+                    self.inverted_index.remove(term);
+                }
+            }
         }
+        true
+    }
+
+    pub fn contains_document(&self, doc_ref: &str) -> bool {
+        self.doc_refs.contains(doc_ref)
     }
 
+    // b sets the length normalization of BM25, or of the similarity set by
+    // set_similarity.  A similarity set afterwards keeps its own b.
     pub fn b(&mut self, value: f64) {
-        self.b = value.max(0.0).min(1.0);
+        self.b = value.clamp(0.0, 1.0);
+        if let Some(similarity) = &mut self.similarity {
+            similarity.set_b(self.b);
+        }
     }
 
+    // k1 sets the term frequency saturation of BM25, or of the similarity set
+    // by set_similarity.  A similarity set afterwards keeps its own k1.
     pub fn k1(&mut self, value: f64) {
         self.k1 = value;
+        if let Some(similarity) = &mut self.similarity {
+            similarity.set_k1(self.k1);
+        }
+    }
+
+    // metadata_whitelist sets the token metadata keys recorded in the index for
+    // each occurrence of a term, and returned in the match data of results
+    pub fn metadata_whitelist(&mut self, keys: Vec<String>) {
+        self.metadata_whitelist = keys;
+    }
+
+    // set_postings_format sets what the postings of terms added afterwards
+    // record for each document.  Positions are the positions of the term in
+    // the tokens of the field.  The default is Freqs; an index built with
+    // Docs cannot be absorbed.
+    pub fn set_postings_format(&mut self, format: PostingsFormat) {
+        self.postings_format = format;
+    }
+
+    pub fn set_similarity<S>(&mut self, similarity: S)
+    where
+        S: Similarity + 'static,
+    {
+        self.similarity = Some(Box::new(similarity));
+    }
+
+    pub fn pipeline(&mut self) -> &mut Pipeline {
+        &mut self.pipeline
     }
 
     pub fn build(&mut self) -> Index {
-        Index::new(
+        let mut index = Index::new(
             self.inverted_index.clone(),
             self.create_field_vectors(),
             self.create_token_set(),
             self.field_names.clone(),
-        )
+            self.pipeline.labels(),
+            self.doc_refs.clone(),
+        );
+        index.set_document_store(self.store.clone());
+        index.set_document_boosts(self.document_boosts.clone());
+        index
     }
 
-    fn calculate_average_field_length(&self) -> HashMap<String, f64> {
-        let mut accumulator: HashMap<String, f64> = HashMap::new();
-        let mut docs_with_field: HashMap<String, usize> = HashMap::new();
-        for (field_ref, len) in self.field_lengths.iter() {
-            let field_name = field_ref.field_name().to_string();
-
-            let count = docs_with_field.get(&field_name).unwrap_or(&0) + 1;
-            docs_with_field.insert(field_name.to_string(), count);
-
-            let count = *accumulator.get(&field_name).unwrap_or(&0.0) + *len as f64;
-            accumulator.insert(field_name, count);
+    // count_field adds the length and term frequencies of a field of a
+    // document to the collection statistics, or subtracts them when the
+    // document is removed
+    fn count_field(
+        &mut self,
+        field_name: &str,
+        length: usize,
+        field_terms: &HashMap<String, usize>,
+        added: bool,
+    ) {
+        let totals = self.field_totals.entry(field_name.to_string()).or_default();
+        if added {
+            totals.0 += length;
+            totals.1 += 1;
+        } else {
+            totals.0 -= length;
+            totals.1 -= 1;
+        }
+        if totals.1 == 0 {
+            self.field_totals.remove(field_name);
         }
+        for (term, term_freq) in field_terms {
+            let key = (term.to_string(), field_name.to_string());
+            let total = self.collection_term_freqs.entry(key.clone()).or_default();
+            if added {
+                *total += term_freq;
+            } else {
+                *total -= term_freq;
+            }
+            if *total == 0 {
+                self.collection_term_freqs.remove(&key);
+            }
+        }
+    }
 
-        for (field_name, val) in accumulator.iter_mut() {
-            *val = *val / *docs_with_field.get(field_name).unwrap() as f64;
+    fn create_field_vectors(&self) -> FieldVectors {
+        let field_ids = field_ids(&self.field_names);
+        let mut field_vectors: FieldVectors = HashMap::new();
+        for doc_id in self.document_fields.keys() {
+            for (field_name, vector) in self.document_vectors(*doc_id) {
+                field_vectors.insert((*doc_id, field_ids[field_name]), vector);
+            }
         }
-        return accumulator;
+        field_vectors
     }
 
-    fn create_field_vectors(&self) -> HashMap<FieldRef, Vector> {
-        let mut field_vectors: HashMap<FieldRef, Vector> = HashMap::new();
-        let average_field_length = self.calculate_average_field_length();
+    // document_vectors weighs the terms of each field of the document
+    pub(crate) fn document_vectors(&self, doc_id: DocId) -> Vec<(&str, Vector)> {
+        self.weigh_document(doc_id, None, |term| self.inverted_index[term].index)
+    }
 
-        for field_ref in self.field_term_frequencies.keys() {
-            let field_name = field_ref.field_name();
-            let field_length = *self.field_lengths.get(field_ref).unwrap();
-            let term_frequencies = self.field_term_frequencies.get(field_ref).unwrap();
-            let terms = term_frequencies.keys();
+    // document_vectors_in weighs the document as one of a larger collection,
+    // with its statistics and term indexes
+    pub(crate) fn document_vectors_in(
+        &self,
+        doc_id: DocId,
+        stats: &CollectionStats,
+        term_indexes: &HashMap<String, u64>,
+    ) -> Vec<(&str, Vector)> {
+        self.weigh_document(doc_id, Some(stats), |term| term_indexes[term])
+    }
 
-            let mut field_vector = Vector::new();
-            for term in terms {
-                let term_freq = *term_frequencies.get(term).unwrap();
-                let term_index = self.inverted_index.get(term).unwrap().index;
+    fn weigh_document<F>(
+        &self,
+        doc_id: DocId,
+        collection: Option<&CollectionStats>,
+        term_index: F,
+    ) -> Vec<(&str, Vector)>
+    where
+        F: Fn(&str) -> u64,
+    {
+        let field_names = match self.document_fields.get(&doc_id) {
+            Some(field_names) => field_names,
+            None => return Vec::new(),
+        };
+        let mut term_stats: HashMap<&str, Vec<(&str, TermStats)>> = HashMap::new();
+        let mut vectors: HashMap<&str, Vector> = HashMap::new();
+        for field_name in field_names {
+            let field_ref = (doc_id, field_name.to_string());
+            for (term, term_freq) in &self.field_term_frequencies[&field_ref] {
+                term_stats.entry(term).or_default().push((
+                    field_name,
+                    self.term_stats(collection, doc_id, field_name, term, *term_freq),
+                ));
+            }
+            vectors.insert(field_name, Vector::new());
+        }
 
-                let idf =
-                    Builder::idf(&self.inverted_index.get(term).unwrap(), self.document_count);
+        for (term, fields) in term_stats {
+            let term_index = term_index(term) as usize;
+            let stats: Vec<TermStats> = fields.iter().map(|(_, s)| s.clone()).collect();
+            for ((field_name, _), score) in fields.iter().zip(self.weigh(&stats)) {
+                vectors
+                    .get_mut(field_name)
+                    .unwrap()
+                    .insert(term_index, score);
+            }
+        }
+        vectors.into_iter().collect()
+    }
 
-                let k1 = self.k1;
-                let b = self.b;
-                let score = idf * ((k1 + 1.0) * term_freq as f64)
-                    / (k1
-                        * (1.0 - b
-                            + b * (field_length as f64
-                                / average_field_length.get(field_name).unwrap()))
-                        + term_freq as f64);
+    // term_weights weighs the term in each field of the document containing
+    // it, as document_vectors does
+    pub(crate) fn term_weights(&self, doc_id: DocId, term: &str) -> Vec<(&str, f64)> {
+        let mut fields: Vec<(&str, TermStats)> = Vec::new();
+        for field_name in self.document_fields.get(&doc_id).into_iter().flatten() {
+            let field_ref = (doc_id, field_name.to_string());
+            let term_freq = self
+                .field_term_frequencies
+                .get(&field_ref)
+                .and_then(|terms| terms.get(term));
+            if let Some(term_freq) = term_freq {
+                fields.push((
+                    field_name,
+                    self.term_stats(None, doc_id, field_name, term, *term_freq),
+                ));
+            }
+        }
+        let stats: Vec<TermStats> = fields.iter().map(|(_, s)| s.clone()).collect();
+        let scores = self.weigh(&stats);
+        fields
+            .into_iter()
+            .zip(scores)
+            .map(|((field_name, _), score)| (field_name, score))
+            .collect()
+    }
 
-                // TODO need to reduce the precision?
-                let score_wth_precision = (score * 1000.0).round() / 1000.0;
-                field_vector.insert(term_index as usize, score_wth_precision);
+    // term_stats gathers the statistics the term is weighed with in the
+    // field of the document, from the collection if given and from the
+    // builder's own documents otherwise
+    fn term_stats(
+        &self,
+        collection: Option<&CollectionStats>,
+        doc_id: DocId,
+        field_name: &str,
+        term: &str,
+        term_freq: usize,
+    ) -> TermStats {
+        let key = (term.to_string(), field_name.to_string());
+        let (doc_count, (total, count), documents_with_term, collection_term_freq) =
+            match collection {
+                Some(stats) => (
+                    stats.doc_count,
+                    stats.field_totals[field_name],
+                    stats.documents_with_term[term],
+                    stats.collection_term_freqs[&key],
+                ),
+                None => (
+                    self.document_count,
+                    self.field_totals[field_name],
+                    Builder::documents_with_term(&self.inverted_index[term]),
+                    self.collection_term_freqs[&key],
+                ),
+            };
+        TermStats {
+            term_freq,
+            field_length: self.field_length(&(doc_id, field_name.to_string())),
+            average_field_length: total as f64 / count as f64,
+            documents_with_term,
+            doc_count,
+            collection_term_freq,
+            collection_length: total,
+            boost: self.field_boost(field_name) * self.document_boost(doc_id),
+        }
+    }
+
+    // collection_stats returns the statistics terms are weighed with, over
+    // the documents of the builder except the excluded ones
+    pub(crate) fn collection_stats(&self, excluded: &HashSet<DocId>) -> CollectionStats {
+        let mut stats = CollectionStats {
+            doc_count: self.document_count,
+            field_totals: self.field_totals.clone(),
+            documents_with_term: self
+                .inverted_index
+                .iter()
+                .map(|(term, ri)| (term.to_string(), Builder::documents_with_term(ri)))
+                .collect(),
+            collection_term_freqs: self.collection_term_freqs.clone(),
+        };
+        for doc_id in excluded {
+            let field_names = match self.document_fields.get(doc_id) {
+                Some(field_names) => field_names,
+                None => continue,
+            };
+            stats.doc_count -= 1;
+            for field_name in field_names {
+                let field_ref = (*doc_id, field_name.to_string());
+                let totals = stats.field_totals.get_mut(field_name).unwrap();
+                totals.0 -= self.field_length(&field_ref);
+                totals.1 -= 1;
+                for (term, term_freq) in &self.field_term_frequencies[&field_ref] {
+                    *stats.documents_with_term.get_mut(term).unwrap() -= 1;
+                    let key = (term.to_string(), field_name.to_string());
+                    *stats.collection_term_freqs.get_mut(&key).unwrap() -= term_freq;
+                }
             }
-            field_vectors.insert(field_ref.clone(), field_vector);
         }
-        return field_vectors;
+        stats
     }
 
     fn create_token_set(&self) -> TokenSet {
@@ -167,19 +762,251 @@ impl Builder {
         TokenSet::from_array(&tokens)
     }
 
-    fn idf(idx: &InvertedIndex, doc_count: usize) -> f64 {
-        let mut documents_with_term = 0;
-        for doc_refs in idx.documents.values() {
-            documents_with_term += doc_refs.len()
+    // weigh scores a term in the fields of a document with the similarity,
+    // which is BM25 with the builder's b and k1 unless set
+    pub(crate) fn weigh(&self, stats: &[TermStats]) -> Vec<f64> {
+        let scores = match &self.similarity {
+            Some(similarity) => similarity.score_fields(stats),
+            None => Bm25 {
+                k1: self.k1,
+                b: self.b,
+            }
+            .score_fields(stats),
+        };
+        // TODO need to reduce the precision?
+        scores
+            .into_iter()
+            .map(|score| (score * 1000.0).round() / 1000.0)
+            .collect()
+    }
+
+    pub(crate) fn documents_with_term(idx: &InvertedIndex) -> usize {
+        idx.documents.values().map(|doc_refs| doc_refs.len()).sum()
+    }
+
+    pub(crate) fn field_names(&self) -> &HashSet<String> {
+        &self.field_names
+    }
+
+    pub(crate) fn document_fields(&self) -> &HashMap<DocId, HashSet<String>> {
+        &self.document_fields
+    }
+
+    pub(crate) fn doc_refs(&self) -> &DocRefs {
+        &self.doc_refs
+    }
+
+    pub(crate) fn inverted_index(&self) -> &HashMap<String, InvertedIndex> {
+        &self.inverted_index
+    }
+
+    pub(crate) fn document_store(&self) -> &DocumentStore {
+        &self.store
+    }
+
+    // document_terms returns the distinct terms of the fields of the document
+    pub(crate) fn document_terms(&self, doc_ref: &str) -> HashSet<String> {
+        let mut terms: HashSet<String> = HashSet::new();
+        let doc_id = match self.doc_refs.id(doc_ref) {
+            Some(doc_id) => doc_id,
+            None => return terms,
+        };
+        for field_name in self.document_fields.get(&doc_id).into_iter().flatten() {
+            let field_ref = (doc_id, field_name.to_string());
+            if let Some(field_terms) = self.field_term_frequencies.get(&field_ref) {
+                terms.extend(field_terms.keys().cloned());
+            }
+        }
+        terms
+    }
+
+    pub(crate) fn field_length(&self, field_ref: &(DocId, String)) -> usize {
+        *self.field_lengths.get(field_ref).unwrap_or(&0)
+    }
+
+    // absorb adds the documents of an index built independently, possibly in
+    // another process and read back from its binary encoding, to combine
+    // indexes without adding the documents again.  The term frequencies and
+    // field lengths are read from the postings, so the index must be built
+    // with the Freqs postings format, which is the default, or Positions; an
+    // index loaded from lunr.js JSON has none.  The documents are rescored
+    // with the combined statistics on the next build.  Terms new to
+    // this builder get new indexes in the order of their indexes in other.
+    // Fields declared only in other are declared with the default options.
+    // Nothing is absorbed when a doc_ref is in both.
+    pub fn absorb(&mut self, other: &Index) -> Result<(), MergeError> {
+        let mut duplicates: Vec<String> = other
+            .doc_refs()
+            .iter()
+            .filter(|(_, doc_ref)| self.contains_document(doc_ref))
+            .map(|(_, doc_ref)| doc_ref.to_string())
+            .collect();
+        if !duplicates.is_empty() {
+            duplicates.sort();
+            return Err(MergeError::DuplicateDocuments(duplicates));
+        }
+        let without_freqs = other
+            .inverted_index()
+            .values()
+            .flat_map(|ri| ri.documents.values())
+            .any(|postings| postings.format() == PostingsFormat::Docs);
+        if without_freqs {
+            return Err(MergeError::MissingTermFrequencies);
+        }
+
+        let mut field_names: Vec<&String> = other.field_names().iter().collect();
+        field_names.sort();
+        for field_name in field_names {
+            if !self.field_names.contains(field_name) {
+                self.add_field(field_name.to_string());
+            }
+        }
+        self.assign_term_indexes(
+            other
+                .inverted_index()
+                .iter()
+                .map(|(term, ri)| (term.as_str(), ri.index)),
+        );
+
+        let mut documents = absorbed_documents(other.inverted_index());
+        // every field analyzed has a field vector, even without any term
+        for (doc_id, field_id) in other.field_vectors().keys() {
+            documents
+                .entry(*doc_id)
+                .or_default()
+                .entry(other.field_name(*field_id))
+                .or_default();
+        }
+        for (other_id, doc_ref) in other.doc_refs().iter() {
+            let fields = documents.remove(&other_id).unwrap_or_default();
+            self.absorb_document(
+                doc_ref,
+                fields,
+                other.document_boost(other_id),
+                other.document_store().compressed(doc_ref),
+            );
+        }
+        Ok(())
+    }
+
+    // absorb_except copies the postings and statistics of the documents in
+    // other, except for the excluded ones, replacing documents with the same
+    // doc_ref.  Documents are added in the order of their ids in other, so
+    // they get the ids and terms the indexes adding them one by one would
+    // give.  Fields declared only in other are declared with its options,
+    // extractor, tokenizer and pipeline included.
+    pub(crate) fn absorb_except(&mut self, other: &Builder, excluded: &HashSet<DocId>) {
+        for (field_name, options) in other.field_options.iter() {
+            if !self.field_names.contains(field_name) {
+                self.add_field_with(field_name.to_string(), options.clone());
+            }
+        }
+        for field_name in other.field_names.iter() {
+            self.field_names.insert(field_name.to_string());
+        }
+
+        let mut documents = absorbed_documents(&other.inverted_index);
+        for (other_id, doc_ref) in other.doc_refs.iter() {
+            if excluded.contains(&other_id) {
+                continue;
+            }
+            let field_names = match other.document_fields.get(&other_id) {
+                Some(field_names) => field_names,
+                None => continue,
+            };
+            // the postings of other may not record term frequencies, which
+            // are taken from its statistics instead
+            let mut fields = documents.remove(&other_id).unwrap_or_default();
+            for field_name in field_names {
+                let other_ref = (other_id, field_name.to_string());
+                let field = fields.entry(field_name).or_default();
+                field.length = other.field_length(&other_ref);
+                let field_terms = other.field_term_frequencies.get(&other_ref);
+                for (term, _, posting, _) in field.postings.iter_mut() {
+                    let term_freq = field_terms.and_then(|terms| terms.get(*term));
+                    posting.term_freq = *term_freq.unwrap_or(&1) as u32;
+                }
+            }
+            self.absorb_document(
+                doc_ref,
+                fields,
+                other.document_boost(other_id),
+                other.store.compressed(doc_ref),
+            );
         }
-        let x = (doc_count as f64 - documents_with_term as f64 + 0.5)
-            / (documents_with_term as f64 + 0.5);
-        return (x.abs() + 1.0).log(std::f64::consts::E);
     }
-}
 
-#[cfg(test)]
-use crate::field::Field;
+    // assign_term_indexes gives the terms new to this builder new indexes,
+    // in the order of the indexes they had where they come from
+    fn assign_term_indexes<'a, I>(&mut self, terms: I)
+    where
+        I: Iterator<Item = (&'a str, u64)>,
+    {
+        let mut terms: Vec<(&str, u64)> = terms.collect();
+        terms.sort_by_key(|(term, index)| (*index, *term));
+        for (term, _) in terms {
+            if !self.inverted_index.contains_key(term) {
+                self.term_index += 1;
+                self.inverted_index
+                    .insert(term.to_string(), InvertedIndex::new(self.term_index));
+            }
+        }
+    }
+
+    // absorb_document adds a document taken from another builder or index,
+    // replacing a document with the same doc_ref.  Terms new to this builder
+    // get new indexes in the order of their indexes in the other.
+    fn absorb_document(
+        &mut self,
+        doc_ref: &str,
+        fields: HashMap<&str, AbsorbedField>,
+        boost: f64,
+        compressed: Option<&[u8]>,
+    ) {
+        self.remove_document(doc_ref);
+        let doc_id = self.doc_refs.intern(doc_ref);
+        self.document_count += 1;
+        self.assign_term_indexes(fields.values().flat_map(|field| {
+            field
+                .postings
+                .iter()
+                .map(|(term, index, _, _)| (*term, *index))
+        }));
+
+        let mut field_names: HashSet<String> = HashSet::new();
+        for (field_name, field) in fields {
+            let field_ref = (doc_id, field_name.to_string());
+            self.field_lengths.insert(field_ref.clone(), field.length);
+            let mut field_terms: HashMap<String, usize> = HashMap::new();
+            for (term, _, mut posting, metadata) in field.postings {
+                posting.doc_id = doc_id;
+                field_terms.insert(term.to_string(), posting.term_freq as usize);
+                let format = self.postings_format;
+                let ridx = self.inverted_index.get_mut(term).unwrap();
+                ridx.documents
+                    .entry(field_name.to_string())
+                    .or_insert_with(|| PostingList::new(format))
+                    .insert_posting(posting);
+                if let Some(metadata) = metadata {
+                    ridx.metadata
+                        .entry(field_name.to_string())
+                        .or_default()
+                        .insert(doc_id, metadata.clone());
+                }
+            }
+            self.count_field(field_name, field.length, &field_terms, true);
+            self.field_term_frequencies.insert(field_ref, field_terms);
+            field_names.insert(field_name.to_string());
+        }
+        self.document_fields.insert(doc_id, field_names);
+        if boost != 1.0 {
+            self.document_boosts.insert(doc_id, boost);
+        }
+        if let Some(compressed) = compressed {
+            self.store.insert_compressed(doc_ref, compressed.to_vec());
+        }
+    }
+}
 
 #[test]
 fn test_add() {
@@ -190,6 +1017,7 @@ fn test_add() {
     let mut b = Builder::new();
     b.add_field("title".into());
     b.add_document(doc);
+    let doc_id = b.doc_refs.id("1").unwrap();
 
     assert!(b
         .inverted_index
@@ -198,28 +1026,109 @@ fn test_add() {
         .documents
         .get("title")
         .unwrap()
-        .contains("1"));
-    assert!(!b.inverted_index.contains_key("missing".into()));
+        .contains(doc_id));
+    assert!(!b.inverted_index.contains_key("missing"));
+    assert!(!b.inverted_index.contains_key("good"));
     assert_eq!(
         *b.field_term_frequencies
-            .get(&FieldRef::new("1".into(), "title".into()))
+            .get(&(doc_id, "title".into()))
             .unwrap()
-            .get("constructor".into())
+            .get("constructor")
             .unwrap(),
         1
     );
     assert_eq!(b.document_count, 1);
 }
 
+#[test]
+fn test_remove_document() {
+    let mut doc = Document::new("1".into());
+    doc.add_field(Field::new_text("title".into(), "green plant".into()));
+    let mut other = Document::new("2".into());
+    other.add_field(Field::new_text("title".into(), "green".into()));
+
+    let mut b = Builder::new();
+    b.add_field("title".into());
+    b.add_document(doc);
+    b.add_document(other);
+    let doc_id = b.doc_refs.id("1").unwrap();
+
+    assert!(b.remove_document("1"));
+    assert!(!b.remove_document("1"));
+    assert_eq!(b.document_count, 1);
+    assert!(!b.inverted_index.contains_key("plant"));
+    assert!(!b
+        .inverted_index
+        .get("green")
+        .unwrap()
+        .documents
+        .get("title")
+        .unwrap()
+        .contains(doc_id));
+    assert!(!b.field_lengths.contains_key(&(doc_id, "title".into())));
+    assert!(!b.contains_document("1"));
+}
+
+#[test]
+fn test_add_document_twice() {
+    let mut b = Builder::new();
+    b.add_field("title".into());
+    for value in ["green plant", "green"].iter() {
+        let mut doc = Document::new("1".into());
+        doc.add_field(Field::new_text("title".into(), value.to_string()));
+        b.add_document(doc);
+    }
+
+    assert_eq!(b.document_count, 1);
+    assert_eq!(b.doc_refs.len(), 1);
+    assert!(!b.inverted_index.contains_key("plant"));
+    let doc_id = b.doc_refs.id("1").unwrap();
+    assert_eq!(*b.field_lengths.get(&(doc_id, "title".into())).unwrap(), 1);
+}
+
+#[test]
+fn test_field_options() {
+    let mut doc = Document::new("1".into());
+    doc.add_field(Field::new_text("title".into(), "green,plant".into()));
+    doc.add_field(Field::new_text("author".into(), "scarlett".into()));
+
+    let mut b = Builder::new();
+    let mut options = FieldOptions::new();
+    options.set_tokenizer(Tokenizer::with_separator(|c| c == ','));
+    options.set_boost(2.0);
+    b.add_field_with("title".into(), options);
+    let mut options = FieldOptions::new();
+    options.set_extractor(|doc: &Document| doc.get("author").map(|v| format!("miss {}", v)));
+    b.add_field_with("byline".into(), options);
+    b.add_document(doc);
+
+    assert!(b.inverted_index.contains_key("plant"));
+    assert!(b.inverted_index.contains_key("miss"));
+    assert!(b
+        .inverted_index
+        .get("scarlett")
+        .unwrap()
+        .documents
+        .contains_key("byline"));
+    assert!(!b
+        .inverted_index
+        .get("scarlett")
+        .unwrap()
+        .documents
+        .contains_key("author"));
+    assert_eq!(b.field_boost("title"), 2.0);
+    assert_eq!(b.field_boost("byline"), 1.0);
+}
+
 #[test]
 fn test_define_field() {
     let mut b = Builder::new();
     b.add_field("title".into());
     b.add_field("isbn".into());
     b.add_field("author/age".into());
-    assert!(b.field_names.contains("title".into()));
-    assert!(b.field_names.contains("isbn".into()));
-    assert!(b.field_names.contains("author/age".into()));
+    assert!(b.field_names.contains("title"));
+    assert!(b.field_names.contains("isbn"));
+    assert!(b.field_names.contains("author/age"));
 }
 
 #[test]
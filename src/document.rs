@@ -1,5 +1,8 @@
 use crate::field::{Field, FieldValue};
 
+use std::collections::HashMap;
+
+#[derive(Clone)]
 pub struct Document {
     doc_ref: String,
     fields: Vec<Field>,
@@ -14,7 +17,7 @@ impl Document {
     }
 
     pub fn doc_ref(&self) -> &str {
-        return &self.doc_ref;
+        &self.doc_ref
     }
 
     pub fn add_field(&mut self, field: Field) {
@@ -22,7 +25,7 @@ impl Document {
     }
 
     pub fn get_field(&self, name: &str) -> Option<&Field> {
-        self.get_fields(name).first().map(|f| *f)
+        self.get_fields(name).first().copied()
     }
 
     pub fn get_fields(&self, name: &str) -> Vec<&Field> {
@@ -54,7 +57,114 @@ impl Document {
             .collect()
     }
 
+    // resolve returns the values at a field path such as "author/name" or
+    // "tags/*".  The first segment names the field, and the rest are resolved
+    // in its value as FieldValue::resolve does.  A field whose name is the
+    // whole path takes precedence.
+    pub fn resolve(&self, path: &str) -> Vec<&FieldValue> {
+        let values = self.get_values(path);
+        if !values.is_empty() {
+            return values;
+        }
+        let segments: Vec<&str> = path.split('/').collect();
+        self.get_values(segments[0])
+            .into_iter()
+            .flat_map(|v| v.resolve(&segments[1..]))
+            .collect()
+    }
+
     pub fn get(&self, name: &str) -> Option<&FieldValue> {
         self.get_field(name).map(|field| field.value())
     }
 }
+
+// DocId is the position of a doc ref in a DocRefs table
+pub type DocId = u32;
+
+// DocRefs interns doc refs into dense ids, so that postings and field vectors
+// refer to documents by integer.  The id of a released doc ref is reused by
+// the next doc ref interned.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct DocRefs {
+    refs: Vec<String>, // doc_id -> doc_ref, empty if released
+    ids: HashMap<String, DocId>,
+    released: Vec<DocId>,
+}
+
+impl DocRefs {
+    pub fn new() -> DocRefs {
+        DocRefs {
+            refs: Vec::new(),
+            ids: HashMap::new(),
+            released: Vec::new(),
+        }
+    }
+
+    // intern returns the id of the doc ref, assigning a new one if needed
+    pub fn intern(&mut self, doc_ref: &str) -> DocId {
+        if let Some(id) = self.ids.get(doc_ref) {
+            return *id;
+        }
+        let id = match self.released.pop() {
+            Some(id) => {
+                self.refs[id as usize] = doc_ref.to_string();
+                id
+            }
+            None => {
+                self.refs.push(doc_ref.to_string());
+                (self.refs.len() - 1) as DocId
+            }
+        };
+        self.ids.insert(doc_ref.to_string(), id);
+        id
+    }
+
+    // release frees the id of the doc ref, and returns it
+    pub fn release(&mut self, doc_ref: &str) -> Option<DocId> {
+        let id = self.ids.remove(doc_ref)?;
+        self.refs[id as usize].clear();
+        self.released.push(id);
+        Some(id)
+    }
+
+    // assign interns the doc ref under the given free id, to mirror the ids
+    // of another table
+    pub(crate) fn assign(&mut self, doc_ref: &str, id: DocId) {
+        while self.refs.len() <= id as usize {
+            self.released.push(self.refs.len() as DocId);
+            self.refs.push(String::new());
+        }
+        self.released.retain(|released| *released != id);
+        self.refs[id as usize] = doc_ref.to_string();
+        self.ids.insert(doc_ref.to_string(), id);
+    }
+
+    pub fn id(&self, doc_ref: &str) -> Option<DocId> {
+        self.ids.get(doc_ref).copied()
+    }
+
+    pub fn doc_ref(&self, id: DocId) -> &str {
+        &self.refs[id as usize]
+    }
+
+    pub fn contains(&self, doc_ref: &str) -> bool {
+        self.ids.contains_key(doc_ref)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    // iter returns the interned doc refs with their ids, in order of id
+    pub fn iter(&self) -> impl Iterator<Item = (DocId, &str)> {
+        self.refs
+            .iter()
+            .enumerate()
+            .map(|(id, doc_ref)| (id as DocId, doc_ref.as_str()))
+            .filter(move |(id, doc_ref)| self.ids.get(*doc_ref) == Some(id))
+    }
+}
@@ -0,0 +1,169 @@
+use crate::builder::Builder;
+use crate::index::{evaluate, plan, Index, MatchResult};
+use crate::query::Query;
+use crate::similarity::bm25_idf;
+use crate::vector::Vector;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// Normalization makes the scores of different indexes comparable
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Normalization {
+    // None keeps the scores of each index as they are
+    None,
+    // GlobalIdf reweighs every query term with its idf over all indexes
+    // instead of the idf within its own index.  The idf is the one of BM25,
+    // so this suits indexes weighed by the BM25 family of similarities.
+    GlobalIdf,
+    // MaxScore divides the scores from each index by the best score of the
+    // query in that index
+    MaxScore,
+}
+
+// FederatedResult is a result of a federated query, with the name of the
+// index it came from
+#[derive(Clone)]
+pub struct FederatedResult {
+    index_name: String,
+    result: MatchResult,
+}
+
+impl FederatedResult {
+    pub fn index_name(&self) -> &str {
+        &self.index_name
+    }
+
+    pub fn result(&self) -> &MatchResult {
+        &self.result
+    }
+
+    pub fn doc_ref(&self) -> &str {
+        self.result.doc_ref()
+    }
+
+    pub fn score(&self) -> f64 {
+        self.result.score()
+    }
+}
+
+// FederatedSearcher runs a query against several named indexes and merges
+// their results into one list.  Doc refs only need to be unique within an
+// index; the same doc ref from two indexes gives two results.
+pub struct FederatedSearcher<'a> {
+    indexes: Vec<(String, &'a Index)>,
+    normalization: Normalization,
+    idf_scales: OnceLock<Vec<HashMap<usize, f64>>>, // for each index, term index -> global idf / idf
+}
+
+impl<'a> FederatedSearcher<'a> {
+    pub fn new(normalization: Normalization) -> FederatedSearcher<'a> {
+        FederatedSearcher {
+            indexes: Vec::new(),
+            normalization,
+            idf_scales: OnceLock::new(),
+        }
+    }
+
+    // add_index adds the index under the name, replacing an index added
+    // under the same name
+    pub fn add_index(&mut self, name: &str, index: &'a Index) {
+        self.indexes.retain(|(n, _)| n != name);
+        self.indexes.push((name.to_string(), index));
+        self.idf_scales = OnceLock::new();
+    }
+
+    pub fn index_names(&self) -> Vec<&str> {
+        self.indexes.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    pub fn query(&self, query: &Query) -> Vec<FederatedResult> {
+        let mut results: Vec<FederatedResult> = Vec::new();
+        for (i, (name, index)) in self.indexes.iter().enumerate() {
+            let mut index_results = match self.normalization {
+                Normalization::GlobalIdf => {
+                    let idf_scales = self.idf_scales.get_or_init(|| self.idf_scales());
+                    self.query_global_idf(index, &idf_scales[i], query)
+                }
+                _ => index.query(query),
+            };
+            if self.normalization == Normalization::MaxScore {
+                let max_score = index_results.iter().map(|r| r.score()).fold(0.0, f64::max);
+                if max_score > 0.0 {
+                    for result in index_results.iter_mut() {
+                        result.set_score(result.score() / max_score);
+                    }
+                }
+            }
+            results.extend(index_results.into_iter().map(|result| FederatedResult {
+                index_name: name.to_string(),
+                result,
+            }));
+        }
+        results.sort_by(|a, b| a.score().partial_cmp(&b.score()).unwrap_or(Ordering::Less));
+        results
+    }
+
+    // query_global_idf scores the query in the index as if the field vectors
+    // were weighed with the global idf.  The scale of each term applies to
+    // its weight in the query vector instead, which gives the same dot
+    // product, while the score is still normalized by the unscaled query.
+    fn query_global_idf(
+        &self,
+        index: &Index,
+        scales: &HashMap<usize, f64>,
+        query: &Query,
+    ) -> Vec<MatchResult> {
+        let plan = plan(index, query);
+        let query_vectors: HashMap<&str, (Vector, f64)> = plan
+            .query_vectors
+            .iter()
+            .map(|(field_name, query_vector)| {
+                let mut scaled = query_vector.clone();
+                for (index, value) in query_vector.iter() {
+                    scaled.upsert(index, value * scales.get(&index).unwrap_or(&1.0));
+                }
+                (*field_name, (scaled, query_vector.magnitude()))
+            })
+            .collect();
+        evaluate(index, query, &plan, |field_name, field_vector| {
+            let (scaled, magnitude) = &query_vectors[field_name];
+            if *magnitude == 0.0 {
+                0.0
+            } else {
+                scaled.dot(field_vector) / magnitude
+            }
+        })
+    }
+
+    // idf_scales returns, for each index, the ratio of the global idf of each
+    // of its terms to its idf within the index.  The number of documents
+    // with each term is summed over the indexes once.
+    fn idf_scales(&self) -> Vec<HashMap<usize, f64>> {
+        let global_doc_count: usize = self.indexes.iter().map(|(_, i)| i.doc_refs().len()).sum();
+        let mut global_documents_with_term: HashMap<&str, usize> = HashMap::new();
+        for (_, index) in self.indexes.iter() {
+            for (term, ri) in index.inverted_index() {
+                *global_documents_with_term.entry(term).or_default() +=
+                    Builder::documents_with_term(ri);
+            }
+        }
+
+        self.indexes
+            .iter()
+            .map(|(_, index)| {
+                let doc_count = index.doc_refs().len();
+                let mut scales: HashMap<usize, f64> = HashMap::new();
+                for (term, ri) in index.inverted_index() {
+                    let idf = bm25_idf(Builder::documents_with_term(ri), doc_count);
+                    let global_idf =
+                        bm25_idf(global_documents_with_term[term.as_str()], global_doc_count);
+                    let scale = if idf > 0.0 { global_idf / idf } else { 1.0 };
+                    scales.insert(ri.index as usize, scale);
+                }
+                scales
+            })
+            .collect()
+    }
+}
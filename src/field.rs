@@ -1,26 +1,77 @@
-#[derive(Clone, Ord, PartialEq, Eq, PartialOrd)]
+use std::collections::BTreeMap;
+use std::fmt;
+
+// FieldId is the position of a field name in the sorted field names of an
+// index
+pub type FieldId = u32;
+
+#[derive(Clone, Debug, Ord, PartialEq, Eq, PartialOrd)]
 pub enum FieldValue {
     U64(u64),
     I64(i64),
     Text(String),
+    Array(Vec<FieldValue>),
+    Object(BTreeMap<String, FieldValue>),
 }
 
-impl ToString for FieldValue {
-    fn to_string(&self) -> String {
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            FieldValue::U64(v) => v.to_string(),
-            FieldValue::I64(v) => v.to_string(),
-            FieldValue::Text(v) => v.to_string(),
+            FieldValue::U64(v) => write!(f, "{}", v),
+            FieldValue::I64(v) => write!(f, "{}", v),
+            FieldValue::Text(v) => write!(f, "{}", v),
+            FieldValue::Array(values) => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                write!(f, "{}", values.join(" "))
+            }
+            FieldValue::Object(values) => {
+                let values: Vec<String> = values.values().map(|v| v.to_string()).collect();
+                write!(f, "{}", values.join(" "))
+            }
         }
     }
 }
 
+impl FieldValue {
+    // resolve returns the values at the path below this value.  A segment
+    // names a key of an object or an index of an array, and "*" matches every
+    // element.  A key applied to an array is applied to each of its elements.
+    pub fn resolve(&self, path: &[&str]) -> Vec<&FieldValue> {
+        let (segment, rest) = match path.split_first() {
+            Some(split) => split,
+            None => return vec![self],
+        };
+        match self {
+            FieldValue::Object(values) if *segment == "*" => {
+                values.values().flat_map(|v| v.resolve(rest)).collect()
+            }
+            FieldValue::Object(values) => values
+                .get(*segment)
+                .map(|v| v.resolve(rest))
+                .unwrap_or_default(),
+            FieldValue::Array(values) if *segment == "*" => {
+                values.iter().flat_map(|v| v.resolve(rest)).collect()
+            }
+            FieldValue::Array(values) => match segment.parse::<usize>() {
+                Ok(i) => values.get(i).map(|v| v.resolve(rest)).unwrap_or_default(),
+                Err(_) => values.iter().flat_map(|v| v.resolve(path)).collect(),
+            },
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Field {
     name: String,
     value: FieldValue,
 }
 
 impl Field {
+    pub fn new(name: String, value: FieldValue) -> Field {
+        Field { name, value }
+    }
+
     pub fn new_text(name: String, value: String) -> Field {
         Field {
             name,
@@ -51,12 +102,17 @@ impl Field {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct FieldParseError;
 
-const FIELD_REF_JOINER: &str = "/";
+// FIELD_REF_JOINER separates the field name and the doc ref.  It is escaped
+// with FIELD_REF_ESCAPE in field names, so nested field paths can be parsed
+// back, and a field ref is split on the first unescaped joiner, so doc refs
+// may contain it.
+const FIELD_REF_JOINER: char = '/';
+const FIELD_REF_ESCAPE: char = '\\';
 
-#[derive(Eq, PartialEq, Hash, Clone)]
+#[derive(Eq, PartialEq, Hash, Clone, Debug)]
 pub struct FieldRef {
     doc_ref: String,
     field_name: String,
@@ -79,13 +135,60 @@ impl FieldRef {
     }
 
     pub fn from_string(s: &str) -> Result<FieldRef, FieldParseError> {
-        let splitten: Vec<&str> = s.splitn(2, FIELD_REF_JOINER).collect();
-        if splitten.len() < 2 {
-            return Err(FieldParseError);
+        let mut field_name = String::new();
+        let mut chars = s.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                FIELD_REF_ESCAPE => match chars.next() {
+                    Some((_, escaped)) => field_name.push(escaped),
+                    None => return Err(FieldParseError),
+                },
+                FIELD_REF_JOINER => {
+                    return Ok(FieldRef {
+                        field_name,
+                        doc_ref: s[i + c.len_utf8()..].into(),
+                    })
+                }
+                _ => field_name.push(c),
+            }
+        }
+        Err(FieldParseError)
+    }
+
+    // to_lunr_string returns the unescaped "field/docRef" form used by lunr.js
+    pub fn to_lunr_string(&self) -> String {
+        format!("{}{}{}", self.field_name, FIELD_REF_JOINER, self.doc_ref)
+    }
+
+    // from_lunr_string parses the unescaped form.  Both the field name and the
+    // doc ref may contain the joiner, so the field name is the longest of the
+    // given field names that is followed by it.
+    pub fn from_lunr_string<'a, I>(s: &str, field_names: I) -> Result<FieldRef, FieldParseError>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        field_names
+            .into_iter()
+            .filter(|field_name| {
+                s.starts_with(field_name) && s[field_name.len()..].starts_with(FIELD_REF_JOINER)
+            })
+            .max_by_key(|field_name| field_name.len())
+            .map(|field_name| FieldRef {
+                field_name: field_name.into(),
+                doc_ref: s[field_name.len() + FIELD_REF_JOINER.len_utf8()..].into(),
+            })
+            .ok_or(FieldParseError)
+    }
+}
+
+impl fmt::Display for FieldRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in self.field_name.chars() {
+            if c == FIELD_REF_JOINER || c == FIELD_REF_ESCAPE {
+                write!(f, "{}", FIELD_REF_ESCAPE)?;
+            }
+            write!(f, "{}", c)?;
         }
-        Ok(FieldRef {
-            field_name: splitten[0].to_string(),
-            doc_ref: splitten[1].into(),
-        })
+        write!(f, "{}{}", FIELD_REF_JOINER, self.doc_ref)
     }
 }
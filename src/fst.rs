@@ -0,0 +1,296 @@
+use crate::binary::{invalid_data, read_varint, try_read_varint, write_varint};
+use crate::regex::Regex;
+use crate::token::TokenSet;
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+// A token set is encoded as a magic followed by its nodes, the root first.
+// Each node is a varint of (edge count << 1 | last), then per edge, in
+// character order, the character and the byte offset of the target node as
+// varints.  Offsets are from the start of the encoding, so the bytes are
+// walked in place without decoding the nodes.
+const MAGIC: &[u8; 4] = b"SGTS";
+const ROOT: usize = MAGIC.len();
+
+fn varint_len(mut value: u64) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+impl TokenSet {
+    // to_bytes encodes the token set for Fst
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let nodes = self.to_nodes();
+
+        // the size of a node depends on the offsets of its targets, so the
+        // offsets are recomputed until they stop growing
+        let mut offsets: Vec<usize> = vec![0; nodes.len()];
+        loop {
+            let mut pos = ROOT;
+            let mut next: Vec<usize> = Vec::with_capacity(nodes.len());
+            for (_, edges) in nodes.iter() {
+                next.push(pos);
+                pos += varint_len((edges.len() as u64) << 1);
+                for (c, target) in edges {
+                    pos += varint_len(*c as u64) + varint_len(offsets[*target] as u64);
+                }
+            }
+            if next == offsets {
+                break;
+            }
+            offsets = next;
+        }
+
+        let mut buf = MAGIC.to_vec();
+        for (last, edges) in nodes.iter() {
+            write_varint(&mut buf, (edges.len() as u64) << 1 | *last as u64);
+            for (c, target) in edges {
+                write_varint(&mut buf, *c as u64);
+                write_varint(&mut buf, offsets[*target] as u64);
+            }
+        }
+        buf
+    }
+}
+
+// Fst reads a token set encoded by TokenSet::to_bytes in place.  The nodes
+// are checked once when opened, and lookups then walk the bytes directly.
+#[derive(Clone, Copy)]
+pub struct Fst<'a> {
+    data: &'a [u8],
+}
+
+// Edges iterates the (character, target offset) edges of a node
+struct Edges<'a> {
+    data: &'a [u8],
+    pos: usize,
+    remaining: usize,
+}
+
+impl Iterator for Edges<'_> {
+    type Item = (char, usize);
+
+    fn next(&mut self) -> Option<(char, usize)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let c = read_varint(self.data, &mut self.pos) as u32;
+        let target = read_varint(self.data, &mut self.pos) as usize;
+        Some((std::char::from_u32(c).expect("corrupted token set"), target))
+    }
+}
+
+impl<'a> Fst<'a> {
+    pub fn new(data: &'a [u8]) -> io::Result<Fst<'a>> {
+        if data.len() <= ROOT || &data[..ROOT] != MAGIC {
+            return Err(invalid_data("not an encoded token set"));
+        }
+        let fst = Fst { data };
+        fst.check()?;
+        Ok(fst)
+    }
+
+    // new_unchecked reads a token set already checked by Fst::new
+    pub(crate) fn new_unchecked(data: &'a [u8]) -> Fst<'a> {
+        Fst { data }
+    }
+
+    // check reads every node reachable from the root with checked reads, so
+    // that lookups cannot read out of bounds.  Edges of a node must be in
+    // character order, and a term must not lead back to one of its prefixes,
+    // as a vocabulary never does, so that walks over all the terms end.
+    fn check(&self) -> io::Result<()> {
+        // a node is absent while unvisited, false while its descendants are
+        // walked and true once they all are
+        let mut done: HashMap<usize, bool> = HashMap::new();
+        let mut stack: Vec<(usize, Vec<usize>)> = vec![(ROOT, self.check_node(ROOT)?)];
+        done.insert(ROOT, false);
+        while let Some((node, targets)) = stack.last_mut() {
+            let node = *node;
+            let target = match targets.pop() {
+                Some(target) => target,
+                None => {
+                    done.insert(node, true);
+                    stack.pop();
+                    continue;
+                }
+            };
+            match done.get(&target) {
+                Some(true) => {}
+                Some(false) => return Err(invalid_data("cyclic token set")),
+                None => {
+                    done.insert(target, false);
+                    stack.push((target, self.check_node(target)?));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // check_node reads the node at the offset and returns its targets
+    fn check_node(&self, offset: usize) -> io::Result<Vec<usize>> {
+        let invalid = || invalid_data("corrupted token set");
+        if offset < ROOT {
+            return Err(invalid());
+        }
+        let mut pos = offset;
+        let count = try_read_varint(self.data, &mut pos)? >> 1;
+        // an edge takes at least two bytes
+        if count > (self.data.len() - pos.min(self.data.len())) as u64 / 2 {
+            return Err(invalid());
+        }
+        let mut targets = Vec::with_capacity(count as usize);
+        let mut prev: Option<char> = None;
+        for _ in 0..count {
+            let c = try_read_varint(self.data, &mut pos)?;
+            let c = Some(c)
+                .filter(|c| *c <= u32::MAX as u64)
+                .and_then(|c| std::char::from_u32(c as u32))
+                .ok_or_else(invalid)?;
+            if prev.is_some_and(|prev| prev >= c) {
+                return Err(invalid());
+            }
+            prev = Some(c);
+            let target = try_read_varint(self.data, &mut pos)?;
+            if target >= self.data.len() as u64 {
+                return Err(invalid());
+            }
+            targets.push(target as usize);
+        }
+        Ok(targets)
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    // node returns whether a term ends at the node, and its edges
+    fn node(&self, offset: usize) -> (bool, Edges<'a>) {
+        let mut pos = offset;
+        let header = read_varint(self.data, &mut pos);
+        let edges = Edges {
+            data: self.data,
+            pos,
+            remaining: (header >> 1) as usize,
+        };
+        (header & 1 == 1, edges)
+    }
+
+    fn child(&self, offset: usize, c: char) -> Option<usize> {
+        let (_, mut edges) = self.node(offset);
+        edges
+            .find(|(key, _)| *key >= c)
+            .filter(|(key, _)| *key == c)
+            .map(|(_, target)| target)
+    }
+
+    // walk follows the characters of s from the root
+    fn walk(&self, s: &str) -> Option<usize> {
+        let mut node = ROOT;
+        for c in s.chars() {
+            node = self.child(node, c)?;
+        }
+        Some(node)
+    }
+
+    pub fn contains(&self, term: &str) -> bool {
+        self.walk(term)
+            .map(|node| self.node(node).0)
+            .unwrap_or(false)
+    }
+
+    // prefix returns the terms starting with the prefix, sorted
+    pub fn prefix(&self, prefix: &str) -> Vec<String> {
+        let mut words: Vec<String> = Vec::new();
+        let node = match self.walk(prefix) {
+            Some(node) => node,
+            None => return words,
+        };
+        let mut stack: Vec<(String, usize)> = vec![(prefix.to_string(), node)];
+        while let Some((prefix, node)) = stack.pop() {
+            let (last, edges) = self.node(node);
+            if last {
+                words.push(prefix.to_string());
+            }
+            let edges: Vec<(char, usize)> = edges.collect();
+            for (c, target) in edges.into_iter().rev() {
+                let mut prefix = prefix.to_string();
+                prefix.push(c);
+                stack.push((prefix, target));
+            }
+        }
+        words
+    }
+
+    pub fn to_vec(&self) -> Vec<String> {
+        self.prefix("")
+    }
+
+    // intersect returns the terms accepted by the given token set, such as a
+    // wildcard or fuzzy one, walking both automata in lockstep as
+    // TokenSet::intersect does.  Every step consumes an edge of this acyclic
+    // automaton, so the walk ends.  The terms are sorted.
+    pub fn intersect(&self, token_set: &TokenSet) -> Vec<String> {
+        let q_nodes = token_set.to_nodes();
+        let mut words: HashSet<String> = HashSet::new();
+        let mut stack: Vec<(usize, usize, String)> = vec![(0, ROOT, String::new())];
+
+        while let Some((q_node, node, prefix)) = stack.pop() {
+            for (q_key, q_next) in q_nodes[q_node].1.iter() {
+                let matches: Vec<(char, usize)> = if *q_key == '*' {
+                    self.node(node).1.collect()
+                } else {
+                    self.child(node, *q_key)
+                        .map(|next| vec![(*q_key, next)])
+                        .unwrap_or_default()
+                };
+                for (n_key, n_next) in matches {
+                    let mut next_prefix = prefix.to_string();
+                    next_prefix.push(n_key);
+                    if self.node(n_next).0 && q_nodes[*q_next].0 {
+                        words.insert(next_prefix.to_string());
+                    }
+                    stack.push((*q_next, n_next, next_prefix));
+                }
+            }
+        }
+        let mut words: Vec<String> = words.into_iter().collect();
+        words.sort();
+        words
+    }
+
+    // intersect_regex returns the first terms in lexicographic order that
+    // the regex matches, at most limit of them, as TokenSet::intersect_regex
+    // does
+    pub fn intersect_regex(&self, regex: &Regex, limit: usize) -> Vec<String> {
+        let mut words: Vec<String> = Vec::new();
+        let mut stack: Vec<(String, usize, Vec<usize>)> =
+            vec![(String::new(), ROOT, regex.start())];
+        while let Some((prefix, node, states)) = stack.pop() {
+            if words.len() >= limit {
+                break;
+            }
+            let (last, edges) = self.node(node);
+            if last && regex.accepts(&states) {
+                words.push(prefix.to_string());
+            }
+            let edges: Vec<(char, usize)> = edges.collect();
+            for (c, target) in edges.into_iter().rev() {
+                let next = regex.step(&states, c);
+                if next.is_empty() {
+                    continue;
+                }
+                let mut prefix = prefix.to_string();
+                prefix.push(c);
+                stack.push((prefix, target, next));
+            }
+        }
+        words
+    }
+}
@@ -1,210 +1,626 @@
-use crate::field::FieldRef;
-use crate::query::{Presence, Query};
+use crate::builder::Builder;
+use crate::document::{DocId, DocRefs, Document};
+use crate::field::{Field, FieldId, FieldRef, FieldValue};
+use crate::postings::{PostingList, PostingsIter};
+use crate::query::{Clause, Presence, Query};
+use crate::regex::Regex;
+use crate::store::DocumentStore;
 use crate::token::TokenSet;
 use crate::vector::Vector;
 
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 
+// Metadata maps a whitelisted token metadata key to its values, one for each
+// occurrence of the term
+pub type Metadata = HashMap<String, Vec<FieldValue>>;
+
 #[derive(Eq, PartialEq, Clone)]
 pub struct InvertedIndex {
     pub index: u64,
-    pub documents: HashMap<String, HashSet<String>>, // field_name -> []document_ref
+    pub documents: HashMap<String, PostingList>, // field_name -> postings
+    pub metadata: HashMap<String, HashMap<DocId, Metadata>>, // field_name -> doc_id -> metadata
+}
+
+impl InvertedIndex {
+    pub fn new(index: u64) -> InvertedIndex {
+        InvertedIndex {
+            index,
+            documents: HashMap::new(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    // get_metadata returns the metadata recorded for the term in the field of
+    // the document
+    pub fn get_metadata(&self, field_name: &str, doc_id: DocId) -> Option<&Metadata> {
+        self.metadata.get(field_name)?.get(&doc_id)
+    }
+}
+
+// FieldVectors maps a field of a document to its vector, keyed by doc id and
+// field id
+pub type FieldVectors = HashMap<(DocId, FieldId), Vector>;
+
+// field_ids numbers the field names in sorted order, the ids field vectors
+// are keyed by
+pub fn field_ids(field_names: &HashSet<String>) -> HashMap<&str, FieldId> {
+    let mut sorted: Vec<&str> = field_names.iter().map(|f| f.as_str()).collect();
+    sorted.sort_unstable();
+    sorted
+        .into_iter()
+        .enumerate()
+        .map(|(field_id, field_name)| (field_name, field_id as FieldId))
+        .collect()
 }
 
 pub struct Index {
     inverted_index: HashMap<String, InvertedIndex>,
-    field_vectors: HashMap<FieldRef, Vector>,
+    field_vectors: FieldVectors,
     token_set: TokenSet,
     field_names: HashSet<String>,
+    pipeline: Vec<String>,
+    store: DocumentStore,
+    doc_refs: DocRefs,
+    document_boosts: HashMap<DocId, f64>,
 
-    complete_doc_refs: HashSet<String>,
+    sorted_field_names: Vec<String>, // field_id -> field_name
+    complete_doc_ids: HashSet<DocId>,
+    max_weights: HashMap<FieldId, HashMap<usize, f64>>, // field_id -> term index -> max weight
 }
 
 impl Index {
+    // new creates an index whose postings and field vectors refer to
+    // documents by their ids in doc_refs, and whose field vectors refer to
+    // fields by their ids from field_ids
     pub fn new(
         inverted_index: HashMap<String, InvertedIndex>,
-        field_vectors: HashMap<FieldRef, Vector>,
+        field_vectors: FieldVectors,
         token_set: TokenSet,
         field_names: HashSet<String>,
+        pipeline: Vec<String>,
+        doc_refs: DocRefs,
     ) -> Index {
-        let mut complete_doc_refs: HashSet<String> = HashSet::new();
+        let mut complete_doc_ids: HashSet<DocId> = HashSet::new();
         for ri in inverted_index.values() {
-            for doc_refs in ri.documents.values() {
-                for doc_ref in doc_refs {
-                    complete_doc_refs.insert(doc_ref.to_string());
-                }
+            for doc_ids in ri.documents.values() {
+                complete_doc_ids.extend(doc_ids.iter());
             }
         }
-        return Index {
+        let mut sorted_field_names: Vec<String> = field_names.iter().cloned().collect();
+        sorted_field_names.sort_unstable();
+        let mut index = Index {
             inverted_index,
             field_vectors,
             token_set,
             field_names,
-            complete_doc_refs,
+            pipeline,
+            store: DocumentStore::new(),
+            doc_refs,
+            document_boosts: HashMap::new(),
+            sorted_field_names,
+            complete_doc_ids,
+            max_weights: HashMap::new(),
         };
+        let field_vectors = std::mem::take(&mut index.field_vectors);
+        for ((_, field_id), vector) in &field_vectors {
+            for (term_index, weight) in vector.iter() {
+                index.raise_max_weight(*field_id, term_index, weight);
+            }
+        }
+        index.field_vectors = field_vectors;
+        index
     }
 
     pub fn inverted_index(&self) -> &HashMap<String, InvertedIndex> {
         &self.inverted_index
     }
 
-    pub fn field_vectors(&self) -> &HashMap<FieldRef, Vector> {
+    pub fn field_vectors(&self) -> &FieldVectors {
         &self.field_vectors
     }
 
+    pub fn field_vector(&self, field_ref: &FieldRef) -> Option<&Vector> {
+        let doc_id = self.doc_refs.id(field_ref.doc_ref())?;
+        let field_id = self.field_id(field_ref.field_name())?;
+        self.field_vectors.get(&(doc_id, field_id))
+    }
+
+    // field_id returns the id of the field in the keys of field vectors
+    pub fn field_id(&self, field_name: &str) -> Option<FieldId> {
+        self.sorted_field_names
+            .binary_search_by(|f| f.as_str().cmp(field_name))
+            .ok()
+            .map(|field_id| field_id as FieldId)
+    }
+
+    pub fn field_name(&self, field_id: FieldId) -> &str {
+        &self.sorted_field_names[field_id as usize]
+    }
+
     pub fn token_set(&self) -> &TokenSet {
         &self.token_set
     }
 
-    pub fn query(&self, query: &Query) -> Vec<MatchResult> {
-        let mut query_vectors: HashMap<String, Vector> = HashMap::new();
-        for field_ref in self.field_vectors.keys() {
-            query_vectors.insert(field_ref.field_name().to_string(), Vector::new());
-        }
+    pub fn doc_refs(&self) -> &DocRefs {
+        &self.doc_refs
+    }
+
+    // doc_ids returns the ids of the documents containing any term
+    pub fn doc_ids(&self) -> &HashSet<DocId> {
+        &self.complete_doc_ids
+    }
+
+    // max_weight returns the largest weight the term has in the field vector
+    // of any document, an upper bound on what it adds to a score
+    pub fn max_weight(&self, term: &str, field_name: &str) -> Option<f64> {
+        let ri = self.inverted_index.get(term)?;
+        self.max_weights(field_name, ri.index as usize)
+    }
+
+    pub(crate) fn max_weights(&self, field_name: &str, index: usize) -> Option<f64> {
+        let field_id = self.field_id(field_name)?;
+        self.max_weights.get(&field_id)?.get(&index).copied()
+    }
+
+    pub fn field_names(&self) -> &HashSet<String> {
+        &self.field_names
+    }
 
-        let mut required_matches: HashMap<String, HashSet<String>> = HashMap::new();
-        let mut prohibited_matches: HashMap<String, HashSet<String>> = HashMap::new();
-        let mut matching_fields: HashMap<FieldRef, MatchData> = HashMap::new();
+    pub fn pipeline(&self) -> &Vec<String> {
+        &self.pipeline
+    }
+
+    pub fn document_store(&self) -> &DocumentStore {
+        &self.store
+    }
+
+    pub(crate) fn set_document_store(&mut self, store: DocumentStore) {
+        self.store = store;
+    }
 
-        for clause in &query.clauses {
-            let query_fields: Vec<String> = clause
-                .fields
-                .clone()
-                .unwrap_or(self.field_names.iter().map(|f| f.to_string()).collect());;
+    // document_boost returns the boost the document was added with
+    pub fn document_boost(&self, doc_id: DocId) -> f64 {
+        *self.document_boosts.get(&doc_id).unwrap_or(&1.0)
+    }
 
-            let mut clause_matches = self.complete_doc_refs.clone();
-            let term_token_set = TokenSet::from_clause(&clause);
-            let expanded_terms = term_token_set.intersect(&term_token_set);
+    pub(crate) fn document_boosts(&self) -> &HashMap<DocId, f64> {
+        &self.document_boosts
+    }
 
-            if expanded_terms.to_vec().is_empty() && clause.presence == Presence::Required {
-                for field in query_fields.iter() {
-                    required_matches.insert(field.to_string(), HashSet::new());
+    pub(crate) fn set_document_boosts(&mut self, document_boosts: HashMap<DocId, f64>) {
+        self.document_boosts = document_boosts;
+    }
+
+    // refresh brings the index up to date with the builder it was built
+    // from, after the documents in doc_refs were added, replaced or removed
+    // there, touching the terms in terms.  Only these documents and terms
+    // are updated: the changed documents are weighed again, and the weights
+    // of the terms in the other documents containing them.  The other weights
+    // keep the document count and average field lengths they were weighed
+    // with, and max weights are only ever raised, so they stay upper bounds.
+    pub(crate) fn refresh(
+        &mut self,
+        builder: &Builder,
+        doc_refs: &HashSet<String>,
+        terms: &HashSet<String>,
+    ) {
+        let field_count = self.sorted_field_names.len() as FieldId;
+        for doc_ref in doc_refs {
+            if let Some(doc_id) = self.doc_refs.release(doc_ref) {
+                for field_id in 0..field_count {
+                    self.field_vectors.remove(&(doc_id, field_id));
                 }
-                break;
+                self.complete_doc_ids.remove(&doc_id);
+                self.document_boosts.remove(&doc_id);
+                self.store.remove(doc_ref);
             }
+        }
 
-            for expanded_term in expanded_terms.to_vec() {
-                let ri = self.inverted_index.get(&expanded_term).unwrap();
-                for field in query_fields.iter() {
-                    let matching_docs = ri.documents.get(field.into()).unwrap();
-                    if clause.presence == Presence::Required {
-                        for doc in matching_docs {
-                            clause_matches.insert(doc.to_string());
-                        }
-                        if !required_matches.contains_key(field) {
-                            required_matches
-                                .insert(field.to_string(), self.complete_doc_refs.clone());
-                        }
-                    }
+        let mut vocabulary_changed = false;
+        for term in terms {
+            vocabulary_changed |= match builder.inverted_index().get(term) {
+                Some(ri) => self
+                    .inverted_index
+                    .insert(term.to_string(), ri.clone())
+                    .is_none(),
+                None => self.inverted_index.remove(term).is_some(),
+            };
+        }
+        // the token set is built again from the vocabulary rather than
+        // patched, so it stays minimal however many commits change it
+        if vocabulary_changed {
+            let mut vocabulary: Vec<String> = self.inverted_index.keys().cloned().collect();
+            vocabulary.sort();
+            self.token_set = TokenSet::from_array(&vocabulary);
+        }
 
-                    if clause.presence == Presence::Prohibited {
-                        if !prohibited_matches.contains_key(field) {
-                            prohibited_matches.insert(field.to_string(), HashSet::new());
-                        }
-                        for doc in matching_docs {
-                            prohibited_matches
-                                .get_mut(field)
-                                .unwrap()
-                                .insert(doc.to_string());
-                        }
-                        continue;
+        for doc_ref in doc_refs {
+            let doc_id = match builder.doc_refs().id(doc_ref) {
+                Some(doc_id) => doc_id,
+                None => continue,
+            };
+            self.doc_refs.assign(doc_ref, doc_id);
+            for (field_name, vector) in builder.document_vectors(doc_id) {
+                if !vector.is_empty() {
+                    self.complete_doc_ids.insert(doc_id);
+                }
+                if let Some(field_id) = self.field_id(field_name) {
+                    for (index, weight) in vector.iter() {
+                        self.raise_max_weight(field_id, index, weight);
                     }
+                    self.field_vectors.insert((doc_id, field_id), vector);
+                }
+            }
+            let boost = builder.document_boost(doc_id);
+            if boost != 1.0 {
+                self.document_boosts.insert(doc_id, boost);
+            }
+            if let Some(compressed) = builder.document_store().compressed(doc_ref) {
+                self.store.insert_compressed(doc_ref, compressed.to_vec());
+            }
+        }
 
-                    let boost = query_vectors
-                        .get(field)
-                        .unwrap()
-                        .get(ri.index as usize)
-                        .unwrap_or(0.0);
-                    query_vectors
-                        .get_mut(field)
-                        .unwrap()
-                        .upsert(ri.index as usize, boost + clause.boost as f64);
-
-                    for doc_ref in matching_docs {
-                        let field_ref = FieldRef::new(doc_ref.to_string(), field.to_string());
-                        if let Some(matching) = matching_fields.get_mut(&field_ref) {
-                            matching.add(expanded_term.to_string(), field.to_string())
-                        } else {
-                            let mut match_data = MatchData::new();
-                            match_data.add(expanded_term.to_string(), field.to_string());
-                            matching_fields.insert(field_ref, match_data);
-                        }
+        for term in terms {
+            let ri = match builder.inverted_index().get(term) {
+                Some(ri) => ri,
+                None => continue,
+            };
+            let mut doc_ids: HashSet<DocId> = HashSet::new();
+            for postings in ri.documents.values() {
+                doc_ids.extend(postings.iter());
+            }
+            for doc_id in doc_ids {
+                if doc_refs.contains(builder.doc_refs().doc_ref(doc_id)) {
+                    continue;
+                }
+                for (field_name, weight) in builder.term_weights(doc_id, term) {
+                    let field_id = match self.field_id(field_name) {
+                        Some(field_id) => field_id,
+                        None => continue,
+                    };
+                    if let Some(vector) = self.field_vectors.get_mut(&(doc_id, field_id)) {
+                        vector.upsert(ri.index as usize, weight);
                     }
+                    self.raise_max_weight(field_id, ri.index as usize, weight);
                 }
             }
+        }
+    }
 
-            if clause.presence == Presence::Required {
-                for field in query_fields.iter() {
-                    let old = required_matches.remove(field).unwrap();
-                    required_matches.insert(
-                        field.to_string(),
-                        old.intersection(&clause_matches)
-                            .map(|f| f.to_string())
-                            .collect(),
-                    );
-                }
+    fn raise_max_weight(&mut self, field_id: FieldId, index: usize, weight: f64) {
+        let max_weight = self
+            .max_weights
+            .entry(field_id)
+            .or_default()
+            .entry(index)
+            .or_insert(weight);
+        *max_weight = max_weight.max(weight);
+    }
+
+    // doc returns the stored fields of the document
+    pub fn doc(&self, doc_ref: &str) -> Option<Document> {
+        self.store.get(doc_ref)
+    }
+
+    // query_with_fields queries the index, and attaches the named stored
+    // fields to each result
+    pub fn query_with_fields(&self, query: &Query, field_names: &[&str]) -> Vec<MatchResult> {
+        let mut results = self.query(query);
+        for result in results.iter_mut() {
+            if let Some(doc) = self.store.get(&result.doc_ref) {
+                result.fields = doc
+                    .get_all_fields()
+                    .into_iter()
+                    .filter(|field| field_names.contains(&field.name()))
+                    .cloned()
+                    .collect();
             }
         }
+        results
+    }
+
+    pub fn query(&self, query: &Query) -> Vec<MatchResult> {
+        let plan = plan(self, query);
+        evaluate(self, query, &plan, |field_name, field_vector| {
+            plan.query_vectors[field_name].score(field_vector)
+        })
+    }
+}
+
+// IndexReader is what planning and evaluating a query read from an index, so
+// that an Index and a MappedIndex answer queries the same way
+pub(crate) trait IndexReader {
+    // field_names returns the names of the fields, sorted
+    fn field_names(&self) -> Vec<&str>;
+
+    // terms returns the terms in the index accepted by the token set, sorted
+    fn terms(&self, token_set: &TokenSet) -> Vec<String>;
+
+    fn regex_terms(&self, regex: &Regex, limit: usize) -> Vec<String>;
+
+    // term_index returns the position of the term in field vectors
+    fn term_index(&self, term: &str) -> Option<u64>;
+
+    fn documents_with_term(&self, term: &str) -> usize;
+
+    fn postings(&self, term: &str, field_name: &str) -> Option<PostingsIter<'_>>;
+
+    // metadata returns the token metadata of the term in the field by doc id
+    fn metadata(&self, term: &str, field_name: &str) -> Cow<'_, HashMap<DocId, Metadata>>;
+
+    fn field_vector(&self, doc_id: DocId, field_name: &str) -> Option<Cow<'_, Vector>>;
+
+    // field_vector_keys returns the (doc id, field name) of every field vector
+    fn field_vector_keys(&self) -> Vec<(DocId, &str)>;
+
+    // complete_doc_ids returns the ids of the documents containing any term
+    fn complete_doc_ids(&self) -> &HashSet<DocId>;
+
+    fn doc_ref(&self, doc_id: DocId) -> &str;
+}
+
+impl IndexReader for Index {
+    fn field_names(&self) -> Vec<&str> {
+        self.sorted_field_names.iter().map(|f| f.as_str()).collect()
+    }
+
+    fn terms(&self, token_set: &TokenSet) -> Vec<String> {
+        self.token_set.intersect(token_set).to_vec()
+    }
+
+    fn regex_terms(&self, regex: &Regex, limit: usize) -> Vec<String> {
+        self.token_set.intersect_regex(regex, limit)
+    }
+
+    fn term_index(&self, term: &str) -> Option<u64> {
+        self.inverted_index.get(term).map(|ri| ri.index)
+    }
+
+    fn documents_with_term(&self, term: &str) -> usize {
+        self.inverted_index
+            .get(term)
+            .map(Builder::documents_with_term)
+            .unwrap_or(0)
+    }
+
+    fn postings(&self, term: &str, field_name: &str) -> Option<PostingsIter<'_>> {
+        let ri = self.inverted_index.get(term)?;
+        ri.documents.get(field_name).map(|docs| docs.iter())
+    }
+
+    fn metadata(&self, term: &str, field_name: &str) -> Cow<'_, HashMap<DocId, Metadata>> {
+        self.inverted_index
+            .get(term)
+            .and_then(|ri| ri.metadata.get(field_name))
+            .map(Cow::Borrowed)
+            .unwrap_or_default()
+    }
+
+    fn field_vector(&self, doc_id: DocId, field_name: &str) -> Option<Cow<'_, Vector>> {
+        let field_id = self.field_id(field_name)?;
+        self.field_vectors
+            .get(&(doc_id, field_id))
+            .map(Cow::Borrowed)
+    }
+
+    fn field_vector_keys(&self) -> Vec<(DocId, &str)> {
+        self.field_vectors
+            .keys()
+            .map(|(doc_id, field_id)| (*doc_id, self.field_name(*field_id)))
+            .collect()
+    }
+
+    fn complete_doc_ids(&self) -> &HashSet<DocId> {
+        &self.complete_doc_ids
+    }
+
+    fn doc_ref(&self, doc_id: DocId) -> &str {
+        self.doc_refs.doc_ref(doc_id)
+    }
+}
+
+// expand returns the terms of the index the clause matches.  Fuzzy terms are
+// ranked by the number of documents containing them.
+fn expand<R: IndexReader>(reader: &R, clause: &Clause) -> Vec<String> {
+    match (&clause.regex, &clause.fuzzy) {
+        (Some(regex), _) => reader.regex_terms(regex, clause.max_expansions),
+        (None, Some(fuzzy)) => {
+            let terms = reader.terms(&TokenSet::from_clause(clause));
+            let frequencies = terms.into_iter().map(|term| {
+                let frequency = reader.documents_with_term(&term);
+                (term, frequency)
+            });
+            fuzzy.rank(&clause.term, frequencies, clause.max_expansions)
+        }
+        (None, None) => reader.terms(&TokenSet::from_clause(clause)),
+    }
+}
+
+// plan expands the clauses of the query into the query vector of each field,
+// the (term, field) pairs to score, and the documents the required and
+// prohibited clauses let through
+pub(crate) fn plan<'a, R: IndexReader>(reader: &'a R, query: &Query) -> QueryPlan<'a> {
+    let field_names = reader.field_names();
+    let mut query_vectors: HashMap<&str, Vector> = field_names
+        .iter()
+        .map(|field_name| (*field_name, Vector::new()))
+        .collect();
+
+    let mut terms = Vec::new();
+    let mut required_matches: HashMap<&str, HashSet<DocId>> = HashMap::new();
+    let mut prohibited = HashSet::new();
+
+    for clause in &query.clauses {
+        let query_fields: Vec<&str> = match &clause.fields {
+            Some(fields) => field_names
+                .iter()
+                .filter(|f| fields.iter().any(|field| field == *f))
+                .cloned()
+                .collect(),
+            None => field_names.clone(),
+        };
 
-        let mut all_required_matches = self.complete_doc_refs.clone();
-        let mut all_prohibited_matches: HashSet<String> = HashSet::new();
-        for field in &self.field_names {
-            if required_matches.contains_key(field) {
-                let set = required_matches.get(field).unwrap();
-                all_required_matches.retain(|doc_ref| set.contains(doc_ref));
+        let expanded_terms: Vec<(String, u64)> = expand(reader, clause)
+            .into_iter()
+            .filter_map(|term| {
+                let index = reader.term_index(&term)?;
+                Some((term, index))
+            })
+            .collect();
+
+        if expanded_terms.is_empty() && clause.presence == Presence::Required {
+            for field in query_fields.iter() {
+                required_matches.insert(field, HashSet::new());
             }
-            if prohibited_matches.contains_key(field) {
-                for doc_ref in prohibited_matches.get(field).unwrap() {
-                    all_prohibited_matches.insert(doc_ref.to_string());
+            break;
+        }
+
+        let mut clause_matches: HashSet<DocId> = HashSet::new();
+        for (expanded_term, index) in expanded_terms {
+            for field in query_fields.iter() {
+                let matching_docs = reader.postings(&expanded_term, field).into_iter().flatten();
+                match clause.presence {
+                    Presence::Required => {
+                        clause_matches.extend(matching_docs);
+                        required_matches
+                            .entry(field)
+                            .or_insert_with(|| reader.complete_doc_ids().clone());
+                    }
+                    Presence::Prohibited => {
+                        prohibited.extend(matching_docs);
+                        continue;
+                    }
+                    Presence::Optional => {}
                 }
+
+                let query_vector = query_vectors.get_mut(field).unwrap();
+                let boost = query_vector.get(index as usize).unwrap_or(0.0);
+                query_vector.upsert(index as usize, boost + clause.boost as f64);
+                terms.push((expanded_term.to_string(), index, *field));
             }
         }
 
-        let matching_field_refs: HashSet<&FieldRef> = if query.is_negated() {
-            for field_ref in self.field_vectors.keys() {
-                matching_fields.insert(field_ref.clone(), MatchData::new());
+        if clause.presence == Presence::Required {
+            for field in query_fields.iter() {
+                if let Some(matches) = required_matches.get_mut(field) {
+                    matches.retain(|doc_id| clause_matches.contains(doc_id));
+                }
             }
-            self.field_vectors.keys().collect()
-        } else {
-            matching_fields.keys().collect()
+        }
+    }
+
+    let mut required = reader.complete_doc_ids().clone();
+    for matches in required_matches.values() {
+        required.retain(|doc_id| matches.contains(doc_id));
+    }
+
+    QueryPlan {
+        query_vectors,
+        terms,
+        required,
+        prohibited,
+    }
+}
+
+// evaluate scores the documents matching the plan term at a time, adding up
+// the score of each of their matching fields
+pub(crate) fn evaluate<R, F>(
+    reader: &R,
+    query: &Query,
+    plan: &QueryPlan,
+    score: F,
+) -> Vec<MatchResult>
+where
+    R: IndexReader,
+    F: Fn(&str, &Vector) -> f64,
+{
+    let mut matching_fields: HashMap<(DocId, &str), MatchData> = HashMap::new();
+    let no_metadata = Metadata::new();
+    for (term, _, field_name) in &plan.terms {
+        let matching_docs = match reader.postings(term, field_name) {
+            Some(matching_docs) => matching_docs,
+            None => continue,
         };
+        let metadata = reader.metadata(term, field_name);
+        for doc_id in matching_docs {
+            let metadata = metadata.get(&doc_id).unwrap_or(&no_metadata);
+            matching_fields
+                .entry((doc_id, field_name))
+                .or_default()
+                .add(term.to_string(), field_name.to_string(), metadata);
+        }
+    }
 
-        let mut doc_matches: HashMap<String, MatchResult> = HashMap::new();
-        for field_ref in matching_field_refs {
-            let doc_ref = field_ref.doc_ref();
-            if !all_required_matches.contains(doc_ref) {
-                continue;
-            }
-            if all_prohibited_matches.contains(doc_ref) {
-                continue;
-            }
+    if query.is_negated() {
+        for key in reader.field_vector_keys() {
+            matching_fields.insert(key, MatchData::new());
+        }
+    }
+
+    let mut doc_matches: HashMap<DocId, MatchResult> = HashMap::new();
+    for ((doc_id, field_name), match_data) in matching_fields {
+        if !plan.accepts(doc_id) {
+            continue;
+        }
 
-            let field_vector = self.field_vectors.get(&field_ref).unwrap();
-            let score = query_vectors
-                .get(field_ref.field_name())
-                .unwrap()
-                .similarity(field_vector);
-            if let Some(mut m) = doc_matches.remove(doc_ref) {
+        let field_vector = match reader.field_vector(doc_id, field_name) {
+            Some(field_vector) => field_vector,
+            None => continue,
+        };
+        let score = score(field_name, &field_vector);
+        match doc_matches.get_mut(&doc_id) {
+            Some(m) => {
                 m.score += score;
-                doc_matches.insert(doc_ref.to_string(), m);
-            } else {
-                let m = MatchResult {
-                    doc_ref: doc_ref.to_string(),
-                    score,
-                };
-                doc_matches.insert(doc_ref.to_string(), m);
+                m.match_data.combine(&match_data);
+            }
+            None => {
+                let doc_ref = reader.doc_ref(doc_id).to_string();
+                let mut m = MatchResult::new(doc_ref, score);
+                m.set_match_data(match_data);
+                doc_matches.insert(doc_id, m);
             }
         }
+    }
+
+    let mut results: Vec<MatchResult> = doc_matches.into_values().collect();
+    sort_results(&mut results);
+    results
+}
+
+// QueryPlan is a query resolved against an index, independent of the order
+// documents are scored in
+pub(crate) struct QueryPlan<'a> {
+    pub query_vectors: HashMap<&'a str, Vector>,
+    pub terms: Vec<(String, u64, &'a str)>, // term, term index, field_name
+    pub required: HashSet<DocId>,
+    pub prohibited: HashSet<DocId>,
+}
 
-        let mut results: Vec<MatchResult> = doc_matches.values().map(|m| m.clone()).collect();
-        results.sort_by(|a, b| a.score.partial_cmp(&b.score).unwrap_or(Ordering::Less));
-        return results;
+impl QueryPlan<'_> {
+    // accepts reports whether the document passes the required and
+    // prohibited clauses
+    pub fn accepts(&self, doc_id: DocId) -> bool {
+        self.required.contains(&doc_id) && !self.prohibited.contains(&doc_id)
     }
 }
 
+pub(crate) fn sort_results(results: &mut [MatchResult]) {
+    results.sort_by(|a, b| a.score.partial_cmp(&b.score).unwrap_or(Ordering::Less));
+}
+
+// MatchData holds the terms matched in a document, with the fields they were
+// found in and the metadata recorded for them
+#[derive(Clone)]
 pub struct MatchData {
-    metadata: HashMap<String, HashSet<String>>,
+    metadata: HashMap<String, HashMap<String, Metadata>>, // term -> field_name -> metadata
+}
+
+impl Default for MatchData {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MatchData {
@@ -216,30 +632,69 @@ impl MatchData {
 
     pub fn combine(&mut self, other: &MatchData) {
         for (term, fields) in &other.metadata {
-            for field in fields {
-                self.add(term.clone(), field.clone());
+            for (field, metadata) in fields {
+                self.add(term.clone(), field.clone(), metadata);
             }
         }
     }
 
-    pub fn add(&mut self, term: String, field: String) {
-        if let Some(fields) = self.metadata.get_mut(&term) {
-            fields.insert(field);
-        } else {
-            let mut set = HashSet::new();
-            set.insert(field);
-            self.metadata.insert(term, set);
+    pub fn add(&mut self, term: String, field: String, metadata: &Metadata) {
+        let entry = self
+            .metadata
+            .entry(term)
+            .or_default()
+            .entry(field)
+            .or_default();
+        for (key, values) in metadata {
+            entry
+                .entry(key.to_string())
+                .or_default()
+                .extend(values.iter().cloned());
         }
     }
+
+    pub fn terms(&self) -> Vec<&str> {
+        self.metadata.keys().map(|t| t.as_str()).collect()
+    }
+
+    pub fn fields(&self, term: &str) -> Vec<&str> {
+        self.metadata
+            .get(term)
+            .map(|fields| fields.keys().map(|f| f.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, term: &str, field_name: &str) -> Option<&Metadata> {
+        self.metadata.get(term)?.get(field_name)
+    }
 }
 
 #[derive(Clone)]
 pub struct MatchResult {
     doc_ref: String,
     score: f64,
+    match_data: MatchData,
+    fields: Vec<Field>,
 }
 
 impl MatchResult {
+    pub(crate) fn new(doc_ref: String, score: f64) -> MatchResult {
+        MatchResult {
+            doc_ref,
+            score,
+            match_data: MatchData::new(),
+            fields: Vec::new(),
+        }
+    }
+
+    pub(crate) fn set_score(&mut self, score: f64) {
+        self.score = score;
+    }
+
+    pub(crate) fn set_match_data(&mut self, match_data: MatchData) {
+        self.match_data = match_data;
+    }
+
     pub fn doc_ref(&self) -> &str {
         &self.doc_ref
     }
@@ -247,4 +702,20 @@ impl MatchResult {
     pub fn score(&self) -> f64 {
         self.score
     }
+
+    pub fn match_data(&self) -> &MatchData {
+        &self.match_data
+    }
+
+    // fields returns the stored fields selected by Index::query_with_fields
+    pub fn fields(&self) -> &Vec<Field> {
+        &self.fields
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FieldValue> {
+        self.fields
+            .iter()
+            .find(|field| field.name() == name)
+            .map(|field| field.value())
+    }
 }
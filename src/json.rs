@@ -0,0 +1,291 @@
+use crate::document::DocRefs;
+use crate::field::{FieldRef, FieldValue};
+use crate::index::{field_ids, FieldVectors, Index, InvertedIndex, Metadata};
+use crate::pipeline::Pipeline;
+use crate::token::TokenSet;
+use crate::vector::Vector;
+
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+// VERSION is the lunr.js version whose serialized format is produced
+pub const VERSION: &str = "2.3.9";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+    Parse(String),
+    VersionMismatch { expected: String, actual: String },
+    UnknownPipelineFunction(String),
+    Malformed(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Parse(msg) => write!(f, "failed to parse index: {}", msg),
+            LoadError::VersionMismatch { expected, actual } => write!(
+                f,
+                "version mismatch when loading serialized index: current version {}, serialized version {}",
+                expected, actual
+            ),
+            LoadError::UnknownPipelineFunction(label) => {
+                write!(f, "cannot load unregistered pipeline function: {}", label)
+            }
+            LoadError::Malformed(msg) => write!(f, "malformed index: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+fn malformed(msg: &str) -> LoadError {
+    LoadError::Malformed(msg.to_string())
+}
+
+fn major_version(version: &str) -> &str {
+    version.split('.').next().unwrap_or("")
+}
+
+pub(crate) fn value_to_json(value: &FieldValue) -> Value {
+    match value {
+        FieldValue::U64(v) => json!(v),
+        FieldValue::I64(v) => json!(v),
+        FieldValue::Text(v) => json!(v),
+        FieldValue::Array(values) => Value::Array(values.iter().map(value_to_json).collect()),
+        FieldValue::Object(values) => Value::Object(
+            values
+                .iter()
+                .map(|(k, v)| (k.to_string(), value_to_json(v)))
+                .collect(),
+        ),
+    }
+}
+
+// value_from_json converts a JSON value to a field value.  Numbers which are
+// not integers, booleans and null are kept as their text.
+pub(crate) fn value_from_json(value: &Value) -> FieldValue {
+    match value {
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(v), _) => FieldValue::U64(v),
+            (None, Some(v)) => FieldValue::I64(v),
+            _ => FieldValue::Text(n.to_string()),
+        },
+        Value::String(s) => FieldValue::Text(s.to_string()),
+        Value::Array(values) => FieldValue::Array(values.iter().map(value_from_json).collect()),
+        Value::Object(values) => FieldValue::Object(
+            values
+                .iter()
+                .map(|(k, v)| (k.to_string(), value_from_json(v)))
+                .collect(),
+        ),
+        _ => FieldValue::Text(value.to_string()),
+    }
+}
+
+impl Index {
+    pub fn to_json(&self) -> Value {
+        let mut fields: Vec<&String> = self.field_names().iter().collect();
+        fields.sort();
+
+        let field_vectors: Vec<Value> = self
+            .field_vectors()
+            .iter()
+            .map(|((doc_id, field_id), vector)| {
+                let doc_ref = self.doc_refs().doc_ref(*doc_id);
+                let field_name = self.field_name(*field_id);
+                let field_ref = FieldRef::new(doc_ref.to_string(), field_name.to_string());
+                (field_ref.to_lunr_string(), vector)
+            })
+            .collect::<BTreeMap<String, _>>()
+            .into_iter()
+            .map(|(field_ref, vector)| {
+                let mut elements: Vec<Value> = Vec::new();
+                for pair in vector.to_flat_vec().chunks(2) {
+                    elements.push(json!(pair[0] as u64));
+                    elements.push(json!(pair[1]));
+                }
+                json!([field_ref, elements])
+            })
+            .collect();
+
+        let mut terms: Vec<&String> = self.inverted_index().keys().collect();
+        terms.sort();
+        let inverted_index: Vec<Value> = terms
+            .into_iter()
+            .map(|term| {
+                let ri = self.inverted_index().get(term).unwrap();
+                let mut posting = Map::new();
+                posting.insert("_index".into(), json!(ri.index));
+                for field in fields.iter() {
+                    let mut docs = Map::new();
+                    if let Some(doc_ids) = ri.documents.get(*field) {
+                        for doc_id in doc_ids {
+                            let doc_ref = self.doc_refs().doc_ref(doc_id);
+                            let mut metadata = Map::new();
+                            if let Some(m) = ri.get_metadata(field, doc_id) {
+                                for (key, values) in m {
+                                    let values: Vec<Value> =
+                                        values.iter().map(value_to_json).collect();
+                                    metadata.insert(key.to_string(), Value::Array(values));
+                                }
+                            }
+                            docs.insert(doc_ref.to_string(), Value::Object(metadata));
+                        }
+                    }
+                    posting.insert(field.to_string(), Value::Object(docs));
+                }
+                json!([term, posting])
+            })
+            .collect();
+
+        json!({
+            "version": VERSION,
+            "fields": fields,
+            "fieldVectors": field_vectors,
+            "invertedIndex": inverted_index,
+            "pipeline": self.pipeline(),
+        })
+    }
+
+    pub fn to_json_string(&self) -> String {
+        self.to_json().to_string()
+    }
+
+    // load restores an index serialized by lunr.js.  Every function label in the
+    // serialized pipeline must be registered in the given pipeline.
+    pub fn load(source: &str, pipeline: &Pipeline) -> Result<Index, LoadError> {
+        let json: Value =
+            serde_json::from_str(source).map_err(|e| LoadError::Parse(e.to_string()))?;
+        Index::from_json(&json, pipeline)
+    }
+
+    pub fn from_json(json: &Value, pipeline: &Pipeline) -> Result<Index, LoadError> {
+        let version = json["version"]
+            .as_str()
+            .ok_or_else(|| malformed("missing version"))?;
+        if major_version(version) != major_version(VERSION) {
+            return Err(LoadError::VersionMismatch {
+                expected: VERSION.to_string(),
+                actual: version.to_string(),
+            });
+        }
+
+        let field_names: HashSet<String> = json["fields"]
+            .as_array()
+            .ok_or_else(|| malformed("missing fields"))?
+            .iter()
+            .map(|f| f.as_str().map(|f| f.to_string()))
+            .collect::<Option<_>>()
+            .ok_or_else(|| malformed("field name must be a string"))?;
+
+        let field_ids = field_ids(&field_names);
+        let mut doc_refs = DocRefs::new();
+        let mut field_vectors: FieldVectors = HashMap::new();
+        for entry in json["fieldVectors"]
+            .as_array()
+            .ok_or_else(|| malformed("missing fieldVectors"))?
+        {
+            // doc refs may contain '/', so field refs are parsed against the
+            // fields of the index rather than split
+            let field_ref = entry[0]
+                .as_str()
+                .ok_or_else(|| malformed("field ref must be a string"))?;
+            let field_ref =
+                FieldRef::from_lunr_string(field_ref, field_names.iter().map(|f| f.as_str()))
+                    .map_err(|_| malformed("unknown field in field ref"))?;
+            let elements: Vec<f64> = entry[1]
+                .as_array()
+                .ok_or_else(|| malformed("field vector must be an array"))?
+                .iter()
+                .map(|v| v.as_f64())
+                .collect::<Option<_>>()
+                .ok_or_else(|| malformed("field vector must contain numbers"))?;
+            if !elements.len().is_multiple_of(2) {
+                return Err(malformed("field vector must have an even length"));
+            }
+            let field_id = field_ids[field_ref.field_name()];
+            let doc_id = doc_refs.intern(field_ref.doc_ref());
+            field_vectors.insert((doc_id, field_id), Vector::from_flat_vec(elements));
+        }
+
+        let mut inverted_index: HashMap<String, InvertedIndex> = HashMap::new();
+        for entry in json["invertedIndex"]
+            .as_array()
+            .ok_or_else(|| malformed("missing invertedIndex"))?
+        {
+            let term = entry[0]
+                .as_str()
+                .ok_or_else(|| malformed("term must be a string"))?;
+            let posting = entry[1]
+                .as_object()
+                .ok_or_else(|| malformed("posting must be an object"))?;
+            let index = posting
+                .get("_index")
+                .and_then(|v| v.as_u64())
+                .ok_or_else(|| malformed("posting must have _index"))?;
+
+            let mut ri = InvertedIndex::new(index);
+            for (field, docs) in posting.iter() {
+                if field == "_index" {
+                    continue;
+                }
+                let docs = docs
+                    .as_object()
+                    .ok_or_else(|| malformed("field posting must be an object"))?;
+                let doc_ids = docs.keys().map(|doc_ref| doc_refs.intern(doc_ref));
+                ri.documents.insert(field.to_string(), doc_ids.collect());
+                for (doc_ref, metadata) in docs.iter() {
+                    let metadata = metadata
+                        .as_object()
+                        .ok_or_else(|| malformed("metadata must be an object"))?;
+                    if metadata.is_empty() {
+                        continue;
+                    }
+                    let mut m = Metadata::new();
+                    for (key, values) in metadata.iter() {
+                        let values = values
+                            .as_array()
+                            .ok_or_else(|| malformed("metadata values must be an array"))?;
+                        m.insert(
+                            key.to_string(),
+                            values.iter().map(value_from_json).collect(),
+                        );
+                    }
+                    ri.metadata
+                        .entry(field.to_string())
+                        .or_default()
+                        .insert(doc_refs.intern(doc_ref), m);
+                }
+            }
+            inverted_index.insert(term.to_string(), ri);
+        }
+
+        let labels: Vec<String> = json["pipeline"]
+            .as_array()
+            .ok_or_else(|| malformed("missing pipeline"))?
+            .iter()
+            .map(|l| l.as_str().map(|l| l.to_string()))
+            .collect::<Option<_>>()
+            .ok_or_else(|| malformed("pipeline label must be a string"))?;
+        let registered = pipeline.labels();
+        for label in labels.iter() {
+            if !registered.contains(label) {
+                return Err(LoadError::UnknownPipelineFunction(label.to_string()));
+            }
+        }
+
+        let mut terms: Vec<String> = inverted_index.keys().cloned().collect();
+        terms.sort();
+        let token_set = TokenSet::from_array(&terms);
+
+        Ok(Index::new(
+            inverted_index,
+            field_vectors,
+            token_set,
+            field_names,
+            labels,
+            doc_refs,
+        ))
+    }
+}
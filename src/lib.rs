@@ -1,10 +1,22 @@
+pub mod binary;
 pub mod document;
+pub mod federated;
 pub mod field;
+pub mod fst;
 pub mod index;
+pub mod json;
+pub mod live;
+pub mod loader;
 
 pub mod builder;
 pub mod pipeline;
+pub mod postings;
 pub mod query;
+pub mod regex;
+pub mod segment;
+pub mod similarity;
+pub mod store;
 pub mod token;
 pub mod tokenizer;
+pub mod topk;
 pub mod vector;
@@ -0,0 +1,105 @@
+use crate::builder::Builder;
+use crate::document::Document;
+use crate::index::{Index, MatchResult};
+use crate::query::Query;
+
+use std::collections::HashSet;
+
+// LiveIndex is an index that accepts document changes after it is built.
+// Changes are applied to the builder's postings and statistics immediately,
+// and reach the index searched on the next commit, which updates only the
+// changed documents and the terms they contain.  Reads see the index as of
+// the last commit.
+//
+// A commit does not weigh the unchanged documents again unless they contain
+// a changed term, so their weights keep the document count and average field
+// lengths of an earlier commit, and drift from those of a full build as the
+// collection changes.  rebuild weighs every document again and restores the
+// scores a full build gives.
+pub struct LiveIndex {
+    builder: Builder,
+    index: Index,
+    changed_docs: HashSet<String>,
+    changed_terms: HashSet<String>,
+}
+
+impl LiveIndex {
+    pub fn new(mut builder: Builder) -> LiveIndex {
+        let index = builder.build();
+        LiveIndex {
+            builder,
+            index,
+            changed_docs: HashSet::new(),
+            changed_terms: HashSet::new(),
+        }
+    }
+
+    // add adds the document, replacing an existing document with the same doc_ref
+    pub fn add(&mut self, doc: Document) {
+        self.add_with_boost(doc, 1.0);
+    }
+
+    pub fn add_with_boost(&mut self, doc: Document, boost: f64) {
+        let doc_ref = doc.doc_ref().to_string();
+        self.track(&doc_ref);
+        self.builder.add_document_with_boost(doc, boost);
+        self.track(&doc_ref);
+    }
+
+    // update replaces the document with the same doc_ref, and returns false
+    // when there is no such document
+    pub fn update(&mut self, doc: Document) -> bool {
+        if !self.builder.contains_document(doc.doc_ref()) {
+            return false;
+        }
+        self.add(doc);
+        true
+    }
+
+    pub fn delete(&mut self, doc_ref: &str) -> bool {
+        if !self.builder.contains_document(doc_ref) {
+            return false;
+        }
+        self.track(doc_ref);
+        self.builder.remove_document(doc_ref)
+    }
+
+    // track records the document and its current terms as changed
+    fn track(&mut self, doc_ref: &str) {
+        self.changed_terms
+            .extend(self.builder.document_terms(doc_ref));
+        self.changed_docs.insert(doc_ref.to_string());
+    }
+
+    pub fn has_pending_changes(&self) -> bool {
+        !self.changed_docs.is_empty()
+    }
+
+    // commit applies the pending changes to the index searched
+    pub fn commit(&mut self) {
+        if self.changed_docs.is_empty() {
+            return;
+        }
+        self.index
+            .refresh(&self.builder, &self.changed_docs, &self.changed_terms);
+        self.changed_docs.clear();
+        self.changed_terms.clear();
+    }
+
+    // rebuild commits the pending changes by building the index again, which
+    // also weighs the unchanged documents with the current document count
+    // and average field lengths
+    pub fn rebuild(&mut self) {
+        self.index = self.builder.build();
+        self.changed_docs.clear();
+        self.changed_terms.clear();
+    }
+
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    pub fn query(&self, query: &Query) -> Vec<MatchResult> {
+        self.index.query(query)
+    }
+}
@@ -0,0 +1,256 @@
+use crate::document::Document;
+use crate::field::{Field, FieldValue};
+use crate::json::value_from_json;
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Lines, Read};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    U64,
+    I64,
+    Text,
+}
+
+// RecordError reports a record which was skipped, with its line number
+// starting from 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+// Loader reads records of JSON Lines or CSV into documents.  The doc_ref is
+// taken from the doc_ref key, and the other keys become fields of the same
+// name unless fields are mapped, in which case only the mapped keys are read.
+// Values are converted to the type set in the schema, or else the type is
+// inferred from the value.
+pub struct Loader {
+    doc_ref_key: String,
+    mappings: Vec<(String, String)>, // key -> field_name
+    schema: HashMap<String, FieldType>,
+}
+
+impl Loader {
+    pub fn new(doc_ref_key: &str) -> Loader {
+        Loader {
+            doc_ref_key: doc_ref_key.to_string(),
+            mappings: Vec::new(),
+            schema: HashMap::new(),
+        }
+    }
+
+    pub fn map_field(&mut self, key: &str, field_name: &str) {
+        self.mappings
+            .push((key.to_string(), field_name.to_string()));
+    }
+
+    pub fn set_type(&mut self, field_name: &str, field_type: FieldType) {
+        self.schema.insert(field_name.to_string(), field_type);
+    }
+
+    // json_lines returns the documents of the JSON Lines.  Blank lines are
+    // not records and are skipped silently.
+    pub fn json_lines<R: BufRead>(&self, reader: R) -> JsonLinesRecords<'_, R> {
+        JsonLinesRecords {
+            loader: self,
+            lines: reader.lines(),
+            line: 0,
+        }
+    }
+
+    // csv returns the documents of the CSV, whose first row is the header
+    pub fn csv<R: Read>(&self, reader: R) -> CsvRecords<'_, R> {
+        CsvRecords {
+            loader: self,
+            reader: csv::Reader::from_reader(reader),
+            headers: None,
+        }
+    }
+
+    fn field_name<'a>(&'a self, key: &'a str) -> Option<&'a str> {
+        if self.mappings.is_empty() {
+            return Some(key);
+        }
+        self.mappings
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, field_name)| field_name.as_str())
+    }
+
+    fn convert(&self, field_name: &str, value: FieldValue) -> Result<FieldValue, String> {
+        let field_type = match self.schema.get(field_name) {
+            Some(field_type) => *field_type,
+            None => return Ok(value),
+        };
+        let invalid = |value: &FieldValue| {
+            format!(
+                "invalid {:?} value for {}: {}",
+                field_type, field_name, value
+            )
+        };
+        match (field_type, value) {
+            (FieldType::Text, value) => Ok(FieldValue::Text(value.to_string())),
+            (FieldType::U64, FieldValue::U64(v)) => Ok(FieldValue::U64(v)),
+            (FieldType::I64, FieldValue::I64(v)) => Ok(FieldValue::I64(v)),
+            (FieldType::I64, FieldValue::U64(v)) if v <= i64::MAX as u64 => {
+                Ok(FieldValue::I64(v as i64))
+            }
+            (FieldType::U64, FieldValue::Text(s)) => s
+                .trim()
+                .parse()
+                .map(FieldValue::U64)
+                .map_err(|_| invalid(&FieldValue::Text(s))),
+            (FieldType::I64, FieldValue::Text(s)) => s
+                .trim()
+                .parse()
+                .map(FieldValue::I64)
+                .map_err(|_| invalid(&FieldValue::Text(s))),
+            (_, value) => Err(invalid(&value)),
+        }
+    }
+
+    // document builds a document from the entries of a record.  The types of
+    // values are inferred when infer is set, only for fields without a type
+    // in the schema; the doc ref is always taken as is.
+    fn document<'a, I>(&self, entries: I, infer: bool) -> Result<Document, String>
+    where
+        I: Iterator<Item = (&'a str, FieldValue)>,
+    {
+        let mut doc_ref: Option<String> = None;
+        let mut fields: Vec<Field> = Vec::new();
+        for (key, value) in entries {
+            if key == self.doc_ref_key {
+                doc_ref = Some(value.to_string());
+                continue;
+            }
+            if let Some(field_name) = self.field_name(key) {
+                let value = match value {
+                    FieldValue::Text(s) if infer && !self.schema.contains_key(field_name) => {
+                        infer_value(&s)
+                    }
+                    value => self.convert(field_name, value)?,
+                };
+                fields.push(Field::new(field_name.to_string(), value));
+            }
+        }
+        let doc_ref = doc_ref
+            .filter(|doc_ref| !doc_ref.is_empty())
+            .ok_or_else(|| format!("missing doc ref {}", self.doc_ref_key))?;
+        let mut doc = Document::new(doc_ref);
+        for field in fields {
+            doc.add_field(field);
+        }
+        Ok(doc)
+    }
+}
+
+// infer_value parses a CSV value as an integer when it looks like one
+fn infer_value(value: &str) -> FieldValue {
+    if let Ok(v) = value.parse::<u64>() {
+        FieldValue::U64(v)
+    } else if let Ok(v) = value.parse::<i64>() {
+        FieldValue::I64(v)
+    } else {
+        FieldValue::Text(value.to_string())
+    }
+}
+
+pub struct JsonLinesRecords<'a, R> {
+    loader: &'a Loader,
+    lines: Lines<R>,
+    line: usize,
+}
+
+impl<'a, R: BufRead> Iterator for JsonLinesRecords<'a, R> {
+    type Item = Result<Document, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let source = self.lines.next()?;
+            self.line += 1;
+            let error = |message: String| RecordError {
+                line: self.line,
+                message,
+            };
+            let source = match source {
+                Ok(source) => source,
+                Err(e) => return Some(Err(error(e.to_string()))),
+            };
+            if source.trim().is_empty() {
+                continue;
+            }
+            let record = match serde_json::from_str::<Value>(&source) {
+                Ok(Value::Object(record)) => record,
+                Ok(_) => return Some(Err(error("record must be an object".into()))),
+                Err(e) => return Some(Err(error(e.to_string()))),
+            };
+            let entries = record
+                .iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k.as_str(), value_from_json(v)));
+            return Some(self.loader.document(entries, false).map_err(error));
+        }
+    }
+}
+
+pub struct CsvRecords<'a, R> {
+    loader: &'a Loader,
+    reader: csv::Reader<R>,
+    headers: Option<Vec<String>>,
+}
+
+impl<'a, R: Read> Iterator for CsvRecords<'a, R> {
+    type Item = Result<Document, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.headers.is_none() {
+            match self.reader.headers() {
+                Ok(headers) => self.headers = Some(headers.iter().map(|h| h.to_string()).collect()),
+                Err(e) => {
+                    self.headers = Some(Vec::new());
+                    return Some(Err(RecordError {
+                        line: 1,
+                        message: e.to_string(),
+                    }));
+                }
+            }
+        }
+
+        let mut record = csv::StringRecord::new();
+        let line = self.reader.position().line() as usize;
+        match self.reader.read_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => {
+                let line = e.position().map(|p| p.line() as usize).unwrap_or(line);
+                return Some(Err(RecordError {
+                    line,
+                    message: e.to_string(),
+                }));
+            }
+        }
+        let line = record.position().map(|p| p.line() as usize).unwrap_or(line);
+        let headers = self.headers.as_ref().unwrap();
+        let entries = headers
+            .iter()
+            .zip(record.iter())
+            .filter(|(_, v)| !v.is_empty())
+            .map(|(k, v)| (k.as_str(), FieldValue::Text(v.to_string())));
+        Some(
+            self.loader
+                .document(entries, true)
+                .map_err(|message| RecordError { line, message }),
+        )
+    }
+}
@@ -1,9 +1,18 @@
 use crate::token::Token;
 
-type PipelineFunction = Fn(i32) -> i32;
+use std::sync::Arc;
 
+type PipelineFunction = dyn Fn(Token) -> Option<Token> + Send + Sync;
+
+#[derive(Clone)]
 pub struct Pipeline {
-    registered: Vec<Box<PipelineFunction>>,
+    registered: Vec<(String, Arc<PipelineFunction>)>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Pipeline {
@@ -13,14 +22,26 @@ impl Pipeline {
         }
     }
 
-    pub fn add<T>(&mut self, f: T)
+    // label identifies the function when the pipeline is serialized with an index
+    pub fn add<T>(&mut self, label: &str, f: T)
     where
-        T: Fn(i32) -> i32 + 'static,
+        T: Fn(Token) -> Option<Token> + Send + Sync + 'static,
     {
-        self.registered.push(Box::new(f));
+        self.registered.push((label.to_string(), Arc::new(f)));
+    }
+
+    pub fn labels(&self) -> Vec<String> {
+        self.registered
+            .iter()
+            .map(|(label, _)| label.to_string())
+            .collect()
     }
 
     pub fn run(&self, tokens: Vec<Token>) -> Vec<Token> {
+        let mut tokens = tokens;
+        for (_, f) in self.registered.iter() {
+            tokens = tokens.into_iter().filter_map(f.as_ref()).collect();
+        }
         tokens
     }
 }
@@ -0,0 +1,539 @@
+use crate::binary::{invalid_data, read_varint, try_read_varint, write_varint};
+use crate::document::DocId;
+
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::iter::FromIterator;
+
+// BLOCK_SIZE is the largest number of postings in a block
+pub const BLOCK_SIZE: usize = 128;
+
+// PostingsFormat is what a posting list records for each document
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostingsFormat {
+    Docs,
+    Freqs,
+    Positions,
+}
+
+impl PostingsFormat {
+    fn from_u8(v: u8) -> Option<PostingsFormat> {
+        match v {
+            0 => Some(PostingsFormat::Docs),
+            1 => Some(PostingsFormat::Freqs),
+            2 => Some(PostingsFormat::Positions),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            PostingsFormat::Docs => 0,
+            PostingsFormat::Freqs => 1,
+            PostingsFormat::Positions => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Posting {
+    pub doc_id: DocId,
+    pub term_freq: u32,
+    pub positions: Vec<u32>,
+}
+
+impl Posting {
+    pub fn new(doc_id: DocId) -> Posting {
+        Posting {
+            doc_id,
+            term_freq: 1,
+            positions: Vec::new(),
+        }
+    }
+}
+
+// Skip locates a block in the data, with the last doc id in the block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Skip {
+    last: DocId,
+    count: u32,
+    offset: u32,
+}
+
+// PostingList is a sorted list of documents containing a term, compressed in
+// blocks of up to BLOCK_SIZE postings.  In a block, doc ids are delta-encoded
+// from the previous one, starting from 0, as varints, each followed by the
+// term frequency and the count and delta-encoded positions if the format
+// records them.  Positions must be sorted.  A skip entry per block lets
+// iterators skip blocks without decoding them.
+#[derive(Clone)]
+pub struct PostingList {
+    format: PostingsFormat,
+    len: usize,
+    skips: Vec<Skip>,
+    data: Vec<u8>,
+}
+
+impl Default for PostingList {
+    fn default() -> Self {
+        Self::new(PostingsFormat::Docs)
+    }
+}
+
+impl PartialEq for PostingList {
+    fn eq(&self, other: &PostingList) -> bool {
+        self.format == other.format && self.len == other.len && self.postings().eq(other.postings())
+    }
+}
+
+impl Eq for PostingList {}
+
+impl fmt::Debug for PostingList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl FromIterator<DocId> for PostingList {
+    fn from_iter<I: IntoIterator<Item = DocId>>(iter: I) -> Self {
+        let mut doc_ids: Vec<DocId> = iter.into_iter().collect();
+        doc_ids.sort_unstable();
+        doc_ids.dedup();
+        let mut list = PostingList::new(PostingsFormat::Docs);
+        for doc_id in doc_ids {
+            list.insert(doc_id);
+        }
+        list
+    }
+}
+
+impl<'a> IntoIterator for &'a PostingList {
+    type Item = DocId;
+    type IntoIter = PostingsIter<'a>;
+
+    fn into_iter(self) -> PostingsIter<'a> {
+        self.iter()
+    }
+}
+
+impl PostingList {
+    pub fn new(format: PostingsFormat) -> PostingList {
+        PostingList {
+            format,
+            len: 0,
+            skips: Vec::new(),
+            data: Vec::new(),
+        }
+    }
+
+    pub fn format(&self) -> PostingsFormat {
+        self.format
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // size returns the number of bytes of the compressed postings
+    pub fn size(&self) -> usize {
+        self.data.len() + self.skips.len() * std::mem::size_of::<Skip>()
+    }
+
+    pub fn iter(&self) -> PostingsIter<'_> {
+        PostingsIter::new(self.format, Cow::Borrowed(&self.skips), &self.data)
+    }
+
+    // postings returns the decoded postings in order of doc id
+    pub fn postings(&self) -> impl Iterator<Item = Posting> + '_ {
+        let mut iter = self.iter();
+        std::iter::from_fn(move || {
+            let doc_id = iter.next()?;
+            Some(Posting {
+                doc_id,
+                term_freq: iter.term_freq(),
+                positions: iter.positions().to_vec(),
+            })
+        })
+    }
+
+    pub fn contains(&self, doc_id: DocId) -> bool {
+        self.iter().advance(doc_id) == Some(doc_id)
+    }
+
+    pub fn get(&self, doc_id: DocId) -> Option<Posting> {
+        let mut iter = self.iter();
+        if iter.advance(doc_id)? != doc_id {
+            return None;
+        }
+        Some(Posting {
+            doc_id,
+            term_freq: iter.term_freq(),
+            positions: iter.positions().to_vec(),
+        })
+    }
+
+    // insert adds the document with a term frequency of 1, and returns false
+    // if it is already in the list
+    pub fn insert(&mut self, doc_id: DocId) -> bool {
+        if self.contains(doc_id) {
+            return false;
+        }
+        self.insert_posting(Posting::new(doc_id));
+        true
+    }
+
+    // insert_posting adds the posting, replacing the one of the same document.
+    // Appending a document after the last one does not decode any block.
+    pub fn insert_posting(&mut self, posting: Posting) {
+        if self.skips.last().is_none_or(|s| s.last < posting.doc_id) {
+            self.append(posting);
+            return;
+        }
+        let block = self.skips.partition_point(|s| s.last < posting.doc_id);
+        let mut postings = self.decode_block(block);
+        match postings.binary_search_by_key(&posting.doc_id, |p| p.doc_id) {
+            Ok(i) => postings[i] = posting,
+            Err(i) => postings.insert(i, posting),
+        }
+        self.replace_block(block, postings);
+    }
+
+    pub fn remove(&mut self, doc_id: DocId) -> bool {
+        let block = self.skips.partition_point(|s| s.last < doc_id);
+        if block == self.skips.len() {
+            return false;
+        }
+        let mut postings = self.decode_block(block);
+        match postings.binary_search_by_key(&doc_id, |p| p.doc_id) {
+            Ok(i) => {
+                postings.remove(i);
+                self.replace_block(block, postings);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn append(&mut self, posting: Posting) {
+        let start_block = self
+            .skips
+            .last()
+            .is_none_or(|s| s.count as usize >= BLOCK_SIZE);
+        if start_block {
+            self.skips.push(Skip {
+                last: 0,
+                count: 0,
+                offset: self.data.len() as u32,
+            });
+        }
+        let skip = self.skips.last_mut().unwrap();
+        let prev = if skip.count == 0 { 0 } else { skip.last };
+        encode_posting(&mut self.data, self.format, &posting, prev);
+        skip.last = posting.doc_id;
+        skip.count += 1;
+        self.len += 1;
+    }
+
+    fn block_range(&self, block: usize) -> (usize, usize) {
+        let start = self.skips[block].offset as usize;
+        let end = match self.skips.get(block + 1) {
+            Some(next) => next.offset as usize,
+            None => self.data.len(),
+        };
+        (start, end)
+    }
+
+    fn decode_block(&self, block: usize) -> Vec<Posting> {
+        let (start, end) = self.block_range(block);
+        let mut iter = PostingsIter::new(
+            self.format,
+            Cow::Owned(vec![Skip {
+                offset: 0,
+                ..self.skips[block]
+            }]),
+            &self.data[start..end],
+        );
+        let mut postings = Vec::with_capacity(self.skips[block].count as usize);
+        while let Some(doc_id) = iter.next() {
+            postings.push(Posting {
+                doc_id,
+                term_freq: iter.term_freq(),
+                positions: iter.positions().to_vec(),
+            });
+        }
+        postings
+    }
+
+    // replace_block encodes the postings in place of the block, splitting them
+    // in two blocks when they overflow one, or dropping the block if empty
+    fn replace_block(&mut self, block: usize, postings: Vec<Posting>) {
+        let (start, end) = self.block_range(block);
+        let old_count = self.skips[block].count as usize;
+
+        let chunk_size = if postings.len() > BLOCK_SIZE {
+            postings.len().div_ceil(2)
+        } else {
+            BLOCK_SIZE
+        };
+        let mut buf: Vec<u8> = Vec::new();
+        let mut skips: Vec<Skip> = Vec::new();
+        for chunk in postings.chunks(chunk_size) {
+            let offset = (start + buf.len()) as u32;
+            let mut prev = 0;
+            for posting in chunk {
+                encode_posting(&mut buf, self.format, posting, prev);
+                prev = posting.doc_id;
+            }
+            skips.push(Skip {
+                last: prev,
+                count: chunk.len() as u32,
+                offset,
+            });
+        }
+
+        let shift = buf.len() as i64 - (end - start) as i64;
+        self.data.splice(start..end, buf);
+        for skip in self.skips[block + 1..].iter_mut() {
+            skip.offset = (skip.offset as i64 + shift) as u32;
+        }
+        self.len = self.len - old_count + postings.len();
+        self.skips.splice(block..block + 1, skips);
+    }
+
+    // to_bytes serializes the list as its format, length and skips followed by
+    // the data, to be read back with PostingList::from_bytes or iterated in
+    // place with PostingsIter::from_bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = vec![self.format.to_u8()];
+        write_varint(&mut buf, self.len as u64);
+        write_varint(&mut buf, self.skips.len() as u64);
+        for skip in self.skips.iter() {
+            write_varint(&mut buf, skip.last as u64);
+            write_varint(&mut buf, skip.count as u64);
+            write_varint(&mut buf, skip.offset as u64);
+        }
+        buf.extend_from_slice(&self.data);
+        buf
+    }
+
+    // from_bytes reads a list serialized by to_bytes, failing if the bytes
+    // are truncated or corrupted
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<PostingList> {
+        let (format, len, skips, pos) = read_header(bytes)?;
+        check_blocks(format, len, &skips, &bytes[pos..])?;
+        Ok(PostingList {
+            format,
+            len,
+            skips,
+            data: bytes[pos..].to_vec(),
+        })
+    }
+}
+
+fn encode_posting(buf: &mut Vec<u8>, format: PostingsFormat, posting: &Posting, prev: DocId) {
+    write_varint(buf, (posting.doc_id - prev) as u64);
+    if format == PostingsFormat::Docs {
+        return;
+    }
+    write_varint(buf, posting.term_freq as u64);
+    if format == PostingsFormat::Positions {
+        write_varint(buf, posting.positions.len() as u64);
+        let mut prev = 0;
+        for position in posting.positions.iter() {
+            write_varint(buf, (position - prev) as u64);
+            prev = *position;
+        }
+    }
+}
+
+fn read_header(bytes: &[u8]) -> io::Result<(PostingsFormat, usize, Vec<Skip>, usize)> {
+    let format = bytes
+        .first()
+        .and_then(|v| PostingsFormat::from_u8(*v))
+        .ok_or_else(|| invalid_data("invalid postings format"))?;
+    let mut pos = 1;
+    let len = try_read_varint(bytes, &mut pos)? as usize;
+    let count = try_read_varint(bytes, &mut pos)? as usize;
+    // a skip takes at least three bytes
+    if count > bytes.len() / 3 {
+        return Err(invalid_data("truncated postings"));
+    }
+    let mut skips = Vec::with_capacity(count);
+    for _ in 0..count {
+        let last = try_read_varint(bytes, &mut pos)?;
+        let count = try_read_varint(bytes, &mut pos)?;
+        let offset = try_read_varint(bytes, &mut pos)?;
+        if last > DocId::MAX as u64 || count > u32::MAX as u64 || offset > u32::MAX as u64 {
+            return Err(invalid_data("invalid postings skip"));
+        }
+        skips.push(Skip {
+            last: last as DocId,
+            count: count as u32,
+            offset: offset as u32,
+        });
+    }
+    Ok((format, len, skips, pos))
+}
+
+// check_blocks decodes every block with checked reads, so that iterating the
+// data afterwards cannot read out of bounds.  Blocks must be contiguous, hold
+// ascending doc ids ending with the last one of their skip, and hold len
+// postings in all.
+fn check_blocks(format: PostingsFormat, len: usize, skips: &[Skip], data: &[u8]) -> io::Result<()> {
+    let invalid = || invalid_data("corrupted postings");
+    let mut total = 0usize;
+    let mut prev_last: Option<DocId> = None;
+    for (block, skip) in skips.iter().enumerate() {
+        let start = skip.offset as usize;
+        let end = match skips.get(block + 1) {
+            Some(next) => next.offset as usize,
+            None => data.len(),
+        };
+        if skip.count == 0 || start > end || end > data.len() || (block == 0 && start != 0) {
+            return Err(invalid());
+        }
+        let block_data = &data[..end];
+        let mut pos = start;
+        let mut doc_id = 0u64;
+        for i in 0..skip.count {
+            let delta = try_read_varint(block_data, &mut pos)?;
+            if i > 0 && delta == 0 {
+                return Err(invalid());
+            }
+            doc_id += delta;
+            if doc_id > DocId::MAX as u64 {
+                return Err(invalid());
+            }
+            if format != PostingsFormat::Docs {
+                try_read_varint(block_data, &mut pos)?;
+            }
+            if format == PostingsFormat::Positions {
+                for _ in 0..try_read_varint(block_data, &mut pos)? {
+                    try_read_varint(block_data, &mut pos)?;
+                }
+            }
+        }
+        if pos != end || doc_id != skip.last as u64 {
+            return Err(invalid());
+        }
+        if prev_last.is_some_and(|prev| prev >= skip.last) {
+            return Err(invalid());
+        }
+        prev_last = Some(skip.last);
+        total += skip.count as usize;
+    }
+    if total != len || (skips.is_empty() && !data.is_empty()) {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+// PostingsIter iterates the doc ids of a posting list in order.  The term
+// frequency and positions of the current document are read with term_freq and
+// positions.
+pub struct PostingsIter<'a> {
+    format: PostingsFormat,
+    skips: Cow<'a, [Skip]>,
+    data: &'a [u8],
+    block: usize,
+    pos: usize,
+    left: u32, // postings left in the block
+    doc_id: DocId,
+    term_freq: u32,
+    positions: Vec<u32>,
+}
+
+impl<'a> PostingsIter<'a> {
+    fn new(format: PostingsFormat, skips: Cow<'a, [Skip]>, data: &'a [u8]) -> PostingsIter<'a> {
+        let mut iter = PostingsIter {
+            format,
+            skips,
+            data,
+            block: 0,
+            pos: 0,
+            left: 0,
+            doc_id: 0,
+            term_freq: 0,
+            positions: Vec::new(),
+        };
+        iter.enter(0);
+        iter
+    }
+
+    // from_bytes iterates a list serialized by PostingList::to_bytes without
+    // copying its data, failing if the bytes are truncated or corrupted
+    pub fn from_bytes(bytes: &'a [u8]) -> io::Result<PostingsIter<'a>> {
+        let (format, len, skips, pos) = read_header(bytes)?;
+        check_blocks(format, len, &skips, &bytes[pos..])?;
+        Ok(PostingsIter::new(format, Cow::Owned(skips), &bytes[pos..]))
+    }
+
+    fn enter(&mut self, block: usize) {
+        self.block = block;
+        match self.skips.get(block) {
+            Some(skip) => {
+                self.pos = skip.offset as usize;
+                self.left = skip.count;
+            }
+            None => self.left = 0,
+        }
+        self.doc_id = 0;
+    }
+
+    pub fn term_freq(&self) -> u32 {
+        self.term_freq
+    }
+
+    pub fn positions(&self) -> &[u32] {
+        &self.positions
+    }
+
+    // advance moves to the first document not returned yet whose id is at
+    // least target, skipping the blocks ending before target
+    pub fn advance(&mut self, target: DocId) -> Option<DocId> {
+        if self.skips.get(self.block).is_some_and(|s| s.last < target) {
+            let rest = &self.skips[self.block + 1..];
+            let block = self.block + 1 + rest.partition_point(|s| s.last < target);
+            self.enter(block);
+        }
+        loop {
+            let doc_id = self.next()?;
+            if doc_id >= target {
+                return Some(doc_id);
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for PostingsIter<'a> {
+    type Item = DocId;
+
+    fn next(&mut self) -> Option<DocId> {
+        while self.left == 0 {
+            if self.block >= self.skips.len() {
+                return None;
+            }
+            self.enter(self.block + 1);
+        }
+        self.left -= 1;
+        self.doc_id += read_varint(self.data, &mut self.pos) as DocId;
+        self.term_freq = 1;
+        self.positions.clear();
+        if self.format != PostingsFormat::Docs {
+            self.term_freq = read_varint(self.data, &mut self.pos) as u32;
+        }
+        if self.format == PostingsFormat::Positions {
+            let mut position = 0;
+            for _ in 0..read_varint(self.data, &mut self.pos) {
+                position += read_varint(self.data, &mut self.pos) as u32;
+                self.positions.push(position);
+            }
+        }
+        Some(self.doc_id)
+    }
+}
@@ -1,3 +1,10 @@
+use crate::regex::{Regex, RegexError};
+use crate::token::FuzzyOptions;
+
+// DEFAULT_MAX_EXPANSIONS caps the number of terms a regex or fuzzy clause
+// expands to
+pub const DEFAULT_MAX_EXPANSIONS: usize = 1024;
+
 #[derive(Eq, PartialEq, Clone)]
 pub enum WildcardMode {
     None,
@@ -20,6 +27,9 @@ pub struct Clause {
     pub use_pipeline: bool,
     pub wildcard: WildcardMode,
     pub presence: Presence,
+    pub regex: Option<Regex>,
+    pub fuzzy: Option<FuzzyOptions>,
+    pub max_expansions: usize,
 }
 
 impl Clause {
@@ -31,9 +41,20 @@ impl Clause {
             use_pipeline: true,
             wildcard: WildcardMode::None,
             presence: Presence::Optional,
+            regex: None,
+            fuzzy: None,
+            max_expansions: DEFAULT_MAX_EXPANSIONS,
         }
     }
 
+    // new_regex creates a clause matching the terms of the index that the
+    // pattern matches entirely, such as "colou?r"
+    pub fn new_regex(pattern: &str) -> Result<Clause, RegexError> {
+        let mut clause = Clause::new(pattern.to_string());
+        clause.regex = Some(Regex::new(pattern)?);
+        Ok(clause)
+    }
+
     pub fn term(&self) -> &str {
         &self.term
     }
@@ -58,6 +79,18 @@ impl Clause {
         self.presence.clone()
     }
 
+    pub fn regex(&self) -> Option<&Regex> {
+        self.regex.as_ref()
+    }
+
+    pub fn fuzzy(&self) -> Option<&FuzzyOptions> {
+        self.fuzzy.as_ref()
+    }
+
+    pub fn max_expansions(&self) -> usize {
+        self.max_expansions
+    }
+
     pub fn set_fields(&mut self, fields: Vec<String>) {
         self.fields = Some(fields);
     }
@@ -77,12 +110,28 @@ impl Clause {
     pub fn set_presence(&mut self, presence: Presence) {
         self.presence = presence;
     }
+
+    // set_fuzzy matches the terms within the edit distance of the clause's
+    // term instead of the term itself
+    pub fn set_fuzzy(&mut self, options: FuzzyOptions) {
+        self.fuzzy = Some(options);
+    }
+
+    pub fn set_max_expansions(&mut self, max_expansions: usize) {
+        self.max_expansions = max_expansions;
+    }
 }
 
 pub struct Query {
     pub clauses: Vec<Clause>,
 }
 
+impl Default for Query {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Query {
     pub fn new() -> Query {
         Query {
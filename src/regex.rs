@@ -0,0 +1,317 @@
+use std::fmt;
+
+// Regex is a restricted regular expression over whole terms, compiled to an
+// NFA that is walked one character at a time alongside a token set.  The
+// syntax is literals, `.`, character classes such as `[a-z]` and `[^0-9]`,
+// groups, alternation with `|`, the repetitions `?`, `*` and `+`, and `\`
+// to escape a special character.  A pattern must match the entire term.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Regex {
+    pattern: String,
+    states: Vec<State>,
+    start: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegexError {
+    UnexpectedEnd,
+    UnexpectedChar(char, usize),
+    Unsupported(char, usize),
+    EmptyClass(usize),
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegexError::UnexpectedEnd => write!(f, "unexpected end of pattern"),
+            RegexError::UnexpectedChar(c, pos) => {
+                write!(f, "unexpected '{}' at position {}", c, pos)
+            }
+            RegexError::Unsupported(c, pos) => {
+                write!(f, "unsupported '{}' at position {}", c, pos)
+            }
+            RegexError::EmptyClass(pos) => {
+                write!(f, "empty character class at position {}", pos)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RegexError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Matcher {
+    Char(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+}
+
+impl Matcher {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Matcher::Char(m) => *m == c,
+            Matcher::Any => true,
+            Matcher::Class { ranges, negated } => {
+                ranges.iter().any(|(lo, hi)| *lo <= c && c <= *hi) != *negated
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum State {
+    Match(Matcher, usize),
+    Split(usize, usize),
+    Accept,
+}
+
+enum Node {
+    Empty,
+    Match(Matcher),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Optional(Box<Node>),
+    Star(Box<Node>),
+    Plus(Box<Node>),
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Result<char, RegexError> {
+        let c = self.peek().ok_or(RegexError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn alt(&mut self) -> Result<Node, RegexError> {
+        let mut branches = vec![self.concat()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            branches.push(self.concat()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Node::Alt(branches)
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node, RegexError> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            nodes.push(self.repeat()?);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn repeat(&mut self) -> Result<Node, RegexError> {
+        let mut node = self.atom()?;
+        while let Some(c) = self.peek() {
+            node = match c {
+                '?' => Node::Optional(Box::new(node)),
+                '*' => Node::Star(Box::new(node)),
+                '+' => Node::Plus(Box::new(node)),
+                _ => break,
+            };
+            self.pos += 1;
+        }
+        Ok(node)
+    }
+
+    fn atom(&mut self) -> Result<Node, RegexError> {
+        let pos = self.pos;
+        match self.next()? {
+            '.' => Ok(Node::Match(Matcher::Any)),
+            '(' => {
+                let node = self.alt()?;
+                match self.next()? {
+                    ')' => Ok(node),
+                    c => Err(RegexError::UnexpectedChar(c, self.pos - 1)),
+                }
+            }
+            '[' => self.class(pos),
+            '\\' => Ok(Node::Match(Matcher::Char(self.next()?))),
+            c @ ('?' | '*' | '+' | ')' | ']') => Err(RegexError::UnexpectedChar(c, pos)),
+            c @ ('{' | '}' | '^' | '$') => Err(RegexError::Unsupported(c, pos)),
+            c => Ok(Node::Match(Matcher::Char(c))),
+        }
+    }
+
+    fn class(&mut self, start: usize) -> Result<Node, RegexError> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = Vec::new();
+        loop {
+            let lo = match self.next()? {
+                ']' => break,
+                '\\' => self.next()?,
+                c => c,
+            };
+            let hi = if self.peek() == Some('-') && self.chars.get(self.pos + 1) != Some(&']') {
+                self.pos += 1;
+                match self.next()? {
+                    '\\' => self.next()?,
+                    c => c,
+                }
+            } else {
+                lo
+            };
+            if hi < lo {
+                return Err(RegexError::UnexpectedChar(hi, self.pos - 1));
+            }
+            ranges.push((lo, hi));
+        }
+        if ranges.is_empty() {
+            return Err(RegexError::EmptyClass(start));
+        }
+        Ok(Node::Match(Matcher::Class { ranges, negated }))
+    }
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, RegexError> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+        };
+        let node = parser.alt()?;
+        if let Some(c) = parser.peek() {
+            return Err(RegexError::UnexpectedChar(c, parser.pos));
+        }
+
+        let mut states = vec![State::Accept];
+        let start = Regex::compile(&node, 0, &mut states);
+        Ok(Regex {
+            pattern: pattern.to_string(),
+            states,
+            start,
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    // compile adds the states matching node and continuing at next, and
+    // returns the state to start at
+    fn compile(node: &Node, next: usize, states: &mut Vec<State>) -> usize {
+        match node {
+            Node::Empty => next,
+            Node::Match(matcher) => {
+                states.push(State::Match(matcher.clone(), next));
+                states.len() - 1
+            }
+            Node::Concat(nodes) => nodes
+                .iter()
+                .rev()
+                .fold(next, |next, node| Regex::compile(node, next, states)),
+            Node::Alt(nodes) => {
+                let starts: Vec<usize> = nodes
+                    .iter()
+                    .map(|node| Regex::compile(node, next, states))
+                    .collect();
+                starts[1..].iter().fold(starts[0], |start, other| {
+                    states.push(State::Split(start, *other));
+                    states.len() - 1
+                })
+            }
+            Node::Optional(node) => {
+                let body = Regex::compile(node, next, states);
+                states.push(State::Split(body, next));
+                states.len() - 1
+            }
+            Node::Star(node) => {
+                states.push(State::Split(next, next));
+                let split = states.len() - 1;
+                let body = Regex::compile(node, split, states);
+                states[split] = State::Split(body, next);
+                split
+            }
+            Node::Plus(node) => {
+                states.push(State::Split(next, next));
+                let split = states.len() - 1;
+                let body = Regex::compile(node, split, states);
+                states[split] = State::Split(body, next);
+                body
+            }
+        }
+    }
+
+    // closure follows the splits from the states, keeping the states that
+    // match a character or accept
+    fn closure(&self, states: Vec<usize>) -> Vec<usize> {
+        let mut stack = states;
+        let mut seen = vec![false; self.states.len()];
+        let mut closure = Vec::new();
+        while let Some(state) = stack.pop() {
+            if seen[state] {
+                continue;
+            }
+            seen[state] = true;
+            match &self.states[state] {
+                State::Split(a, b) => {
+                    stack.push(*a);
+                    stack.push(*b);
+                }
+                _ => closure.push(state),
+            }
+        }
+        closure.sort_unstable();
+        closure
+    }
+
+    // start returns the states before the first character
+    pub(crate) fn start(&self) -> Vec<usize> {
+        self.closure(vec![self.start])
+    }
+
+    // step returns the states after the character, empty once no term
+    // continuing this way can match
+    pub(crate) fn step(&self, states: &[usize], c: char) -> Vec<usize> {
+        let next: Vec<usize> = states
+            .iter()
+            .filter_map(|state| match &self.states[*state] {
+                State::Match(matcher, next) if matcher.matches(c) => Some(*next),
+                _ => None,
+            })
+            .collect();
+        self.closure(next)
+    }
+
+    pub(crate) fn accepts(&self, states: &[usize]) -> bool {
+        states
+            .iter()
+            .any(|state| self.states[*state] == State::Accept)
+    }
+
+    pub fn is_match(&self, term: &str) -> bool {
+        let mut states = self.start();
+        for c in term.chars() {
+            states = self.step(&states, c);
+            if states.is_empty() {
+                return false;
+            }
+        }
+        self.accepts(&states)
+    }
+}
@@ -0,0 +1,539 @@
+use crate::builder::{Builder, CollectionStats};
+use crate::document::{DocId, Document};
+use crate::field::FieldId;
+use crate::index::{
+    evaluate, field_ids, plan, sort_results, FieldVectors, IndexReader, MatchResult, Metadata,
+};
+use crate::postings::PostingsIter;
+use crate::query::Query;
+use crate::regex::Regex;
+use crate::token::TokenSet;
+use crate::vector::Vector;
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+// MergePolicy decides which segments are merged after a flush.  Segments are
+// grouped into levels by size, each level holding segments up to merge_factor
+// times larger than the previous one, and merge_factor segments on the same
+// level are merged into one.  A segment whose ratio of deleted documents
+// exceeds max_deleted_ratio is rewritten on its own to drop the tombstones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MergePolicy {
+    pub merge_factor: usize,
+    pub max_segment_docs: usize,
+    pub max_deleted_ratio: f64,
+}
+
+impl Default for MergePolicy {
+    fn default() -> Self {
+        MergePolicy {
+            merge_factor: 10,
+            max_segment_docs: usize::MAX,
+            max_deleted_ratio: 0.5,
+        }
+    }
+}
+
+impl MergePolicy {
+    fn level(&self, docs: usize) -> usize {
+        let factor = self.merge_factor.max(2);
+        let mut level = 0;
+        let mut size = docs / factor;
+        while size > 0 {
+            level += 1;
+            size /= factor;
+        }
+        level
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SegmentStats {
+    pub documents: usize,
+    pub deleted: usize,
+}
+
+type NewBuilder = Arc<dyn Fn() -> Builder + Send + Sync>;
+
+struct Segment {
+    id: usize,
+    builder: Arc<Builder>,
+    tombstones: HashSet<DocId>,
+    // the live documents weighed with the statistics of the whole index, as
+    // of the last flush or merge
+    field_vectors: FieldVectors,
+    complete_doc_ids: HashSet<DocId>,
+}
+
+impl Segment {
+    fn new(id: usize, builder: Builder) -> Segment {
+        Segment {
+            id,
+            builder: Arc::new(builder),
+            tombstones: HashSet::new(),
+            field_vectors: HashMap::new(),
+            complete_doc_ids: HashSet::new(),
+        }
+    }
+
+    fn documents(&self) -> usize {
+        self.builder.document_fields().len() - self.tombstones.len()
+    }
+
+    // live_id returns the id of the document unless it is deleted
+    fn live_id(&self, doc_ref: &str) -> Option<DocId> {
+        self.builder
+            .doc_refs()
+            .id(doc_ref)
+            .filter(|doc_id| !self.tombstones.contains(doc_id))
+    }
+
+    // delete tombstones the document.  It stops matching at once, as queries
+    // only accept complete documents, while its field vectors are dropped at
+    // the next weighing.
+    fn delete(&mut self, doc_ref: &str) -> bool {
+        let doc_id = match self.live_id(doc_ref) {
+            Some(doc_id) => doc_id,
+            None => return false,
+        };
+        self.tombstones.insert(doc_id);
+        self.complete_doc_ids.remove(&doc_id);
+        true
+    }
+
+    fn deleted_ratio(&self) -> f64 {
+        let total = self.builder.document_fields().len();
+        if total == 0 {
+            0.0
+        } else {
+            self.tombstones.len() as f64 / total as f64
+        }
+    }
+
+    // weigh computes the field vectors of the live documents with the
+    // statistics and term indexes of the whole index
+    fn weigh(
+        &mut self,
+        stats: &CollectionStats,
+        term_indexes: &HashMap<String, u64>,
+        field_ids: &HashMap<&str, FieldId>,
+    ) {
+        self.field_vectors.clear();
+        self.complete_doc_ids.clear();
+        for doc_id in self.builder.document_fields().keys() {
+            if self.tombstones.contains(doc_id) {
+                continue;
+            }
+            for (field_name, vector) in
+                self.builder
+                    .document_vectors_in(*doc_id, stats, term_indexes)
+            {
+                if !vector.is_empty() {
+                    self.complete_doc_ids.insert(*doc_id);
+                }
+                self.field_vectors
+                    .insert((*doc_id, field_ids[field_name]), vector);
+            }
+        }
+    }
+}
+
+// Vocabulary holds the terms of all segments, numbered in sorted order, with
+// the number of live documents containing them, as of the last flush or
+// merge.  Queries expand terms against it so that every segment scores the
+// same query vectors.
+struct Vocabulary {
+    field_names: Vec<String>, // field_id -> field_name
+    token_set: TokenSet,
+    term_indexes: HashMap<String, u64>,
+    documents_with_term: HashMap<String, usize>,
+}
+
+// SegmentReader reads a segment through the vocabulary of the whole index
+struct SegmentReader<'a> {
+    vocabulary: &'a Vocabulary,
+    segment: &'a Segment,
+}
+
+impl IndexReader for SegmentReader<'_> {
+    fn field_names(&self) -> Vec<&str> {
+        self.vocabulary
+            .field_names
+            .iter()
+            .map(|f| f.as_str())
+            .collect()
+    }
+
+    fn terms(&self, token_set: &TokenSet) -> Vec<String> {
+        self.vocabulary.token_set.intersect(token_set).to_vec()
+    }
+
+    fn regex_terms(&self, regex: &Regex, limit: usize) -> Vec<String> {
+        self.vocabulary.token_set.intersect_regex(regex, limit)
+    }
+
+    fn term_index(&self, term: &str) -> Option<u64> {
+        self.vocabulary.term_indexes.get(term).cloned()
+    }
+
+    fn documents_with_term(&self, term: &str) -> usize {
+        self.vocabulary
+            .documents_with_term
+            .get(term)
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    fn postings(&self, term: &str, field_name: &str) -> Option<PostingsIter<'_>> {
+        let ri = self.segment.builder.inverted_index().get(term)?;
+        ri.documents.get(field_name).map(|docs| docs.iter())
+    }
+
+    fn metadata(&self, term: &str, field_name: &str) -> Cow<'_, HashMap<DocId, Metadata>> {
+        self.segment
+            .builder
+            .inverted_index()
+            .get(term)
+            .and_then(|ri| ri.metadata.get(field_name))
+            .map(Cow::Borrowed)
+            .unwrap_or_default()
+    }
+
+    fn field_vector(&self, doc_id: DocId, field_name: &str) -> Option<Cow<'_, Vector>> {
+        let field_id = self
+            .vocabulary
+            .field_names
+            .binary_search_by(|f| f.as_str().cmp(field_name))
+            .ok()?;
+        self.segment
+            .field_vectors
+            .get(&(doc_id, field_id as FieldId))
+            .map(Cow::Borrowed)
+    }
+
+    fn field_vector_keys(&self) -> Vec<(DocId, &str)> {
+        self.segment
+            .field_vectors
+            .keys()
+            .map(|(doc_id, field_id)| {
+                let field_name = &self.vocabulary.field_names[*field_id as usize];
+                (*doc_id, field_name.as_str())
+            })
+            .collect()
+    }
+
+    fn complete_doc_ids(&self) -> &HashSet<DocId> {
+        &self.segment.complete_doc_ids
+    }
+
+    fn doc_ref(&self, doc_id: DocId) -> &str {
+        self.segment.builder.doc_refs().doc_ref(doc_id)
+    }
+}
+
+// Merge is a merge running on a worker thread.  Its segments stay searchable
+// until it is installed, and documents deleted from them meanwhile are
+// deleted from the merged segment then.
+struct Merge {
+    segments: Vec<(usize, HashSet<DocId>)>, // segment id, tombstones when started
+    worker: JoinHandle<Builder>,
+}
+
+// merge_builders builds a new segment from the live documents of segments
+fn merge_builders(
+    new_builder: &NewBuilder,
+    segments: &[(Arc<Builder>, HashSet<DocId>)],
+) -> Builder {
+    let mut builder = new_builder();
+    for (other, tombstones) in segments {
+        builder.absorb_except(other, tombstones);
+    }
+    builder
+}
+
+// SegmentedIndex stores documents in small immutable segments, each built by
+// a Builder.  Added documents are buffered and become searchable when the
+// buffer is flushed into a new segment.  Deleted documents are kept as
+// tombstones in their segment until the segment is merged.  Merges run on a
+// worker thread, one at a time, and are installed by the next flush.
+//
+// Every flush and merge weighs the documents of all segments with the
+// statistics of all live documents, so scores do not depend on how documents
+// are spread over segments.  Deleted documents stop matching at once, but
+// leave the statistics only at the next flush or merge.
+pub struct SegmentedIndex {
+    new_builder: NewBuilder,
+    buffer: Builder,
+    flush_threshold: usize,
+    merge_policy: MergePolicy,
+    segments: Vec<Segment>,
+    next_id: usize,
+    merge: Option<Merge>,
+    vocabulary: Vocabulary,
+}
+
+impl SegmentedIndex {
+    // new_builder creates the builder for each segment, with fields and
+    // pipeline configured.  It is called from the merge worker too.
+    pub fn new<F>(new_builder: F) -> SegmentedIndex
+    where
+        F: Fn() -> Builder + Send + Sync + 'static,
+    {
+        let buffer = new_builder();
+        let mut field_names: Vec<String> = buffer.field_names().iter().cloned().collect();
+        field_names.sort_unstable();
+        SegmentedIndex {
+            new_builder: Arc::new(new_builder),
+            buffer,
+            flush_threshold: 1000,
+            merge_policy: MergePolicy::default(),
+            segments: Vec::new(),
+            next_id: 0,
+            merge: None,
+            vocabulary: Vocabulary {
+                field_names,
+                token_set: TokenSet::from_array(&Vec::new()),
+                term_indexes: HashMap::new(),
+                documents_with_term: HashMap::new(),
+            },
+        }
+    }
+
+    pub fn set_flush_threshold(&mut self, flush_threshold: usize) {
+        self.flush_threshold = flush_threshold.max(1);
+    }
+
+    pub fn set_merge_policy(&mut self, merge_policy: MergePolicy) {
+        self.merge_policy = merge_policy;
+    }
+
+    pub fn segments(&self) -> Vec<SegmentStats> {
+        self.segments
+            .iter()
+            .map(|segment| SegmentStats {
+                documents: segment.documents(),
+                deleted: segment.tombstones.len(),
+            })
+            .collect()
+    }
+
+    // add adds the document, replacing an existing document with the same doc_ref
+    pub fn add(&mut self, doc: Document) {
+        self.add_with_boost(doc, 1.0);
+    }
+
+    pub fn add_with_boost(&mut self, doc: Document, boost: f64) {
+        for segment in self.segments.iter_mut() {
+            segment.delete(doc.doc_ref());
+        }
+        self.buffer.add_document_with_boost(doc, boost);
+        if self.buffer.document_fields().len() >= self.flush_threshold {
+            self.flush();
+        }
+    }
+
+    pub fn delete(&mut self, doc_ref: &str) -> bool {
+        let mut deleted = self.buffer.remove_document(doc_ref);
+        for segment in self.segments.iter_mut() {
+            deleted = segment.delete(doc_ref) || deleted;
+        }
+        deleted
+    }
+
+    // flush builds the buffered documents into a new segment, installs the
+    // running merge if it has finished, and starts the next merge the merge
+    // policy decides on
+    pub fn flush(&mut self) {
+        if !self.buffer.document_fields().is_empty() {
+            let builder = std::mem::replace(&mut self.buffer, (self.new_builder)());
+            self.push_segment(builder);
+        }
+        if self.merge.as_ref().is_some_and(|m| m.worker.is_finished()) {
+            let merge = self.merge.take().unwrap();
+            self.install_merge(merge);
+        }
+        self.start_merge();
+        self.refresh();
+    }
+
+    // wait_for_merges blocks until the running merge and every merge the
+    // merge policy decides on after it are installed
+    pub fn wait_for_merges(&mut self) {
+        while let Some(merge) = self.merge.take() {
+            self.install_merge(merge);
+            self.start_merge();
+        }
+        self.refresh();
+    }
+
+    // force_merge merges all segments into one and drops every tombstone
+    pub fn force_merge(&mut self) {
+        if !self.buffer.document_fields().is_empty() {
+            let builder = std::mem::replace(&mut self.buffer, (self.new_builder)());
+            self.push_segment(builder);
+        }
+        if let Some(merge) = self.merge.take() {
+            self.install_merge(merge);
+        }
+        if self.segments.len() > 1 || self.segments.iter().any(|s| !s.tombstones.is_empty()) {
+            let sources: Vec<(Arc<Builder>, HashSet<DocId>)> = self
+                .segments
+                .drain(..)
+                .map(|s| (s.builder, s.tombstones))
+                .collect();
+            let builder = merge_builders(&self.new_builder, &sources);
+            self.push_segment(builder);
+        }
+        self.refresh();
+    }
+
+    fn push_segment(&mut self, builder: Builder) {
+        if builder.document_fields().is_empty() {
+            return;
+        }
+        self.segments.push(Segment::new(self.next_id, builder));
+        self.next_id += 1;
+    }
+
+    // start_merge starts merging the segments the merge policy picks, unless
+    // a merge is already running
+    fn start_merge(&mut self) {
+        if self.merge.is_some() {
+            return;
+        }
+        let candidates = match self.find_merge() {
+            Some(candidates) => candidates,
+            None => return,
+        };
+        let sources: Vec<(Arc<Builder>, HashSet<DocId>)> = candidates
+            .iter()
+            .map(|i| {
+                let segment = &self.segments[*i];
+                (segment.builder.clone(), segment.tombstones.clone())
+            })
+            .collect();
+        let segments = candidates
+            .iter()
+            .zip(sources.iter())
+            .map(|(i, (_, tombstones))| (self.segments[*i].id, tombstones.clone()))
+            .collect();
+        let new_builder = self.new_builder.clone();
+        let worker = thread::spawn(move || merge_builders(&new_builder, &sources));
+        self.merge = Some(Merge { segments, worker });
+    }
+
+    // install_merge waits for the merge and replaces its segments with the
+    // merged one
+    fn install_merge(&mut self, merge: Merge) {
+        let builder = merge
+            .worker
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e));
+        let mut deleted: Vec<String> = Vec::new();
+        for (id, tombstones) in merge.segments {
+            let i = self.segments.iter().position(|s| s.id == id).unwrap();
+            let segment = self.segments.remove(i);
+            for doc_id in segment.tombstones.difference(&tombstones) {
+                deleted.push(segment.builder.doc_refs().doc_ref(*doc_id).to_string());
+            }
+        }
+        self.push_segment(builder);
+        if let Some(segment) = self.segments.last_mut() {
+            for doc_ref in deleted {
+                segment.delete(&doc_ref);
+            }
+        }
+    }
+
+    fn find_merge(&self) -> Option<Vec<usize>> {
+        let policy = &self.merge_policy;
+        if let Some(i) = self
+            .segments
+            .iter()
+            .position(|s| s.deleted_ratio() > policy.max_deleted_ratio)
+        {
+            return Some(vec![i]);
+        }
+
+        let mut levels: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (i, segment) in self.segments.iter().enumerate() {
+            if segment.documents() <= policy.max_segment_docs {
+                levels
+                    .entry(policy.level(segment.documents()))
+                    .or_default()
+                    .push(i);
+            }
+        }
+        let mut levels: Vec<(usize, Vec<usize>)> = levels.into_iter().collect();
+        levels.sort();
+        for (_, mut candidates) in levels {
+            if candidates.len() < policy.merge_factor.max(2) {
+                continue;
+            }
+            candidates.sort_by_key(|i| self.segments[*i].documents());
+            candidates.truncate(policy.merge_factor.max(2));
+            let documents: usize = candidates
+                .iter()
+                .map(|i| self.segments[*i].documents())
+                .sum();
+            if documents <= policy.max_segment_docs {
+                return Some(candidates);
+            }
+        }
+        None
+    }
+
+    // refresh gathers the statistics and the vocabulary of all segments and
+    // weighs every segment with them
+    fn refresh(&mut self) {
+        let mut stats = CollectionStats::default();
+        for segment in self.segments.iter() {
+            stats.add(segment.builder.collection_stats(&segment.tombstones));
+        }
+        let mut terms: Vec<String> = stats
+            .documents_with_term
+            .iter()
+            .filter(|(_, count)| **count > 0)
+            .map(|(term, _)| term.to_string())
+            .collect();
+        terms.sort_unstable();
+        let term_indexes: HashMap<String, u64> = terms
+            .iter()
+            .enumerate()
+            .map(|(index, term)| (term.to_string(), index as u64))
+            .collect();
+
+        let field_names: HashSet<String> = self.vocabulary.field_names.iter().cloned().collect();
+        let field_ids = field_ids(&field_names);
+        for segment in self.segments.iter_mut() {
+            segment.weigh(&stats, &term_indexes, &field_ids);
+        }
+        self.vocabulary.token_set = TokenSet::from_array(&terms);
+        self.vocabulary.term_indexes = term_indexes;
+        self.vocabulary.documents_with_term = stats.documents_with_term;
+    }
+
+    // query plans and evaluates the query on each segment as Index::query
+    // does, through the vocabulary of the whole index
+    pub fn query(&self, query: &Query) -> Vec<MatchResult> {
+        let mut results: Vec<MatchResult> = Vec::new();
+        for segment in self.segments.iter() {
+            let reader = SegmentReader {
+                vocabulary: &self.vocabulary,
+                segment,
+            };
+            let plan = plan(&reader, query);
+            results.extend(evaluate(
+                &reader,
+                query,
+                &plan,
+                |field_name, field_vector| plan.query_vectors[field_name].score(field_vector),
+            ));
+        }
+        sort_results(&mut results);
+        results
+    }
+}
@@ -0,0 +1,192 @@
+// TermStats describes a term in a field of a document, together with the
+// collection statistics a similarity needs to weigh it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TermStats {
+    pub term_freq: usize,
+    pub field_length: usize,
+    pub average_field_length: f64,
+    pub documents_with_term: usize,
+    pub doc_count: usize,
+    pub collection_term_freq: usize, // occurrences of the term in the field of all documents
+    pub collection_length: usize,    // number of terms in the field of all documents
+    pub boost: f64,
+}
+
+// Similarity weighs a term of a document, which becomes the element of the
+// term in the field vector.
+pub trait Similarity: Send + Sync {
+    fn score(&self, stats: &TermStats) -> f64;
+
+    // score_fields weighs a term in all fields of a document containing it,
+    // for models combining the fields before weighing.  By default each
+    // field is weighed on its own.
+    fn score_fields(&self, stats: &[TermStats]) -> Vec<f64> {
+        stats.iter().map(|s| self.score(s)).collect()
+    }
+
+    // set_k1 and set_b tune the term frequency saturation and the length
+    // normalization of the BM25 family.  Other models ignore them.
+    fn set_k1(&mut self, _k1: f64) {}
+
+    fn set_b(&mut self, _b: f64) {}
+}
+
+fn idf(stats: &TermStats) -> f64 {
+    bm25_idf(stats.documents_with_term, stats.doc_count)
+}
+
+pub(crate) fn bm25_idf(documents_with_term: usize, doc_count: usize) -> f64 {
+    let df = documents_with_term as f64;
+    let x = (doc_count as f64 - df + 0.5) / (df + 0.5);
+    (x.abs() + 1.0).ln()
+}
+
+fn length_norm(stats: &TermStats, b: f64) -> f64 {
+    1.0 - b + b * (stats.field_length as f64 / stats.average_field_length)
+}
+
+// Bm25 is the Okapi BM25 model, as used by lunr.js
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bm25 {
+    pub k1: f64,
+    pub b: f64,
+}
+
+impl Default for Bm25 {
+    fn default() -> Self {
+        Bm25 { k1: 1.2, b: 0.75 }
+    }
+}
+
+impl Similarity for Bm25 {
+    fn score(&self, stats: &TermStats) -> f64 {
+        let tf = stats.term_freq as f64;
+        idf(stats) * ((self.k1 + 1.0) * tf) / (self.k1 * length_norm(stats, self.b) + tf)
+            * stats.boost
+    }
+
+    fn set_k1(&mut self, k1: f64) {
+        self.k1 = k1;
+    }
+
+    fn set_b(&mut self, b: f64) {
+        self.b = b;
+    }
+}
+
+// Bm25Plus adds delta to the saturated term frequency of BM25, so that a
+// match in a very long field still weighs more than no match
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bm25Plus {
+    pub k1: f64,
+    pub b: f64,
+    pub delta: f64,
+}
+
+impl Default for Bm25Plus {
+    fn default() -> Self {
+        Bm25Plus {
+            k1: 1.2,
+            b: 0.75,
+            delta: 1.0,
+        }
+    }
+}
+
+impl Similarity for Bm25Plus {
+    fn score(&self, stats: &TermStats) -> f64 {
+        let tf = stats.term_freq as f64;
+        let saturated = ((self.k1 + 1.0) * tf) / (self.k1 * length_norm(stats, self.b) + tf);
+        idf(stats) * (saturated + self.delta) * stats.boost
+    }
+
+    fn set_k1(&mut self, k1: f64) {
+        self.k1 = k1;
+    }
+
+    fn set_b(&mut self, b: f64) {
+        self.b = b;
+    }
+}
+
+// TfIdf is the classic vector space model, with the square root of the term
+// frequency and a length norm of the field
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TfIdf;
+
+impl Similarity for TfIdf {
+    fn score(&self, stats: &TermStats) -> f64 {
+        let tf = (stats.term_freq as f64).sqrt();
+        let idf =
+            1.0 + ((stats.doc_count as f64 + 1.0) / (stats.documents_with_term as f64 + 1.0)).ln();
+        let norm = 1.0 / (stats.field_length.max(1) as f64).sqrt();
+        tf * idf * norm * stats.boost
+    }
+}
+
+// Bm25F sums the length-normalized term frequencies of all fields, weighted
+// by their boosts, and saturates the sum once.  The score is split over the
+// fields in proportion to their share of the sum.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bm25F {
+    pub k1: f64,
+    pub b: f64,
+}
+
+impl Default for Bm25F {
+    fn default() -> Self {
+        Bm25F { k1: 1.2, b: 0.75 }
+    }
+}
+
+impl Similarity for Bm25F {
+    fn score(&self, stats: &TermStats) -> f64 {
+        self.score_fields(std::slice::from_ref(stats))[0]
+    }
+
+    fn score_fields(&self, stats: &[TermStats]) -> Vec<f64> {
+        let weights: Vec<f64> = stats
+            .iter()
+            .map(|s| s.boost * s.term_freq as f64 / length_norm(s, self.b))
+            .collect();
+        let tf: f64 = weights.iter().sum();
+        if tf <= 0.0 {
+            return vec![0.0; stats.len()];
+        }
+        let score = idf(&stats[0]) * ((self.k1 + 1.0) * tf) / (self.k1 + tf);
+        weights.iter().map(|w| score * w / tf).collect()
+    }
+
+    fn set_k1(&mut self, k1: f64) {
+        self.k1 = k1;
+    }
+
+    fn set_b(&mut self, b: f64) {
+        self.b = b;
+    }
+}
+
+// Dirichlet is a language model with Dirichlet smoothing.  Scores below zero
+// are clamped, as they would rank a match below no match.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dirichlet {
+    pub mu: f64,
+}
+
+impl Default for Dirichlet {
+    fn default() -> Self {
+        Dirichlet { mu: 2000.0 }
+    }
+}
+
+impl Similarity for Dirichlet {
+    fn score(&self, stats: &TermStats) -> f64 {
+        if stats.collection_length == 0 {
+            return 0.0;
+        }
+        let p = stats.collection_term_freq.max(1) as f64 / stats.collection_length as f64;
+        let score = (1.0 + stats.term_freq as f64 / (self.mu * p)).ln()
+            + (self.mu / (stats.field_length as f64 + self.mu)).ln();
+        score.max(0.0) * stats.boost
+    }
+}
@@ -0,0 +1,182 @@
+use crate::binary::{invalid_data, try_read_varint, write_varint};
+use crate::document::Document;
+use crate::field::{Field, FieldValue};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read, Write};
+
+const TAG_U64: u8 = 0;
+const TAG_I64: u8 = 1;
+const TAG_TEXT: u8 = 2;
+const TAG_ARRAY: u8 = 3;
+const TAG_OBJECT: u8 = 4;
+
+// MAX_DEPTH bounds the nesting of arrays and objects read back, so corrupted
+// data cannot exhaust the stack
+const MAX_DEPTH: usize = 128;
+
+pub(crate) fn write_str(buf: &mut Vec<u8>, s: &str) {
+    write_varint(buf, s.len() as u64);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+pub(crate) fn read_str(data: &[u8], pos: &mut usize) -> io::Result<String> {
+    let len = try_read_varint(data, pos)? as usize;
+    let bytes = data
+        .get(*pos..)
+        .and_then(|rest| rest.get(..len))
+        .ok_or_else(|| invalid_data("truncated string"))?;
+    *pos += len;
+    Ok(String::from_utf8_lossy(bytes).into_owned())
+}
+
+pub(crate) fn write_value(buf: &mut Vec<u8>, value: &FieldValue) {
+    match value {
+        FieldValue::U64(v) => {
+            buf.push(TAG_U64);
+            write_varint(buf, *v);
+        }
+        FieldValue::I64(v) => {
+            buf.push(TAG_I64);
+            write_varint(buf, ((v << 1) ^ (v >> 63)) as u64);
+        }
+        FieldValue::Text(v) => {
+            buf.push(TAG_TEXT);
+            write_str(buf, v);
+        }
+        FieldValue::Array(values) => {
+            buf.push(TAG_ARRAY);
+            write_varint(buf, values.len() as u64);
+            for v in values {
+                write_value(buf, v);
+            }
+        }
+        FieldValue::Object(values) => {
+            buf.push(TAG_OBJECT);
+            write_varint(buf, values.len() as u64);
+            for (k, v) in values {
+                write_str(buf, k);
+                write_value(buf, v);
+            }
+        }
+    }
+}
+
+pub(crate) fn read_value(data: &[u8], pos: &mut usize) -> io::Result<FieldValue> {
+    read_nested_value(data, pos, 0)
+}
+
+fn read_nested_value(data: &[u8], pos: &mut usize, depth: usize) -> io::Result<FieldValue> {
+    if depth > MAX_DEPTH {
+        return Err(invalid_data("values nested too deeply"));
+    }
+    let tag = *data
+        .get(*pos)
+        .ok_or_else(|| invalid_data("truncated value"))?;
+    *pos += 1;
+    let value = match tag {
+        TAG_U64 => FieldValue::U64(try_read_varint(data, pos)?),
+        TAG_I64 => {
+            let v = try_read_varint(data, pos)?;
+            FieldValue::I64(((v >> 1) as i64) ^ -((v & 1) as i64))
+        }
+        TAG_TEXT => FieldValue::Text(read_str(data, pos)?),
+        TAG_ARRAY => {
+            let len = try_read_varint(data, pos)?;
+            let mut values = Vec::new();
+            for _ in 0..len {
+                values.push(read_nested_value(data, pos, depth + 1)?);
+            }
+            FieldValue::Array(values)
+        }
+        TAG_OBJECT => {
+            let len = try_read_varint(data, pos)?;
+            let mut values = BTreeMap::new();
+            for _ in 0..len {
+                let k = read_str(data, pos)?;
+                values.insert(k, read_nested_value(data, pos, depth + 1)?);
+            }
+            FieldValue::Object(values)
+        }
+        _ => return Err(invalid_data("unknown value tag")),
+    };
+    Ok(value)
+}
+
+// DocumentStore keeps the stored fields of each document, compressed with
+// deflate, so that the original documents can be returned with results.
+#[derive(Clone, Default)]
+pub struct DocumentStore {
+    documents: HashMap<String, Vec<u8>>,
+}
+
+impl DocumentStore {
+    pub fn new() -> DocumentStore {
+        DocumentStore {
+            documents: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, doc_ref: &str, fields: &[Field]) {
+        let mut buf: Vec<u8> = Vec::new();
+        write_varint(&mut buf, fields.len() as u64);
+        for field in fields {
+            write_str(&mut buf, field.name());
+            write_value(&mut buf, field.value());
+        }
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&buf).unwrap();
+        self.documents
+            .insert(doc_ref.to_string(), encoder.finish().unwrap());
+    }
+
+    pub fn remove(&mut self, doc_ref: &str) -> bool {
+        self.documents.remove(doc_ref).is_some()
+    }
+
+    pub fn contains(&self, doc_ref: &str) -> bool {
+        self.documents.contains_key(doc_ref)
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    pub fn get(&self, doc_ref: &str) -> Option<Document> {
+        let compressed = self.documents.get(doc_ref)?;
+        decode(doc_ref, compressed).ok()
+    }
+
+    // compressed returns the compressed fields of the document as stored
+    pub(crate) fn compressed(&self, doc_ref: &str) -> Option<&[u8]> {
+        self.documents.get(doc_ref).map(|c| c.as_slice())
+    }
+
+    pub(crate) fn insert_compressed(&mut self, doc_ref: &str, compressed: Vec<u8>) {
+        self.documents.insert(doc_ref.to_string(), compressed);
+    }
+}
+
+// decode inflates the stored fields of a document, failing if they are
+// corrupted
+pub(crate) fn decode(doc_ref: &str, compressed: &[u8]) -> io::Result<Document> {
+    let mut data: Vec<u8> = Vec::new();
+    DeflateDecoder::new(compressed).read_to_end(&mut data)?;
+
+    let mut doc = Document::new(doc_ref.to_string());
+    let mut pos = 0;
+    let len = try_read_varint(&data, &mut pos)?;
+    for _ in 0..len {
+        let name = read_str(&data, &mut pos)?;
+        let value = read_value(&data, &mut pos)?;
+        doc.add_field(Field::new(name, value));
+    }
+    Ok(doc)
+}
@@ -1,301 +1,602 @@
-use std::cell::RefCell;
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::ops::{Bound, RangeBounds};
 
+use crate::field::FieldValue;
 use crate::query::Clause;
+use crate::regex::Regex;
 
+// Token is a term of a document.  The metadata holds arbitrary data about the
+// token, such as its "position" and "index" set by the tokenizer, and pipeline
+// functions may add their own keys.  Keys whitelisted in the builder are
+// recorded in the index for each occurrence of the token.
 #[derive(Eq, PartialEq, Clone)]
 pub struct Token {
     pub index: usize,
     pub start: usize,
     pub value: String,
+    pub metadata: HashMap<String, FieldValue>,
 }
 
 impl Token {
     pub fn value(&self) -> &str {
         &self.value
     }
+
+    pub fn metadata(&self, key: &str) -> Option<&FieldValue> {
+        self.metadata.get(key)
+    }
+
+    pub fn set_metadata(&mut self, key: &str, value: FieldValue) {
+        self.metadata.insert(key.to_string(), value);
+    }
 }
 
-pub struct TokenSet {
-    root: Rc<RefCell<TokenSetNode>>,
+// FuzzyOptions configures fuzzy matching: how many edits a matching term may
+// be away from the source, which edits count, and the length of a prefix
+// that has to match exactly.  A transposition swaps two adjacent characters
+// for one edit, so "recieve" is one edit away from "receive".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FuzzyOptions {
+    pub edit_distance: u64,
+    pub prefix_length: usize,
+    pub insertions: bool,
+    pub deletions: bool,
+    pub substitutions: bool,
+    pub transpositions: bool,
+}
+
+impl Default for FuzzyOptions {
+    fn default() -> Self {
+        FuzzyOptions {
+            edit_distance: 1,
+            prefix_length: 0,
+            insertions: true,
+            deletions: true,
+            substitutions: true,
+            transpositions: true,
+        }
+    }
 }
 
-// id is a unique id for a TokenSetNode
-static mut GLOBAL_ID: usize = 0;
+impl FuzzyOptions {
+    pub fn new(edit_distance: u64) -> FuzzyOptions {
+        FuzzyOptions {
+            edit_distance,
+            ..FuzzyOptions::default()
+        }
+    }
 
-#[derive(Debug)]
-pub struct TokenSetNode {
+    // distance returns the fewest allowed edits turning source into term,
+    // or None if the prefixes differ or it takes more than edit_distance.
+    // Each character is edited at most once, as in the optimal string
+    // alignment distance.
+    pub fn distance(&self, source: &str, term: &str) -> Option<u64> {
+        let a: Vec<char> = source.chars().collect();
+        let b: Vec<char> = term.chars().collect();
+        let prefix_length = self.prefix_length.min(a.len());
+        if b.len() < prefix_length || a[..prefix_length] != b[..prefix_length] {
+            return None;
+        }
+        let (a, b) = (&a[prefix_length..], &b[prefix_length..]);
+
+        // u64::MAX marks alignments the allowed edits cannot reach
+        let mut d = vec![vec![u64::MAX; b.len() + 1]; a.len() + 1];
+        d[0][0] = 0;
+        for i in 0..=a.len() {
+            for j in 0..=b.len() {
+                let mut best = d[i][j];
+                if i > 0 && j > 0 {
+                    if a[i - 1] == b[j - 1] {
+                        best = best.min(d[i - 1][j - 1]);
+                    } else if self.substitutions {
+                        best = best.min(d[i - 1][j - 1].saturating_add(1));
+                    }
+                }
+                if i > 0 && self.deletions {
+                    best = best.min(d[i - 1][j].saturating_add(1));
+                }
+                if j > 0 && self.insertions {
+                    best = best.min(d[i][j - 1].saturating_add(1));
+                }
+                if self.transpositions
+                    && i > 1
+                    && j > 1
+                    && a[i - 1] == b[j - 2]
+                    && a[i - 2] == b[j - 1]
+                {
+                    best = best.min(d[i - 2][j - 2].saturating_add(1));
+                }
+                d[i][j] = best;
+            }
+        }
+        Some(d[a.len()][b.len()]).filter(|distance| *distance <= self.edit_distance)
+    }
+
+    // rank keeps the terms within the edit distance of source and returns
+    // at most limit of them, the closest first and, among equally close
+    // terms, the most frequent first
+    pub(crate) fn rank<I>(&self, source: &str, terms: I, limit: usize) -> Vec<String>
+    where
+        I: IntoIterator<Item = (String, usize)>,
+    {
+        let mut ranked: Vec<(u64, usize, String)> = terms
+            .into_iter()
+            .filter_map(|(term, frequency)| {
+                self.distance(source, &term)
+                    .map(|distance| (distance, frequency, term))
+            })
+            .collect();
+        ranked.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)).then(a.2.cmp(&b.2)));
+        ranked.truncate(limit);
+        ranked.into_iter().map(|(_, _, term)| term).collect()
+    }
+}
+
+// TokenSet is an automaton of terms, stored as a flat arena of nodes with the
+// root at 0.  Each node keeps its edges sorted by character, pointing to
+// other nodes of the same set, so a built set is plain data that can be read
+// from many threads at once.
+#[derive(Clone, Debug)]
+pub struct TokenSet {
+    nodes: Vec<TokenSetNode>,
+}
+
+// TokenSetNode is a node of the arena.  Equal nodes accept the same suffixes
+// once their children are minimized, so a node is its own key when
+// minimizing.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+struct TokenSetNode {
     last: bool,
-    edges: HashMap<char, Rc<RefCell<TokenSetNode>>>,
-    id: usize,
+    edges: Vec<(char, usize)>, // sorted by char
 }
 
 impl TokenSet {
+    fn new() -> TokenSet {
+        TokenSet {
+            nodes: vec![TokenSetNode::default()],
+        }
+    }
+
+    fn push(&mut self, last: bool) -> usize {
+        self.nodes.push(TokenSetNode {
+            last,
+            edges: Vec::new(),
+        });
+        self.nodes.len() - 1
+    }
+
+    fn edge(&self, node: usize, c: char) -> Option<usize> {
+        let edges = &self.nodes[node].edges;
+        edges
+            .binary_search_by_key(&c, |(k, _)| *k)
+            .ok()
+            .map(|i| edges[i].1)
+    }
+
+    fn set_edge(&mut self, node: usize, c: char, child: usize) {
+        let edges = &mut self.nodes[node].edges;
+        match edges.binary_search_by_key(&c, |(k, _)| *k) {
+            Ok(i) => edges[i].1 = child,
+            Err(i) => edges.insert(i, (c, child)),
+        }
+    }
+
+    // child returns the node the edge leads to, adding the edge to a new node
+    // if there is none
+    fn child(&mut self, node: usize, c: char) -> usize {
+        match self.edge(node, c) {
+            Some(child) => child,
+            None => {
+                let child = self.push(false);
+                self.set_edge(node, c, child);
+                child
+            }
+        }
+    }
+
     pub fn from_array(tokens: &Vec<String>) -> TokenSet {
         let mut builder = TokenSetBuilder::new();
         for token in tokens {
-            builder.insert(&token);
+            builder.insert(token);
         }
-        builder.finish();
-        TokenSet { root: builder.root }
+        builder.finish()
     }
 
     pub fn from_string(source: &str) -> TokenSet {
-        let mut root = Rc::new(RefCell::new(TokenSetNode::new()));
-        let mut node = Rc::clone(&mut root);
-
+        let mut set = TokenSet::new();
+        let mut node = 0;
+        let len = source.chars().count();
         for (i, c) in source.chars().enumerate() {
-            let last = i == source.len() - 1;
+            let last = i == len - 1;
             if c == '*' {
-                let mut r = node.borrow_mut();
-                r.edges.insert(c, Rc::clone(&node));
-                r.last = last;
+                set.set_edge(node, c, node);
+                set.nodes[node].last = last;
             } else {
-                let mut n = TokenSetNode::new();
-                n.last = last;
-                let next = Rc::new(RefCell::new(n));
-                node.borrow_mut().edges.insert(c, Rc::clone(&next));
-                node = next
+                let next = set.push(last);
+                set.set_edge(node, c, next);
+                node = next;
             }
         }
-        TokenSet { root }
+        set
     }
 
     pub fn from_fuzzy_string(source: &str, edit_distance: u64) -> TokenSet {
+        TokenSet::from_fuzzy(source, &FuzzyOptions::new(edit_distance))
+    }
+
+    // from_fuzzy builds a set accepting every term within the edit distance
+    // of source with the allowed edits.  Edits share wildcard nodes, so the
+    // set accepts some terms further away as well, which
+    // FuzzyOptions::distance rules out.
+    pub fn from_fuzzy(source: &str, options: &FuzzyOptions) -> TokenSet {
         struct Frame {
-            node: Rc<RefCell<TokenSetNode>>,
+            node: usize,
             edits_remaining: u64,
-            source: String,
+            source: Vec<char>,
+        }
+        let chars: Vec<char> = source.chars().collect();
+        let prefix_length = options.prefix_length.min(chars.len());
+        let mut set = TokenSet::new();
+        let mut node = 0;
+        for c in chars[..prefix_length].iter() {
+            node = set.child(node, *c);
+        }
+        if prefix_length > 0 && prefix_length == chars.len() {
+            set.nodes[node].last = true;
         }
-        let root = Rc::new(RefCell::new(TokenSetNode::new()));
         let mut stack: Vec<Frame> = vec![Frame {
-            node: Rc::clone(&root),
-            edits_remaining: edit_distance,
-            source: source.to_string(),
+            node,
+            edits_remaining: options.edit_distance,
+            source: chars[prefix_length..].to_vec(),
         }];
 
         while let Some(frame) = stack.pop() {
-            if let Some(c) = frame.source.chars().into_iter().next() {
-                let no_edit_node: Rc<RefCell<TokenSetNode>>;
-                if frame.node.borrow().edges.contains_key(&c) {
-                    no_edit_node = Rc::clone(frame.node.borrow().edges.get(&c).unwrap());
-                } else {
-                    no_edit_node = Rc::new(RefCell::new(TokenSetNode::new()));
-                    frame
-                        .node
-                        .borrow_mut()
-                        .edges
-                        .insert(c, Rc::clone(&no_edit_node));
-                }
+            if let Some(c) = frame.source.first() {
+                let no_edit_node = set.child(frame.node, *c);
                 if frame.source.len() == 1 {
-                    no_edit_node.borrow_mut().last = true;
+                    set.nodes[no_edit_node].last = true;
                 }
                 stack.push(Frame {
                     node: no_edit_node,
                     edits_remaining: frame.edits_remaining,
-                    source: frame.source[1..].to_string(),
+                    source: frame.source[1..].to_vec(),
                 });
             }
             if frame.edits_remaining == 0 {
                 continue;
             }
-            let insertion_node = if frame.node.borrow().edges.contains_key(&'*') {
-                Rc::clone(frame.node.borrow().edges.get(&'*').unwrap())
-            } else {
-                let n = Rc::new(RefCell::new(TokenSetNode::new()));
-                frame.node.borrow_mut().edges.insert('*', Rc::clone(&n));
-                n
-            };
-            if frame.source.len() == 0 {
-                insertion_node.borrow_mut().last = true;
-            }
-            stack.push(Frame {
-                node: insertion_node,
-                edits_remaining: frame.edits_remaining - 1,
-                source: frame.source.to_string(),
-            });
 
-            if frame.source.len() > 1 {
+            if options.insertions {
+                let insertion_node = set.child(frame.node, '*');
+                if frame.source.is_empty() {
+                    set.nodes[insertion_node].last = true;
+                }
                 stack.push(Frame {
-                    node: Rc::clone(&frame.node),
+                    node: insertion_node,
                     edits_remaining: frame.edits_remaining - 1,
-                    source: frame.source[1..].to_string(),
+                    source: frame.source.clone(),
                 });
             }
-            if frame.source.len() == 1 {
-                frame.node.borrow_mut().last = true;
+
+            if options.deletions {
+                if frame.source.len() > 1 {
+                    stack.push(Frame {
+                        node: frame.node,
+                        edits_remaining: frame.edits_remaining - 1,
+                        source: frame.source[1..].to_vec(),
+                    });
+                }
+                if frame.source.len() == 1 {
+                    set.nodes[frame.node].last = true;
+                }
             }
 
-            if frame.source.len() >= 1 {
-                let substitution_node = if frame.node.borrow().edges.contains_key(&'*') {
-                    Rc::clone(frame.node.borrow().edges.get(&'*').unwrap())
-                } else {
-                    let n = Rc::new(RefCell::new(TokenSetNode::new()));
-                    frame.node.borrow_mut().edges.insert('*', Rc::clone(&n));
-                    n
-                };
+            if options.substitutions && !frame.source.is_empty() {
+                let substitution_node = set.child(frame.node, '*');
                 if frame.source.len() == 1 {
-                    substitution_node.borrow_mut().last = true
+                    set.nodes[substitution_node].last = true;
                 }
                 stack.push(Frame {
                     node: substitution_node,
                     edits_remaining: frame.edits_remaining - 1,
-                    source: frame.source[1..].to_string(),
-                })
+                    source: frame.source[1..].to_vec(),
+                });
             }
 
-            if frame.source.len() > 1 {
-                let mut chars = frame.source.chars().into_iter();
-                let c1 = chars.next().unwrap();
-                let c2 = chars.next().unwrap();
-                let transpose_node = if frame.node.borrow().edges.contains_key(&c2) {
-                    Rc::clone(frame.node.borrow().edges.get(&c2).unwrap())
-                } else {
-                    let n = Rc::new(RefCell::new(TokenSetNode::new()));
-                    frame.node.borrow_mut().edges.insert(c2, Rc::clone(&n));
-                    n
-                };
-                if frame.source.len() == 1 {
-                    transpose_node.borrow_mut().last = true;
-                }
+            if options.transpositions && frame.source.len() > 1 {
+                let transpose_node = set.child(frame.node, frame.source[1]);
+                let mut source = vec![frame.source[0]];
+                source.extend_from_slice(&frame.source[2..]);
                 stack.push(Frame {
-                    node: Rc::clone(&transpose_node),
+                    node: transpose_node,
                     edits_remaining: frame.edits_remaining - 1,
-                    source: (c1.to_string() + &frame.source[2..]).to_string(),
-                })
+                    source,
+                });
             }
         }
-
-        TokenSet { root }
+        set
     }
 
     pub fn from_clause(clause: &Clause) -> TokenSet {
-        TokenSet::from_string(&clause.term)
+        match &clause.fuzzy {
+            Some(options) => TokenSet::from_fuzzy(&clause.term, options),
+            None => TokenSet::from_string(&clause.term),
+        }
     }
 
     pub fn intersect(&self, b: &Self) -> TokenSet {
-        struct Frame {
-            q_node: Rc<RefCell<TokenSetNode>>,
-            output: Rc<RefCell<TokenSetNode>>,
-            node: Rc<RefCell<TokenSetNode>>,
+        let mut output = TokenSet::new();
+        // (node of b, node of output, node of self)
+        let mut stack: Vec<(usize, usize, usize)> = vec![(0, 0, 0)];
+
+        while let Some((q_node, out, node)) = stack.pop() {
+            for (q_key, q_next) in b.nodes[q_node].edges.iter() {
+                let matches: Vec<(char, usize)> = if *q_key == '*' {
+                    self.nodes[node].edges.clone()
+                } else {
+                    self.edge(node, *q_key)
+                        .map(|n_next| vec![(*q_key, n_next)])
+                        .unwrap_or_default()
+                };
+                for (n_key, n_next) in matches {
+                    let last = self.nodes[n_next].last && b.nodes[*q_next].last;
+                    let next = match output.edge(out, n_key) {
+                        Some(next) => {
+                            output.nodes[next].last |= last;
+                            next
+                        }
+                        None => {
+                            let next = output.push(last);
+                            output.set_edge(out, n_key, next);
+                            next
+                        }
+                    };
+                    stack.push((*q_next, next, n_next));
+                }
+            }
         }
+        output
+    }
 
-        let output = Rc::new(RefCell::new(TokenSetNode::new()));
-        let mut stack: Vec<Frame> = vec![Frame {
-            q_node: Rc::clone(&b.root),
-            output: Rc::clone(&output),
-            node: Rc::clone(&self.root),
-        }];
+    pub fn to_vec(&self) -> Vec<String> {
+        self.iter().collect()
+    }
 
-        while let Some(frame) = stack.pop() {
-            for q_key in frame.q_node.borrow().edges.keys() {
-                for n_key in frame.node.borrow().edges.keys() {
-                    if *n_key != *q_key && *q_key != '*' {
-                        continue;
+    // iter returns the terms of the set in lexicographic order, found as the
+    // iterator advances
+    pub fn iter(&self) -> TokenSetIter<'_> {
+        self.range::<std::ops::RangeFull>(..)
+    }
+
+    // range returns the terms of the set within the bounds in lexicographic
+    // order.  Subtrees whose terms all fall below the lower bound are
+    // skipped, and the iteration stops at the first term past the upper one.
+    pub fn range<'b, R: RangeBounds<&'b str>>(&self, range: R) -> TokenSetIter<'_> {
+        let to_owned = |bound: Bound<&&str>| match bound {
+            Bound::Included(s) => Bound::Included(s.to_string()),
+            Bound::Excluded(s) => Bound::Excluded(s.to_string()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        TokenSetIter {
+            set: self,
+            stack: vec![(String::new(), 0)],
+            lower: to_owned(range.start_bound()),
+            upper: to_owned(range.end_bound()),
+        }
+    }
+
+    // union returns the set of the terms in either set
+    pub fn union(&self, other: &Self) -> TokenSet {
+        self.combine(other, true, |a, b| a || b)
+    }
+
+    // difference returns the set of the terms in this set but not in the
+    // other.  Wildcard edges are compared as plain characters here.
+    pub fn difference(&self, other: &Self) -> TokenSet {
+        self.combine(other, false, |a, b| a && !b)
+    }
+
+    // combine walks both automata in lockstep, building a node for each pair
+    // of nodes reached by the same prefix.  Edges of the other set are only
+    // followed if other_edges is set, and last decides which nodes end a
+    // term.
+    fn combine<F>(&self, other: &Self, other_edges: bool, last: F) -> TokenSet
+    where
+        F: Fn(bool, bool) -> bool,
+    {
+        let is_last = |set: &TokenSet, node: Option<usize>| node.is_some_and(|n| set.nodes[n].last);
+        let mut output = TokenSet::new();
+        output.nodes[0].last = last(self.nodes[0].last, other.nodes[0].last);
+        let mut ids: HashMap<(Option<usize>, Option<usize>), usize> = HashMap::new();
+        ids.insert((Some(0), Some(0)), 0);
+        let mut stack: Vec<(Option<usize>, Option<usize>)> = vec![(Some(0), Some(0))];
+
+        while let Some((a, b)) = stack.pop() {
+            let out = ids[&(a, b)];
+            let mut keys: Vec<char> = Vec::new();
+            if let Some(a) = a {
+                keys.extend(self.nodes[a].edges.iter().map(|(c, _)| *c));
+            }
+            if let (Some(b), true) = (b, other_edges) {
+                keys.extend(other.nodes[b].edges.iter().map(|(c, _)| *c));
+            }
+            keys.sort_unstable();
+            keys.dedup();
+            for c in keys {
+                let next = (
+                    a.and_then(|a| self.edge(a, c)),
+                    b.and_then(|b| other.edge(b, c)),
+                );
+                let id = match ids.get(&next) {
+                    Some(id) => *id,
+                    None => {
+                        let id = output.push(last(is_last(self, next.0), is_last(other, next.1)));
+                        ids.insert(next, id);
+                        stack.push(next);
+                        id
                     }
-                    let node = Rc::clone(&frame.node.borrow().edges.get(&n_key).unwrap());
-                    let q_node = Rc::clone(&frame.q_node.borrow().edges.get(&q_key).unwrap());
-                    let last = node.borrow().last && q_node.borrow().last;
-                    let next = if frame.output.borrow().edges.contains_key(&n_key) {
-                        let next = Rc::clone(&frame.output.borrow().edges.get(&n_key).unwrap());
-                        let mut next_mut = next.borrow_mut();
-                        next_mut.last = next_mut.last || last;
-                        Rc::clone(&next)
-                    } else {
-                        let mut n = TokenSetNode::new();
-                        n.last = last;
-                        let next = Rc::new(RefCell::new(n));
-                        frame
-                            .output
-                            .borrow_mut()
-                            .edges
-                            .insert(*n_key, Rc::clone(&next));
-                        next
-                    };
-                    stack.push(Frame {
-                        q_node: Rc::clone(&q_node),
-                        output: next,
-                        node: Rc::clone(&node),
-                    });
-                }
+                };
+                output.set_edge(out, c, id);
             }
         }
-        TokenSet { root: output }
+        output.trim()
     }
 
-    pub fn to_vec(&self) -> Vec<String> {
-        struct Frame {
-            prefix: String,
-            node: Rc<RefCell<TokenSetNode>>,
+    // trim drops the nodes from which no term can be completed
+    fn trim(self) -> TokenSet {
+        let mut live: Vec<bool> = self.nodes.iter().map(|node| node.last).collect();
+        // children are mostly created after their parents, so walking the
+        // nodes backwards settles in one pass unless there are cycles
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (i, node) in self.nodes.iter().enumerate().rev() {
+                if !live[i] && node.edges.iter().any(|(_, child)| live[*child]) {
+                    live[i] = true;
+                    changed = true;
+                }
+            }
         }
-        let mut words: Vec<String> = Vec::new();
-        let mut stack: Vec<Frame> = vec![Frame {
-            prefix: "".into(),
-            node: Rc::clone(&self.root),
-        }];
+        self.compact(|node| live[node])
+    }
 
-        while let Some(frame) = stack.pop() {
-            let node = frame.node.borrow();
-            if node.last {
-                words.push(frame.prefix.to_string())
+    // compact copies the nodes reachable from the root through nodes that
+    // keep accepts into a new set, numbered breadth first
+    fn compact<F>(&self, keep: F) -> TokenSet
+    where
+        F: Fn(usize) -> bool,
+    {
+        let mut positions: HashMap<usize, usize> = HashMap::new();
+        positions.insert(0, 0);
+        let mut queue: Vec<usize> = vec![0];
+        let mut nodes: Vec<TokenSetNode> = Vec::new();
+        while nodes.len() < queue.len() {
+            let node = &self.nodes[queue[nodes.len()]];
+            let mut edges = Vec::with_capacity(node.edges.len());
+            for (c, child) in node.edges.iter().filter(|(_, child)| keep(*child)) {
+                let position = *positions.entry(*child).or_insert_with(|| {
+                    queue.push(*child);
+                    queue.len() - 1
+                });
+                edges.push((*c, position));
             }
+            nodes.push(TokenSetNode {
+                last: node.last,
+                edges,
+            });
+        }
+        TokenSet { nodes }
+    }
 
-            let edge_keys = node.edges.keys();
-            for edge_key in edge_keys {
-                let mut prefix = String::from(frame.prefix.to_string());
-                prefix.push(*edge_key);
-                stack.push(Frame {
-                    prefix,
-                    node: Rc::clone(frame.node.borrow().edges.get(edge_key).unwrap()),
-                })
+    // intersect_regex returns the first terms of the set in lexicographic
+    // order that the regex matches, at most limit of them.  Branches are
+    // left as soon as no term below them can match.
+    pub fn intersect_regex(&self, regex: &Regex, limit: usize) -> Vec<String> {
+        let mut words: Vec<String> = Vec::new();
+        let mut stack: Vec<(String, usize, Vec<usize>)> = vec![(String::new(), 0, regex.start())];
+        while let Some((prefix, node, states)) = stack.pop() {
+            if words.len() >= limit {
+                break;
+            }
+            let node = &self.nodes[node];
+            if node.last && regex.accepts(&states) {
+                words.push(prefix.to_string());
+            }
+            for (c, child) in node.edges.iter().rev() {
+                let next = regex.step(&states, *c);
+                if next.is_empty() {
+                    continue;
+                }
+                let mut prefix = prefix.to_string();
+                prefix.push(*c);
+                stack.push((prefix, *child, next));
             }
         }
         words
     }
+
+    // to_nodes returns the automaton as an array of (last, edges) with the
+    // root at 0.  Edges are sorted by character and point into the array.
+    pub fn to_nodes(&self) -> Vec<(bool, Vec<(char, usize)>)> {
+        self.nodes
+            .iter()
+            .map(|node| (node.last, node.edges.clone()))
+            .collect()
+    }
+}
+
+// TokenSetIter walks a token set depth first, in the order of the edges,
+// which yields the terms sorted
+pub struct TokenSetIter<'a> {
+    set: &'a TokenSet,
+    stack: Vec<(String, usize)>,
+    lower: Bound<String>,
+    upper: Bound<String>,
 }
 
-impl TokenSetNode {
-    pub fn new() -> TokenSetNode {
-        unsafe {
-            GLOBAL_ID += 1;
-            TokenSetNode {
-                edges: HashMap::new(),
-                last: false,
-                id: GLOBAL_ID,
+impl Iterator for TokenSetIter<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        while let Some((prefix, node)) = self.stack.pop() {
+            // every term below the node starts with the prefix, so none is
+            // within the range once the prefix is past the upper bound
+            let past_upper = match &self.upper {
+                Bound::Included(upper) => prefix.as_str() > upper.as_str(),
+                Bound::Excluded(upper) => prefix.as_str() >= upper.as_str(),
+                Bound::Unbounded => false,
+            };
+            if past_upper {
+                self.stack.clear();
+                return None;
             }
-        }
-    }
 
-    fn to_str(&self) -> String {
-        let mut id = if self.last { "1".into() } else { "0".into() };
-        for (c, child) in self.edges.iter() {
-            id = format!("{}{}{}", id, c, child.borrow().id);
+            let node = &self.set.nodes[node];
+            for (c, child) in node.edges.iter().rev() {
+                let mut next = prefix.to_string();
+                next.push(*c);
+                let below_lower = match &self.lower {
+                    Bound::Included(lower) | Bound::Excluded(lower) => {
+                        next.as_str() < lower.as_str() && !lower.starts_with(next.as_str())
+                    }
+                    Bound::Unbounded => false,
+                };
+                if !below_lower {
+                    self.stack.push((next, *child));
+                }
+            }
+
+            let above_lower = match &self.lower {
+                Bound::Included(lower) => prefix.as_str() >= lower.as_str(),
+                Bound::Excluded(lower) => prefix.as_str() > lower.as_str(),
+                Bound::Unbounded => true,
+            };
+            if node.last && above_lower {
+                return Some(prefix);
+            }
         }
-        id
+        None
     }
 }
 
-#[derive(Debug)]
-struct UncheckedNodes {
-    parent: Rc<RefCell<TokenSetNode>>,
+struct UncheckedNode {
+    parent: usize,
     c: char,
-    child: Rc<RefCell<TokenSetNode>>,
+    child: usize,
 }
 
 struct TokenSetBuilder {
     prev_word: String,
-    root: Rc<RefCell<TokenSetNode>>,
-    unchecked_nodes: Vec<UncheckedNodes>,
-    minimized_nodes: HashMap<String, Rc<RefCell<TokenSetNode>>>,
+    set: TokenSet,
+    unchecked_nodes: Vec<UncheckedNode>,
+    minimized_nodes: HashMap<TokenSetNode, usize>,
 }
 
 impl TokenSetBuilder {
     fn new() -> TokenSetBuilder {
-        let root = Rc::new(RefCell::new(TokenSetNode::new()));
         TokenSetBuilder {
             prev_word: "".into(),
-            root: root,
+            set: TokenSet::new(),
             unchecked_nodes: Vec::new(),
             minimized_nodes: HashMap::new(),
         }
@@ -306,162 +607,99 @@ impl TokenSetBuilder {
             panic!("Out of order word insertion")
         }
 
-        let mut common_prefix = 0;
-        let mut r1 = word.chars().into_iter();
-        let mut r2 = self.prev_word.chars().into_iter();
-        loop {
-            let n1 = r1.next();
-            let n2 = r2.next();
-            if n1.is_none() || n2.is_none() || n1.unwrap() != n2.unwrap() {
-                break;
-            }
-            common_prefix += 1;
-        }
+        let common_prefix = word
+            .chars()
+            .zip(self.prev_word.chars())
+            .take_while(|(c1, c2)| c1 == c2)
+            .count();
         self.minimize(common_prefix);
 
-        let mut node = if self.unchecked_nodes.is_empty() {
-            Rc::clone(&self.root)
-        } else {
-            Rc::clone(&self.unchecked_nodes.last().unwrap().child)
+        let mut node = match self.unchecked_nodes.last() {
+            Some(unchecked) => unchecked.child,
+            None => 0,
         };
-
-        for c in word[common_prefix..].chars() {
-            let next_node = Rc::new(RefCell::new(TokenSetNode::new()));
-            node.borrow_mut().edges.insert(c, Rc::clone(&next_node));
-
-            self.unchecked_nodes.push(UncheckedNodes {
+        for c in word.chars().skip(common_prefix) {
+            let next = self.set.push(false);
+            self.set.set_edge(node, c, next);
+            self.unchecked_nodes.push(UncheckedNode {
                 parent: node,
                 c,
-                child: Rc::clone(&next_node),
+                child: next,
             });
-            node = next_node;
+            node = next;
         }
 
-        node.borrow_mut().last = true;
+        self.set.nodes[node].last = true;
         self.prev_word = word.to_string();
     }
 
-    fn finish(&mut self) {
+    // finish minimizes the remaining nodes and drops the nodes that were
+    // replaced by an equal one, numbering the rest breadth first
+    fn finish(mut self) -> TokenSet {
         self.minimize(0);
+        self.set.compact(|_| true)
     }
 
     fn minimize(&mut self, down_to: usize) {
-        for i in (down_to..self.unchecked_nodes.len()).rev() {
-            let node = &self.unchecked_nodes.get(i).unwrap();
-            let child_id = node.child.borrow().to_str();
-            if self.minimized_nodes.contains_key(&child_id) {
-                node.parent.borrow_mut().edges.insert(
-                    node.c,
-                    Rc::clone(self.minimized_nodes.get(&child_id).unwrap()),
-                );
-            } else {
-                self.minimized_nodes
-                    .insert(child_id, Rc::clone(&node.child));
+        while self.unchecked_nodes.len() > down_to {
+            let unchecked = self.unchecked_nodes.pop().unwrap();
+            let child = &self.set.nodes[unchecked.child];
+            match self.minimized_nodes.get(child) {
+                Some(minimized) => self.set.set_edge(unchecked.parent, unchecked.c, *minimized),
+                None => {
+                    self.minimized_nodes.insert(child.clone(), unchecked.child);
+                }
             }
-            self.unchecked_nodes.pop();
         }
     }
 }
 
 #[test]
 fn test_from_string() {
-    let root = TokenSet::from_string("a".into()).root;
-    let a = Rc::clone(root.borrow().edges.get(&'a').unwrap());
-    assert!(a.borrow().last);
-
-    let root = TokenSet::from_string("a*".into()).root;
-    let a = Rc::clone(root.borrow().edges.get(&'a').unwrap());
-    assert!(a.borrow().last);
-    let wild = Rc::clone(a.borrow().edges.get(&'*').unwrap());
-    assert!(wild.borrow().last);
-    let wild2 = Rc::clone(wild.borrow().edges.get(&'*').unwrap());
-    assert_eq!(&wild.borrow().id, &wild2.borrow().id);
+    let set = TokenSet::from_string("a");
+    let a = set.edge(0, 'a').unwrap();
+    assert!(set.nodes[a].last);
+
+    let set = TokenSet::from_string("a*");
+    let a = set.edge(0, 'a').unwrap();
+    assert!(set.nodes[a].last);
+    assert_eq!(set.edge(a, '*'), Some(a));
 }
 
 #[test]
-fn test_to_str() {
-    let non_last = TokenSetNode::new();
-    let mut last = TokenSetNode::new();
-    let mut other_last = TokenSetNode::new();
-    last.last = true;
-    other_last.last = true;
-
-    assert_ne!(non_last.to_str(), last.to_str());
-    assert_eq!(last.to_str(), other_last.to_str());
-
-    let zero_edges = TokenSetNode::new();
-    let mut one_edge = TokenSetNode::new();
-    let mut two_edges = TokenSetNode::new();
-    one_edge.edges.insert(
-        'a',
-        Rc::new(RefCell::new(TokenSetNode {
-            edges: HashMap::new(),
-            id: 99999,
-            last: false,
-        })),
-    );
-    two_edges.edges.insert(
-        'a',
-        Rc::new(RefCell::new(TokenSetNode {
-            edges: HashMap::new(),
-            id: 99999,
-            last: false,
-        })),
-    );
-    two_edges.edges.insert(
-        'b',
-        Rc::new(RefCell::new(TokenSetNode {
-            edges: HashMap::new(),
-            id: 99999,
-            last: false,
-        })),
-    );
-
-    assert_ne!(zero_edges.to_str(), one_edge.to_str());
-    assert_ne!(two_edges.to_str(), one_edge.to_str());
-    assert_ne!(two_edges.to_str(), zero_edges.to_str());
-
-    let child_a = Rc::new(RefCell::new(TokenSetNode::new()));
-    let child_b = Rc::new(RefCell::new(TokenSetNode::new()));
-    let parent_a = Rc::new(RefCell::new(TokenSetNode::new()));
-    let parent_b = Rc::new(RefCell::new(TokenSetNode::new()));
-    let parent_c = Rc::new(RefCell::new(TokenSetNode::new()));
-
-    parent_a.borrow_mut().edges.insert('a', Rc::clone(&child_a));
-    parent_b.borrow_mut().edges.insert('a', Rc::clone(&child_b));
-    parent_c.borrow_mut().edges.insert('a', Rc::clone(&child_b));
-
-    assert_eq!(parent_b.borrow().to_str(), parent_c.borrow().to_str());
-    assert_ne!(parent_a.borrow().to_str(), parent_c.borrow().to_str());
-    assert_ne!(parent_a.borrow().to_str(), parent_b.borrow().to_str());
+fn test_node_key() {
+    let non_last = TokenSetNode::default();
+    let last = TokenSetNode {
+        last: true,
+        edges: Vec::new(),
+    };
+    assert_ne!(non_last, last);
+    assert_eq!(last, last.clone());
+
+    let one_edge = TokenSetNode {
+        last: false,
+        edges: vec![('a', 1)],
+    };
+    let two_edges = TokenSetNode {
+        last: false,
+        edges: vec![('a', 1), ('b', 1)],
+    };
+    let other_child = TokenSetNode {
+        last: false,
+        edges: vec![('a', 2)],
+    };
+    assert_ne!(non_last, one_edge);
+    assert_ne!(two_edges, one_edge);
+    assert_ne!(one_edge, other_child);
+    assert_eq!(one_edge, one_edge.clone());
 }
 
 #[test]
 fn test_from_array() {
     let s = TokenSet::from_array(&vec!["ac".into(), "dc".into()]);
-    let ac_node = s
-        .root
-        .borrow()
-        .edges
-        .get(&'a')
-        .unwrap()
-        .borrow()
-        .edges
-        .get(&'c')
-        .unwrap()
-        .borrow()
-        .id;
-    let dc_node = s
-        .root
-        .borrow()
-        .edges
-        .get(&'d')
-        .unwrap()
-        .borrow()
-        .edges
-        .get(&'c')
-        .unwrap()
-        .borrow()
-        .id;
-    assert_eq!(ac_node, dc_node);
+    let a = s.edge(0, 'a').unwrap();
+    let d = s.edge(0, 'd').unwrap();
+    assert_eq!(s.edge(a, 'c'), s.edge(d, 'c'));
+    assert_eq!(a, d);
+    assert_eq!(s.nodes.len(), 3);
 }
@@ -1,10 +1,35 @@
+use crate::field::FieldValue;
 use crate::token::Token;
+use std::collections::HashMap;
+use std::sync::Arc;
 
-pub struct Tokenizer;
+type Separator = dyn Fn(char) -> bool + Send + Sync;
+
+#[derive(Clone)]
+pub struct Tokenizer {
+    separator: Arc<Separator>,
+}
+
+impl Default for Tokenizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Tokenizer {
     pub fn new() -> Tokenizer {
-        Tokenizer
+        Tokenizer::with_separator(|c| c.is_whitespace() || c == '-')
+    }
+
+    // with_separator creates a tokenizer splitting on characters for which the
+    // separator returns true
+    pub fn with_separator<T>(separator: T) -> Tokenizer
+    where
+        T: Fn(char) -> bool + Send + Sync + 'static,
+    {
+        Tokenizer {
+            separator: Arc::new(separator),
+        }
     }
 
     pub fn tokenize(&self, source: &str) -> Vec<Token> {
@@ -15,13 +40,23 @@ impl Tokenizer {
         let chars: Vec<char> = source.chars().collect();
         let len = source.len();
         for end in 0..len + 1 {
-            if end == len || chars[end].is_whitespace() || chars[end] == '-' {
+            if end == len || (self.separator)(chars[end]) {
                 if end - start > 0 {
                     let value = &source[start..end];
+                    let mut metadata = HashMap::new();
+                    metadata.insert(
+                        "position".to_string(),
+                        FieldValue::Array(vec![
+                            FieldValue::U64(start as u64),
+                            FieldValue::U64((end - start) as u64),
+                        ]),
+                    );
+                    metadata.insert("index".to_string(), FieldValue::U64(tokens.len() as u64));
                     let token = Token {
                         index: tokens.len(),
                         start,
                         value: value.to_string(),
+                        metadata,
                     };
                     tokens.push(token)
                 }
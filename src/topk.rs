@@ -0,0 +1,193 @@
+use crate::document::DocId;
+use crate::index::{plan, sort_results, Index, IndexReader, MatchData, MatchResult, Metadata};
+use crate::postings::PostingsIter;
+use crate::query::Query;
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+
+// Cursor walks the postings of one (term, field) pair of the query, bounding
+// what the pair can add to the score of any document
+struct Cursor<'a> {
+    postings: PostingsIter<'a>,
+    doc_id: Option<DocId>,
+    field_name: &'a str,
+    bound: f64,
+}
+
+// Candidate is a scored document; candidates order by score so the heap of
+// the best k can drop the worst one
+struct Candidate {
+    score: f64,
+    doc_id: DocId,
+    field_names: Vec<String>,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then_with(|| other.doc_id.cmp(&self.doc_id))
+    }
+}
+
+impl Index {
+    // query_top_k returns the k best scoring results of the query, ordered
+    // the way Index::query orders them. Documents are scored one at a time
+    // with WAND: a document is only scored when the max weights of the terms
+    // that can match it add up to more than the kth best score so far, and
+    // the postings of the other terms skip ahead to it.
+    pub fn query_top_k(&self, query: &Query, k: usize) -> Vec<MatchResult> {
+        if k == 0 {
+            return Vec::new();
+        }
+        if query.is_negated() {
+            // the best k are kept as the heap below keeps them: by score,
+            // then by lower doc id
+            let mut results = self.query(query);
+            results.sort_by(|a, b| {
+                b.score().total_cmp(&a.score()).then_with(|| {
+                    let doc_id = |r: &MatchResult| self.doc_refs().id(r.doc_ref());
+                    doc_id(a).cmp(&doc_id(b))
+                })
+            });
+            results.truncate(k);
+            sort_results(&mut results);
+            return results;
+        }
+
+        let plan = plan(self, query);
+        let mut seen: HashSet<(u64, &str)> = HashSet::new();
+        let mut cursors: Vec<Cursor> = Vec::new();
+        for (term, index, field_name) in &plan.terms {
+            if !seen.insert((*index, field_name)) {
+                continue;
+            }
+            let mut postings = match self.postings(term, field_name) {
+                Some(postings) => postings,
+                None => continue,
+            };
+            let query_vector = &plan.query_vectors[field_name];
+            let magnitude = query_vector.magnitude();
+            if magnitude == 0.0 {
+                continue;
+            }
+            let boost = query_vector.get(*index as usize).unwrap_or(0.0);
+            let max_weight = self.max_weights(field_name, *index as usize).unwrap_or(0.0);
+            cursors.push(Cursor {
+                doc_id: postings.next(),
+                postings,
+                field_name,
+                bound: (boost * max_weight / magnitude).max(0.0),
+            });
+        }
+
+        let mut heap: BinaryHeap<Reverse<Candidate>> = BinaryHeap::with_capacity(k + 1);
+        loop {
+            cursors.retain(|c| c.doc_id.is_some());
+            if cursors.is_empty() {
+                break;
+            }
+            cursors.sort_by_key(|c| c.doc_id);
+
+            // the pivot is the first document whose terms could lift it into
+            // the top k; every document before it is skipped
+            let threshold = match heap.peek() {
+                Some(Reverse(worst)) if heap.len() == k => Some(worst.score),
+                _ => None,
+            };
+            let mut upper_bound = 0.0;
+            let pivot = cursors.iter().position(|c| {
+                upper_bound += c.bound;
+                threshold.is_none_or(|threshold| upper_bound > threshold)
+            });
+            let pivot_doc = match pivot {
+                Some(pivot) => cursors[pivot].doc_id.unwrap(),
+                None => break,
+            };
+
+            if cursors[0].doc_id != Some(pivot_doc) {
+                for cursor in cursors.iter_mut() {
+                    if cursor.doc_id >= Some(pivot_doc) {
+                        break;
+                    }
+                    cursor.doc_id = cursor.postings.advance(pivot_doc);
+                }
+                continue;
+            }
+
+            let mut field_names: Vec<String> = Vec::new();
+            for cursor in cursors.iter_mut() {
+                if cursor.doc_id != Some(pivot_doc) {
+                    break;
+                }
+                if !field_names.iter().any(|f| f == cursor.field_name) {
+                    field_names.push(cursor.field_name.to_string());
+                }
+                cursor.doc_id = cursor.postings.next();
+            }
+            if !plan.accepts(pivot_doc) {
+                continue;
+            }
+
+            let mut score = 0.0;
+            for field_name in &field_names {
+                if let Some(field_vector) = IndexReader::field_vector(self, pivot_doc, field_name) {
+                    score += plan.query_vectors[field_name.as_str()].score(&field_vector);
+                }
+            }
+            if threshold.is_none_or(|threshold| score > threshold) {
+                heap.push(Reverse(Candidate {
+                    score,
+                    doc_id: pivot_doc,
+                    field_names,
+                }));
+                if heap.len() > k {
+                    heap.pop();
+                }
+            }
+        }
+
+        let no_metadata = Metadata::new();
+        let mut results: Vec<MatchResult> = Vec::with_capacity(heap.len());
+        for Reverse(candidate) in heap {
+            let mut match_data = MatchData::new();
+            for (term, _, field_name) in &plan.terms {
+                if !candidate.field_names.iter().any(|f| f == field_name) {
+                    continue;
+                }
+                let ri = &self.inverted_index()[term];
+                let matched = ri
+                    .documents
+                    .get(*field_name)
+                    .is_some_and(|postings| postings.contains(candidate.doc_id));
+                if matched {
+                    let metadata = ri
+                        .get_metadata(field_name, candidate.doc_id)
+                        .unwrap_or(&no_metadata);
+                    match_data.add(term.to_string(), field_name.to_string(), metadata);
+                }
+            }
+            let doc_ref = self.doc_refs().doc_ref(candidate.doc_id).to_string();
+            let mut result = MatchResult::new(doc_ref, candidate.score);
+            result.set_match_data(match_data);
+            results.push(result);
+        }
+        sort_results(&mut results);
+        results
+    }
+}
@@ -1,18 +1,35 @@
-#[derive(Clone)]
+use std::sync::OnceLock;
+
+#[derive(Clone, Debug)]
 struct Element {
     index: usize,
     value: f64,
 }
 
-#[derive(Clone)]
+// Vector is a sparse vector of term weights, its elements sorted by index.
+// The magnitude is computed once and kept until the vector changes, so a
+// field vector scored by many queries computes it only once.
+#[derive(Clone, Debug)]
 pub struct Vector {
     elements: Vec<Element>,
+    magnitude: OnceLock<f64>,
+}
+
+impl Default for Vector {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Vector {
     pub fn new() -> Vector {
+        Vector::from_elements(Vec::new())
+    }
+
+    fn from_elements(elements: Vec<Element>) -> Vector {
         Vector {
-            elements: Vec::new(),
+            elements,
+            magnitude: OnceLock::new(),
         }
     }
 
@@ -21,15 +38,61 @@ impl Vector {
         for (i, v) in values.into_iter().enumerate() {
             eles.push(Element { index: i, value: v })
         }
-        Vector { elements: eles }
+        Vector::from_elements(eles)
     }
 
-    pub fn magnitude(&self) -> f64 {
-        let mut sum = 0.0;
+    // from_flat_vec creates a vector from [index, value, index, value, ...].
+    // Pairs out of index order are sorted, and of pairs with the same index
+    // the last one is kept.
+    pub fn from_flat_vec(values: Vec<f64>) -> Vector {
+        let mut eles: Vec<Element> = Vec::with_capacity(values.len() / 2);
+        for pair in values.chunks(2) {
+            if pair.len() == 2 {
+                eles.push(Element {
+                    index: pair[0] as usize,
+                    value: pair[1],
+                })
+            }
+        }
+        if eles.windows(2).any(|w| w[0].index >= w[1].index) {
+            eles.reverse();
+            eles.sort_by_key(|e| e.index);
+            eles.dedup_by_key(|e| e.index);
+        }
+        Vector::from_elements(eles)
+    }
+
+    // to_flat_vec returns elements as [index, value, index, value, ...]
+    pub fn to_flat_vec(&self) -> Vec<f64> {
+        let mut flat = Vec::with_capacity(self.elements.len() * 2);
         for e in self.elements.iter() {
-            sum += e.value * e.value;
+            flat.push(e.index as f64);
+            flat.push(e.value);
         }
-        return sum.sqrt();
+        flat
+    }
+
+    // iter returns the (index, value) pairs of the vector in index order
+    pub fn iter(&self) -> impl Iterator<Item = (usize, f64)> + '_ {
+        self.elements.iter().map(|e| (e.index, e.value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn magnitude(&self) -> f64 {
+        *self.magnitude.get_or_init(|| {
+            let mut sum = 0.0;
+            for e in self.elements.iter() {
+                sum += e.value * e.value;
+            }
+            sum.sqrt()
+        })
     }
 
     pub fn dot(&self, other: &Self) -> f64 {
@@ -40,7 +103,7 @@ impl Vector {
         let mut product = 0.0;
         while i < alen && j < blen {
             let aidx = self.elements[i].index;
-            let bidx = other.elements[i].index;
+            let bidx = other.elements[j].index;
             if aidx < bidx {
                 i += 1;
             } else if aidx > bidx {
@@ -54,31 +117,92 @@ impl Vector {
         product
     }
 
-    pub fn get(&self, index: usize) -> Option<f64> {
-        self.elements
+    // add returns the sum of both vectors
+    pub fn add(&self, other: &Self) -> Vector {
+        let alen = self.elements.len();
+        let blen = other.elements.len();
+        let mut i = 0;
+        let mut j = 0;
+        let mut eles = Vec::with_capacity(alen.max(blen));
+        while i < alen || j < blen {
+            let aidx = self.elements.get(i).map_or(usize::MAX, |e| e.index);
+            let bidx = other.elements.get(j).map_or(usize::MAX, |e| e.index);
+            if aidx < bidx {
+                eles.push(self.elements[i].clone());
+                i += 1;
+            } else if aidx > bidx {
+                eles.push(other.elements[j].clone());
+                j += 1;
+            } else {
+                eles.push(Element {
+                    index: aidx,
+                    value: self.elements[i].value + other.elements[j].value,
+                });
+                i += 1;
+                j += 1;
+            }
+        }
+        Vector::from_elements(eles)
+    }
+
+    // scale returns the vector with every element multiplied by factor
+    pub fn scale(&self, factor: f64) -> Vector {
+        let eles = self
+            .elements
             .iter()
-            .find(|&e| e.index == index)
-            .map(|e| e.value)
+            .map(|e| Element {
+                index: e.index,
+                value: e.value * factor,
+            })
+            .collect();
+        Vector::from_elements(eles)
+    }
+
+    fn position(&self, index: usize) -> Result<usize, usize> {
+        self.elements.binary_search_by_key(&index, |e| e.index)
+    }
+
+    pub fn get(&self, index: usize) -> Option<f64> {
+        self.position(index)
+            .ok()
+            .map(|pos| self.elements[pos].value)
     }
 
+    // insert adds an element, panicking if the index is already set
     pub fn insert(&mut self, index: usize, value: f64) {
-        if let Some(pos) = self.elements.iter().position(|e| e.index >= index) {
-            assert_ne!(self.elements[pos].index, index);
-            self.elements.insert(pos, Element { index, value })
-        } else {
-            self.elements.push(Element { index, value })
+        match self.position(index) {
+            Ok(_) => panic!("duplicate index {} in vector", index),
+            Err(pos) => self.elements.insert(pos, Element { index, value }),
         }
+        self.magnitude = OnceLock::new();
     }
 
+    // upsert sets the element at the index, adding it if there is none
     pub fn upsert(&mut self, index: usize, value: f64) {
-        if let Some(pos) = self.elements.iter().position(|e| e.index >= index) {
-            self.elements.insert(pos, Element { index, value })
-        } else {
-            self.elements.push(Element { index, value })
+        match self.position(index) {
+            Ok(pos) => self.elements[pos].value = value,
+            Err(pos) => self.elements.insert(pos, Element { index, value }),
         }
+        self.magnitude = OnceLock::new();
     }
 
+    // similarity returns the cosine of the angle between the vectors, or 0
+    // if either of them is zero
     pub fn similarity(&self, other: &Self) -> f64 {
+        let magnitude = self.magnitude() * other.magnitude();
+        if magnitude == 0.0 {
+            0.0
+        } else {
+            self.dot(other) / magnitude
+        }
+    }
+
+    // score returns the dot product divided by the magnitude of this vector
+    // only, which is how a query vector scores a field vector, as in lunr.
+    // The field weights are already normalized by the similarity that
+    // weighed them, and dividing by their magnitude too would cancel the
+    // field and document boosts multiplied into every weight.
+    pub fn score(&self, other: &Self) -> f64 {
         let magnitude = self.magnitude();
         if magnitude == 0.0 {
             0.0
@@ -0,0 +1,288 @@
+extern crate sagume;
+
+use sagume::binary::MappedIndex;
+use sagume::builder::{Builder, FieldOptions};
+use sagume::document::Document;
+use sagume::field::{Field, FieldRef};
+use sagume::index::Index;
+use sagume::pipeline::Pipeline;
+use sagume::query::{Clause, Presence, Query};
+use sagume::token::{FuzzyOptions, TokenSet};
+
+use std::fs::File;
+
+fn get_index() -> Index {
+    let mut doc1 = Document::new("a".into());
+    doc1.add_field(Field::new_text(
+        "title".into(),
+        "Mr. Green kills Colonel Mustard".into(),
+    ));
+    doc1.add_field(Field::new_text(
+        "body".into(),
+        "Mr. Green killed Colonel Mustard in the study with the candlestick".into(),
+    ));
+
+    let mut doc2 = Document::new("b".into());
+    doc2.add_field(Field::new_text("title".into(), "Plumb waters plant".into()));
+    doc2.add_field(Field::new_text(
+        "body".into(),
+        "Professor Plumb has a green plant in his study".into(),
+    ));
+
+    let mut builder = Builder::new();
+    builder.add_field("title".into());
+    builder.add_field("body".into());
+    builder.add_document(doc1);
+    builder.add_document(doc2);
+    builder.build()
+}
+
+#[test]
+fn test_to_index() {
+    let index = get_index();
+    let mapped = MappedIndex::from_bytes(index.to_bytes().unwrap()).unwrap();
+
+    assert_eq!(mapped.to_index().unwrap().to_json(), index.to_json());
+}
+
+#[test]
+fn test_lookup() {
+    let index = get_index();
+    let mapped = MappedIndex::from_bytes(index.to_bytes().unwrap()).unwrap();
+
+    assert_eq!(mapped.field_names(), vec!["body", "title"]);
+    assert_eq!(mapped.doc_count(), 2);
+    assert_eq!(
+        mapped.term_index("plumb"),
+        Some(index.inverted_index().get("plumb").unwrap().index)
+    );
+    assert_eq!(mapped.term_index("scarlett"), None);
+    assert_eq!(mapped.postings("green", "body"), vec!["a", "b"]);
+    assert_eq!(mapped.postings("green", "title"), vec!["a"]);
+    assert!(mapped.postings("green", "memo").is_empty());
+
+    let field_ref = FieldRef::new("b".into(), "title".into());
+    assert_eq!(
+        mapped.field_vector(&field_ref).unwrap().to_flat_vec(),
+        index.field_vector(&field_ref).unwrap().to_flat_vec()
+    );
+
+    assert_eq!(
+        mapped.expand(&TokenSet::from_string("pl*")),
+        vec!["plant", "plumb"]
+    );
+    assert_eq!(mapped.expand(&TokenSet::from_string("*ed")), vec!["killed"]);
+}
+
+#[test]
+fn test_open() {
+    let path = std::env::temp_dir().join(format!("sagume-binary-{}.idx", std::process::id()));
+    get_index()
+        .write_to(&mut File::create(&path).unwrap())
+        .unwrap();
+    let mapped = MappedIndex::open(&path).unwrap();
+
+    let mut q = Query::new();
+    q.add_clause(Clause::new("plumb".into()));
+    let results = mapped.query(&q);
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].doc_ref(), "b");
+
+    let mut q = Query::new();
+    q.add_clause(Clause::new("green".into()));
+    assert_eq!(mapped.query(&q).len(), 2);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_invalid_data() {
+    assert!(MappedIndex::from_bytes(b"not an index".to_vec()).is_err());
+}
+
+// get_stored_index is get_index with stored titles and token metadata, so
+// that every section of the file has data
+fn get_stored_index() -> Index {
+    let mut builder = Builder::new();
+    let mut options = FieldOptions::new();
+    options.set_stored(true);
+    builder.add_field_with("title".into(), options);
+    builder.add_field("body".into());
+    builder.metadata_whitelist(vec!["position".into()]);
+    for (doc_ref, title, body) in [
+        ("a", "Mr. Green kills Colonel Mustard", "in the study"),
+        (
+            "b",
+            "Plumb waters plant",
+            "Professor Plumb has a green plant",
+        ),
+    ] {
+        let mut doc = Document::new(doc_ref.into());
+        doc.add_field(Field::new_text("title".into(), title.into()));
+        doc.add_field(Field::new_text("body".into(), body.into()));
+        builder.add_document(doc);
+    }
+    builder.build()
+}
+
+#[test]
+fn test_corrupted_data() {
+    let bytes = get_stored_index().to_bytes().unwrap();
+    let mut q = Query::new();
+    q.add_clause(Clause::new("pl*".into()));
+    q.add_clause(Clause::new("green".into()));
+
+    // a corrupted file is either rejected when opened or read without panics
+    let mut corrupted: Vec<Vec<u8>> = (0..bytes.len()).map(|len| bytes[..len].to_vec()).collect();
+    for i in 0..bytes.len() {
+        for mask in [0x01, 0x80, 0xff] {
+            let mut b = bytes.clone();
+            b[i] ^= mask;
+            corrupted.push(b);
+        }
+    }
+    for b in corrupted {
+        if let Ok(mapped) = MappedIndex::from_bytes(b) {
+            mapped.query(&q);
+            mapped.doc("a");
+            mapped.postings("plant", "body");
+            mapped.field_vector(&FieldRef::new("b".into(), "title".into()));
+            mapped.token_set().to_vec();
+            let _ = mapped.check();
+            let _ = mapped.to_index();
+        }
+    }
+    assert!(MappedIndex::from_bytes(bytes).is_ok());
+}
+
+#[test]
+fn test_lazy_checks() {
+    let mut bytes = get_stored_index().to_bytes().unwrap();
+    let u64_at = |bytes: &[u8], pos: usize| {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(&bytes[pos..pos + 8]);
+        u64::from_le_bytes(buf) as usize
+    };
+    // the header holds the offset of each section after the magic and the
+    // version; the store (8th) is a count and 12-byte entries followed by the
+    // deflated documents, which are overwritten here
+    let store = u64_at(&bytes, 8 + 8 * 7);
+    let metadata = u64_at(&bytes, 8 + 8 * 8);
+    let stored = bytes[store] as usize;
+    assert_eq!(stored, 2);
+    for b in bytes[store + 4 + 12 * stored..metadata].iter_mut() {
+        *b = 0xff;
+    }
+
+    // opening does not inflate the store, so only reading documents fails
+    let mapped = MappedIndex::from_bytes(bytes).unwrap();
+    assert!(mapped.check().is_err());
+    assert!(mapped.to_index().is_err());
+    assert!(mapped.doc("a").is_none());
+    let mut q = Query::new();
+    q.add_clause(Clause::new("plumb".into()));
+    let results = mapped.query(&q);
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].doc_ref(), "b");
+}
+
+#[test]
+fn test_term_index_overflow() {
+    let source = r#"{
+        "version": "2.3.9",
+        "fields": ["title"],
+        "fieldVectors": [["title/a", [4294967296, 1.0]]],
+        "invertedIndex": [["green", {"_index": 4294967296, "title": {"a": {}}}]],
+        "pipeline": []
+    }"#;
+    let index = Index::load(source, &Pipeline::new()).unwrap();
+    assert!(index.to_bytes().is_err());
+}
+
+#[test]
+fn test_query_as_index() {
+    let index = get_stored_index();
+    let mapped = MappedIndex::from_bytes(index.to_bytes().unwrap()).unwrap();
+
+    let clause = |term: &str, presence: Presence| {
+        let mut clause = Clause::new(term.into());
+        clause.set_presence(presence);
+        clause
+    };
+    let mut queries: Vec<Query> = Vec::new();
+    for clauses in [
+        vec![clause("green", Presence::Optional)],
+        vec![clause("pl*", Presence::Optional)],
+        vec![
+            clause("plant", Presence::Optional),
+            clause("green", Presence::Required),
+        ],
+        vec![
+            clause("green", Presence::Optional),
+            clause("plumb", Presence::Prohibited),
+        ],
+        vec![clause("plumb", Presence::Prohibited)],
+        vec![clause("scarlett", Presence::Required)],
+    ] {
+        let mut query = Query::new();
+        for clause in clauses {
+            query.add_clause(clause);
+        }
+        queries.push(query);
+    }
+    let mut clause = Clause::new("green".into());
+    clause.set_fields(vec!["title".into()]);
+    let mut query = Query::new();
+    query.add_clause(clause);
+    queries.push(query);
+
+    for query in queries {
+        let results = |results: Vec<sagume::index::MatchResult>| {
+            let mut results: Vec<(String, String)> = results
+                .iter()
+                .map(|r| (r.doc_ref().to_string(), format!("{:.9}", r.score())))
+                .collect();
+            results.sort();
+            results
+        };
+        assert_eq!(results(mapped.query(&query)), results(index.query(&query)));
+    }
+}
+
+#[test]
+fn test_fuzzy_query() {
+    let index = get_index();
+    let mapped = MappedIndex::from_bytes(index.to_bytes().unwrap()).unwrap();
+
+    for (term, edit_distance, max_expansions) in [
+        ("plnat", 1, 10),
+        ("kill", 2, 10),
+        ("kill", 2, 1),
+        ("gren", 1, 10),
+        ("zzz", 1, 10),
+    ] {
+        let mut clause = Clause::new(term.into());
+        clause.set_fuzzy(FuzzyOptions::new(edit_distance));
+        clause.set_max_expansions(max_expansions);
+        let mut query = Query::new();
+        query.add_clause(clause);
+        let results = |results: Vec<sagume::index::MatchResult>| {
+            let mut results: Vec<(String, Vec<String>)> = results
+                .iter()
+                .map(|r| {
+                    let mut terms: Vec<String> = r
+                        .match_data()
+                        .terms()
+                        .iter()
+                        .map(|t| t.to_string())
+                        .collect();
+                    terms.sort();
+                    (r.doc_ref().to_string(), terms)
+                })
+                .collect();
+            results.sort();
+            results
+        };
+        assert_eq!(results(mapped.query(&query)), results(index.query(&query)));
+    }
+}
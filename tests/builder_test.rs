@@ -1,9 +1,16 @@
 extern crate sagume;
 
-use sagume::builder::Builder;
+use sagume::binary::MappedIndex;
+use sagume::builder::{Builder, FieldOptions, MergeError};
 use sagume::document::Document;
-use sagume::field::{Field, FieldRef};
+use sagume::field::{Field, FieldRef, FieldValue};
+use sagume::index::{Index, InvertedIndex, MatchResult};
+use sagume::pipeline::Pipeline;
+use sagume::postings::PostingsFormat;
+use sagume::query::{Clause, Query};
 use sagume::token::TokenSet;
+use sagume::tokenizer::Tokenizer;
+use std::collections::BTreeMap;
 
 #[test]
 fn test_build() {
@@ -17,6 +24,7 @@ fn test_build() {
     b.add_field("isbn".into());
     b.add_document(doc);
     let index = b.build();
+    let doc_id = index.doc_refs().id("1").unwrap();
 
     assert!(index
         .inverted_index()
@@ -25,16 +33,416 @@ fn test_build() {
         .documents
         .get("title")
         .unwrap()
-        .contains("1"));
+        .contains(doc_id));
     assert!(index
-        .field_vectors()
-        .contains_key(&FieldRef::new("1".into(), "title".into())));
+        .field_vector(&FieldRef::new("1".into(), "title".into()))
+        .is_some());
 
     assert_eq!(
         index
             .token_set()
-            .intersect(&TokenSet::from_string("action".into()))
+            .intersect(&TokenSet::from_string("action"))
             .to_vec(),
         vec!["action"],
     );
 }
+
+#[test]
+fn test_term_index_follows_field_declaration() {
+    let fields = ["title", "author", "isbn", "publisher", "memo", "summary"];
+    for _ in 0..3 {
+        let mut doc = Document::new("1".into());
+        for (i, name) in fields.iter().enumerate() {
+            doc.add_field(Field::new_text(name.to_string(), format!("term{}", i)));
+        }
+
+        let mut b = Builder::new();
+        for name in fields.iter() {
+            b.add_field(name.to_string());
+        }
+        b.add_document(doc);
+        let index = b.build();
+        let term_indexes: Vec<usize> = (0..fields.len())
+            .map(|i| index.inverted_index()[&format!("term{}", i)].index as usize)
+            .collect();
+        assert_eq!(term_indexes, (1..=fields.len()).collect::<Vec<usize>>());
+    }
+}
+
+#[test]
+fn test_field_boost() {
+    let build = |boost: f64| {
+        let mut doc = Document::new("1".into());
+        doc.add_field(Field::new_text("title".into(), "Lucene in Action".into()));
+        let mut b = Builder::new();
+        let mut options = FieldOptions::new();
+        options.set_boost(boost);
+        b.add_field_with("title".into(), options);
+        b.add_document(doc);
+        b.build()
+    };
+    let field_ref = FieldRef::new("1".into(), "title".into());
+    let normal = build(1.0).field_vector(&field_ref).unwrap().to_flat_vec();
+    let boosted = build(10.0).field_vector(&field_ref).unwrap().to_flat_vec();
+
+    for (n, b) in normal.chunks(2).zip(boosted.chunks(2)) {
+        assert_eq!(n[0], b[0]);
+        assert!((n[1] * 10.0 - b[1]).abs() < 0.01);
+    }
+}
+
+#[test]
+fn test_nested_fields() {
+    let mut author = BTreeMap::new();
+    author.insert("name".to_string(), FieldValue::Text("Erik Hatcher".into()));
+    let mut doc = Document::new("1".into());
+    doc.add_field(Field::new("author".into(), FieldValue::Object(author)));
+    doc.add_field(Field::new(
+        "tags".into(),
+        FieldValue::Array(vec![
+            FieldValue::Text("search".into()),
+            FieldValue::Text("java".into()),
+        ]),
+    ));
+
+    let mut b = Builder::new();
+    b.add_field("author/name".into());
+    b.add_field("tags/*".into());
+    b.add_document(doc);
+    let index = b.build();
+    let doc_id = index.doc_refs().id("1").unwrap();
+
+    let documents = &index.inverted_index().get("hatcher").unwrap().documents;
+    assert!(documents.get("author/name").unwrap().contains(doc_id));
+    let documents = &index.inverted_index().get("java").unwrap().documents;
+    assert!(documents.get("tags/*").unwrap().contains(doc_id));
+    let field_ref = FieldRef::new("1".into(), "author/name".into());
+    assert!(index.field_vector(&field_ref).is_some());
+
+    let loaded = Index::load(&index.to_json_string(), &Pipeline::new()).unwrap();
+    assert!(loaded.field_vector(&field_ref).is_some());
+}
+
+#[test]
+fn test_add_documents_parallel() {
+    let words = [
+        "green",
+        "plant",
+        "study",
+        "professor",
+        "plumb",
+        "scarlett",
+        "mustard",
+        "colonel",
+    ];
+    let docs: Vec<(Document, f64)> = (0..40)
+        .map(|i| {
+            let mut doc = Document::new(format!("doc{}", i));
+            let title: Vec<&str> = (0..3).map(|j| words[(i * 3 + j) % words.len()]).collect();
+            let body: Vec<&str> = (0..7).map(|j| words[(i * j + 1) % words.len()]).collect();
+            doc.add_field(Field::new_text("title".into(), title.join(" ")));
+            doc.add_field(Field::new_text("body".into(), body.join(" ")));
+            (doc, 1.0 + (i % 3) as f64 / 2.0)
+        })
+        .collect();
+
+    let new_builder = || {
+        let mut b = Builder::new();
+        let mut options = FieldOptions::new();
+        options.set_boost(2.0);
+        b.add_field_with("title".into(), options);
+        b.add_field("body".into());
+        let mut options = FieldOptions::new();
+        options.set_extractor(|doc| Some(doc.doc_ref().replace("doc", "number ")));
+        b.add_field_with("number".into(), options);
+        b.metadata_whitelist(vec!["position".into()]);
+        b.set_postings_format(PostingsFormat::Positions);
+        b
+    };
+
+    let mut serial = new_builder();
+    for (doc, boost) in docs.clone() {
+        serial.add_document_with_boost(doc, boost);
+    }
+    for threads in [1, 3, 4, 64] {
+        let mut parallel = new_builder();
+        parallel.add_documents_parallel_with_boosts(docs.clone(), threads);
+        let (serial, parallel) = (serial.build(), parallel.build());
+
+        assert_eq!(
+            serial.inverted_index().len(),
+            parallel.inverted_index().len()
+        );
+        for (term, ri) in serial.inverted_index() {
+            assert!(parallel.inverted_index()[term] == *ri);
+        }
+        assert_eq!(serial.field_vectors().len(), parallel.field_vectors().len());
+        for (field_ref, vector) in serial.field_vectors() {
+            assert_eq!(
+                parallel.field_vectors()[field_ref].to_flat_vec(),
+                vector.to_flat_vec()
+            );
+        }
+    }
+
+    // replacing documents may number terms differently, but scores the same
+    let mut parallel = new_builder();
+    parallel.add_documents_parallel_with_boosts(docs, 4);
+    let mut doc = Document::new("doc3".into());
+    doc.add_field(Field::new_text("title".into(), "replaced".into()));
+    let mut replacing = vec![doc];
+    for i in [7, 40, 7] {
+        let mut doc = Document::new(format!("doc{}", i));
+        doc.add_field(Field::new_text("body".into(), format!("green {}", i)));
+        replacing.push(doc);
+    }
+    for doc in replacing.clone() {
+        serial.add_document(doc);
+    }
+    parallel.add_documents_parallel(replacing, 2);
+    let (serial, parallel) = (serial.build(), parallel.build());
+
+    assert!(parallel
+        .field_vector(&FieldRef::new("doc3".into(), "body".into()))
+        .is_none());
+    for (term, ri) in serial.inverted_index() {
+        let parallel_ri = &parallel.inverted_index()[term];
+        assert_eq!(doc_refs(&parallel, parallel_ri), doc_refs(&serial, ri));
+    }
+    for terms in ["green", "replaced number", "plant 7", "mustard 40"] {
+        let mut q = Query::new();
+        for term in terms.split(' ') {
+            q.add_clause(Clause::new(term.into()));
+        }
+        let results = |results: Vec<MatchResult>| {
+            let mut results: Vec<(String, f64)> = results
+                .iter()
+                .map(|r| (r.doc_ref().to_string(), r.score()))
+                .collect();
+            results.sort_by(|a, b| a.0.cmp(&b.0));
+            results
+        };
+        assert_eq!(results(parallel.query(&q)), results(serial.query(&q)));
+    }
+}
+
+#[test]
+fn test_add_documents_parallel_field_options() {
+    let docs: Vec<Document> = (0..20)
+        .map(|i| {
+            let mut doc = Document::new(format!("doc{}", i));
+            doc.add_field(Field::new_text(
+                "tags".into(),
+                format!("green,plant-{},study", i % 4),
+            ));
+            doc
+        })
+        .collect();
+
+    let new_builder = || {
+        let mut b = Builder::new();
+        let mut options = FieldOptions::new();
+        options.set_tokenizer(Tokenizer::with_separator(|c| c == ','));
+        let mut pipeline = Pipeline::new();
+        pipeline.add("dropGreen", |t| {
+            if t.value() == "green" {
+                None
+            } else {
+                Some(t)
+            }
+        });
+        options.set_pipeline(pipeline);
+        b.add_field_with("tags".into(), options);
+        let mut options = FieldOptions::new();
+        options.set_extractor(|doc| Some(format!("{} extracted", doc.doc_ref())));
+        b.add_field_with("extracted".into(), options);
+        b
+    };
+
+    let mut serial = new_builder();
+    for doc in docs.clone() {
+        serial.add_document(doc);
+    }
+    let mut parallel = new_builder();
+    parallel.add_documents_parallel(docs, 4);
+
+    // documents added after the parallel ones are analyzed with the field
+    // options too
+    let mut doc = Document::new("late".into());
+    doc.add_field(Field::new_text("tags".into(), "green,late-tag".into()));
+    serial.add_document(doc.clone());
+    parallel.add_document(doc);
+
+    let (serial, parallel) = (serial.build(), parallel.build());
+    assert!(serial.inverted_index().contains_key("plant-1"));
+    assert!(serial.inverted_index().contains_key("doc3"));
+    assert!(!serial.inverted_index().contains_key("green"));
+    assert_eq!(
+        serial.inverted_index().len(),
+        parallel.inverted_index().len()
+    );
+    for (term, ri) in serial.inverted_index() {
+        assert!(parallel.inverted_index()[term] == *ri);
+    }
+    for (field_ref, vector) in serial.field_vectors() {
+        assert_eq!(
+            parallel.field_vectors()[field_ref].to_flat_vec(),
+            vector.to_flat_vec()
+        );
+    }
+}
+
+fn doc_refs(index: &Index, ri: &InvertedIndex) -> Vec<(String, String)> {
+    let mut doc_refs: Vec<(String, String)> = ri
+        .documents
+        .iter()
+        .flat_map(|(field_name, doc_ids)| {
+            doc_ids.iter().map(move |doc_id| {
+                let doc_ref = index.doc_refs().doc_ref(doc_id);
+                (field_name.to_string(), doc_ref.to_string())
+            })
+        })
+        .collect();
+    doc_refs.sort();
+    doc_refs
+}
+
+#[test]
+fn test_absorb() {
+    let data = [
+        ("a", "Mr. Green kills Colonel Mustard"),
+        ("b", "Plumb waters plant"),
+        ("c", "Scarlett helps Professor Plumb"),
+        ("d", "Green plant in the study"),
+    ];
+    // the shards are built with the default postings format
+    let new_builder = || {
+        let mut b = Builder::new();
+        b.add_field("title".into());
+        b
+    };
+    let mut all = new_builder();
+    let mut first = new_builder();
+    let mut second = new_builder();
+    for (i, (doc_ref, title)) in data.iter().enumerate() {
+        let mut doc = Document::new(doc_ref.to_string());
+        doc.add_field(Field::new_text("title".into(), title.to_string()));
+        all.add_document_with_boost(doc.clone(), 1.0 + i as f64);
+        if i < 2 {
+            first.add_document_with_boost(doc, 1.0 + i as f64);
+        } else {
+            second.add_document_with_boost(doc, 1.0 + i as f64);
+        }
+    }
+    // the second shard is built apart and read back from its binary encoding
+    let second = MappedIndex::from_bytes(second.build().to_bytes().unwrap())
+        .unwrap()
+        .to_index()
+        .unwrap();
+    let mut extra = Builder::new();
+    extra.set_postings_format(PostingsFormat::Positions);
+    extra.add_field("body".into());
+    let mut doc = Document::new("e".into());
+    doc.add_field(Field::new_text("body".into(), "green".into()));
+    extra.add_document(doc);
+    let extra = extra.build();
+
+    first.absorb(&second).unwrap();
+    let merged = first.build();
+    let all = all.build();
+
+    assert_eq!(merged.inverted_index().len(), all.inverted_index().len());
+    let mut indexes: Vec<u64> = merged
+        .inverted_index()
+        .values()
+        .map(|ri| ri.index)
+        .collect();
+    indexes.sort_unstable();
+    indexes.dedup();
+    assert_eq!(indexes.len(), merged.inverted_index().len());
+    for (term, ri) in all.inverted_index() {
+        let merged_ri = &merged.inverted_index()[term];
+        assert_eq!(doc_refs(&merged, merged_ri), doc_refs(&all, ri));
+        for ((doc_id, field_id), vector) in all.field_vectors() {
+            let doc_ref = all.doc_refs().doc_ref(*doc_id);
+            let field_name = all.field_name(*field_id);
+            let field_ref = FieldRef::new(doc_ref.to_string(), field_name.to_string());
+            assert_eq!(
+                merged
+                    .field_vector(&field_ref)
+                    .unwrap()
+                    .get(merged_ri.index as usize),
+                vector.get(ri.index as usize)
+            );
+        }
+    }
+    let mut terms = merged.token_set().to_vec();
+    terms.sort();
+    let mut expected: Vec<String> = all.inverted_index().keys().cloned().collect();
+    expected.sort();
+    assert_eq!(terms, expected);
+
+    assert_eq!(
+        first.absorb(&second),
+        Err(MergeError::DuplicateDocuments(vec![
+            "c".to_string(),
+            "d".to_string()
+        ]))
+    );
+
+    first.absorb(&extra).unwrap();
+    let merged = first.build();
+    assert!(merged.field_names().contains("body"));
+    let doc_id = merged.doc_refs().id("e").unwrap();
+    assert!(merged.inverted_index()["green"].documents["body"].contains(doc_id));
+
+    let mut without_freqs = Builder::new();
+    without_freqs.add_field("title".into());
+    without_freqs.set_postings_format(PostingsFormat::Docs);
+    let mut doc = Document::new("f".into());
+    doc.add_field(Field::new_text("title".into(), "lamp".into()));
+    without_freqs.add_document(doc);
+    assert_eq!(
+        first.absorb(&without_freqs.build()),
+        Err(MergeError::MissingTermFrequencies)
+    );
+}
+
+#[test]
+fn test_absorb_term_indexes() {
+    let mut shard = Builder::new();
+    shard.add_field("title".into());
+    shard.set_postings_format(PostingsFormat::Freqs);
+    let mut doc = Document::new("a".into());
+    doc.add_field(Field::new_text(
+        "title".into(),
+        "zebra yak xenops walrus vole".into(),
+    ));
+    shard.add_document(doc);
+    let shard = shard.build();
+
+    // new terms are numbered in the order of their indexes in the shard
+    let mut shard_terms: Vec<(u64, &String)> = shard
+        .inverted_index()
+        .iter()
+        .map(|(term, ri)| (ri.index, term))
+        .collect();
+    shard_terms.sort();
+    for _ in 0..3 {
+        let mut builder = Builder::new();
+        builder.add_field("title".into());
+        let mut doc = Document::new("b".into());
+        doc.add_field(Field::new_text("title".into(), "yak".into()));
+        builder.add_document(doc);
+        builder.absorb(&shard).unwrap();
+        let index = builder.build();
+        let mut terms: Vec<(u64, &String)> = index
+            .inverted_index()
+            .iter()
+            .map(|(term, ri)| (ri.index, term))
+            .collect();
+        terms.sort();
+        let terms: Vec<&str> = terms.iter().map(|(_, term)| term.as_str()).collect();
+        assert_eq!(terms, vec!["yak", "zebra", "xenops", "walrus", "vole"]);
+    }
+}
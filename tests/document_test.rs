@@ -0,0 +1,25 @@
+extern crate sagume;
+
+use sagume::document::DocRefs;
+
+#[test]
+fn test_doc_refs() {
+    let mut doc_refs = DocRefs::new();
+    assert_eq!(doc_refs.intern("a"), 0);
+    assert_eq!(doc_refs.intern("b"), 1);
+    assert_eq!(doc_refs.intern("a"), 0);
+    assert_eq!(doc_refs.doc_ref(1), "b");
+    assert_eq!(doc_refs.len(), 2);
+
+    assert_eq!(doc_refs.release("a"), Some(0));
+    assert_eq!(doc_refs.release("a"), None);
+    assert!(!doc_refs.contains("a"));
+    assert_eq!(doc_refs.iter().collect::<Vec<_>>(), vec![(1, "b")]);
+
+    assert_eq!(doc_refs.intern("c"), 0);
+    assert_eq!(doc_refs.id("c"), Some(0));
+    assert_eq!(
+        doc_refs.iter().collect::<Vec<_>>(),
+        vec![(0, "c"), (1, "b")]
+    );
+}
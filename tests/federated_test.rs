@@ -0,0 +1,146 @@
+extern crate sagume;
+
+use sagume::builder::Builder;
+use sagume::document::Document;
+use sagume::federated::{FederatedSearcher, Normalization};
+use sagume::field::Field;
+use sagume::index::Index;
+use sagume::query::{Clause, Query};
+
+const TITLES: [&str; 4] = [
+    "green plant",
+    "plant in the study",
+    "green study lamp",
+    "office plant",
+];
+
+fn build(docs: &[(String, &str)]) -> Index {
+    let mut builder = Builder::new();
+    builder.add_field("title".into());
+    for (doc_ref, title) in docs {
+        let mut doc = Document::new(doc_ref.to_string());
+        doc.add_field(Field::new_text("title".into(), title.to_string()));
+        builder.add_document(doc);
+    }
+    builder.build()
+}
+
+fn docs(prefix: &str) -> Vec<(String, &'static str)> {
+    TITLES
+        .iter()
+        .enumerate()
+        .map(|(i, title)| (format!("{}{}", prefix, i), *title))
+        .collect()
+}
+
+fn query(terms: &[&str]) -> Query {
+    let mut q = Query::new();
+    for term in terms {
+        q.add_clause(Clause::new(term.to_string()));
+    }
+    q
+}
+
+#[test]
+fn test_global_idf() {
+    let a = build(&docs("a"));
+    let b = build(&docs("b"));
+    let mut all = docs("a");
+    all.extend(docs("b"));
+    let combined = build(&all);
+
+    let mut searcher = FederatedSearcher::new(Normalization::GlobalIdf);
+    searcher.add_index("a", &a);
+    searcher.add_index("b", &b);
+    assert_eq!(searcher.index_names(), vec!["a", "b"]);
+
+    let q = query(&["green", "plant"]);
+    let results = searcher.query(&q);
+    let expected = combined.query(&q);
+    assert_eq!(results.len(), expected.len());
+    for result in results.iter() {
+        assert!(result.doc_ref().starts_with(result.index_name()));
+        let expected = expected
+            .iter()
+            .find(|r| r.doc_ref() == result.doc_ref())
+            .unwrap();
+        // field vector weights are rounded at build time
+        assert!(
+            (result.score() - expected.score()).abs() < 1e-2,
+            "{} {}",
+            result.score(),
+            expected.score()
+        );
+        assert_eq!(
+            result.result().match_data().terms().len(),
+            expected.match_data().terms().len()
+        );
+    }
+}
+
+#[test]
+fn test_max_score() {
+    let a = build(&docs("a"));
+    let b = build(&[
+        ("b0".to_string(), "green green plant"),
+        ("b1".to_string(), "lamp"),
+    ]);
+
+    let mut searcher = FederatedSearcher::new(Normalization::MaxScore);
+    searcher.add_index("a", &a);
+    searcher.add_index("b", &b);
+    let results = searcher.query(&query(&["green"]));
+    assert_eq!(results.len(), 3);
+    for name in ["a", "b"] {
+        let best = results
+            .iter()
+            .filter(|r| r.index_name() == name)
+            .map(|r| r.score())
+            .fold(0.0, f64::max);
+        assert!((best - 1.0).abs() < 1e-9);
+    }
+
+    let mut searcher = FederatedSearcher::new(Normalization::None);
+    searcher.add_index("a", &a);
+    searcher.add_index("a", &b);
+    let results = searcher.query(&query(&["green"]));
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].doc_ref(), "b0");
+    assert_eq!(results[0].score(), b.query(&query(&["green"]))[0].score());
+}
+
+#[test]
+fn test_global_idf_after_adding_index() {
+    let a = build(&docs("a"));
+    let b = build(&[
+        ("b0".to_string(), "green lamp"),
+        ("b1".to_string(), "office desk"),
+    ]);
+    let q = query(&["green", "plant"]);
+
+    let mut searcher = FederatedSearcher::new(Normalization::GlobalIdf);
+    searcher.add_index("a", &a);
+    let before = searcher.query(&q);
+
+    // the global idf is recomputed with the index added after querying
+    searcher.add_index("b", &b);
+    let after = searcher.query(&q);
+    let mut fresh = FederatedSearcher::new(Normalization::GlobalIdf);
+    fresh.add_index("a", &a);
+    fresh.add_index("b", &b);
+    let expected = fresh.query(&q);
+
+    assert_eq!(after.len(), expected.len());
+    for (result, expected) in after.iter().zip(expected.iter()) {
+        assert_eq!(result.doc_ref(), expected.doc_ref());
+        assert_eq!(result.score(), expected.score());
+    }
+    let a0 = |results: &[sagume::federated::FederatedResult]| {
+        results
+            .iter()
+            .find(|r| r.doc_ref() == "a0")
+            .unwrap()
+            .score()
+    };
+    assert!(a0(&before) != a0(&after));
+}
@@ -0,0 +1,79 @@
+extern crate sagume;
+
+use sagume::document::Document;
+use sagume::field::{Field, FieldRef, FieldValue};
+use std::collections::BTreeMap;
+
+fn text(value: &str) -> FieldValue {
+    FieldValue::Text(value.into())
+}
+
+fn get_document() -> Document {
+    let mut author = BTreeMap::new();
+    author.insert("name".to_string(), text("Shreya Gamble"));
+    author.insert("age".to_string(), FieldValue::U64(42));
+
+    let mut editor = BTreeMap::new();
+    editor.insert("name".to_string(), text("Fariha Le"));
+
+    let mut doc = Document::new("1".into());
+    doc.add_field(Field::new("author".into(), FieldValue::Object(author)));
+    doc.add_field(Field::new(
+        "tags".into(),
+        FieldValue::Array(vec![text("search"), text("rust")]),
+    ));
+    doc.add_field(Field::new(
+        "editors".into(),
+        FieldValue::Array(vec![FieldValue::Object(editor)]),
+    ));
+    doc
+}
+
+#[test]
+fn test_resolve() {
+    let doc = get_document();
+    assert_eq!(doc.resolve("author/name"), vec![&text("Shreya Gamble")]);
+    assert_eq!(doc.resolve("author/age"), vec![&FieldValue::U64(42)]);
+    assert_eq!(doc.resolve("tags/*"), vec![&text("search"), &text("rust")]);
+    assert_eq!(doc.resolve("tags/1"), vec![&text("rust")]);
+    assert_eq!(doc.resolve("editors/name"), vec![&text("Fariha Le")]);
+    assert!(doc.resolve("author/missing").is_empty());
+    assert!(doc.resolve("missing").is_empty());
+    assert_eq!(doc.resolve("tags")[0].to_string(), "search rust");
+}
+
+#[test]
+fn test_field_ref() {
+    let field_ref = FieldRef::new("1".into(), "title".into());
+    assert_eq!(field_ref.to_string(), "title/1");
+    let parsed = FieldRef::from_string("title//docs/intro").unwrap();
+    assert_eq!(parsed.field_name(), "title");
+    assert_eq!(parsed.doc_ref(), "/docs/intro");
+
+    let field_ref = FieldRef::new("/docs/intro".into(), "author/name".into());
+    assert_eq!(field_ref.to_string(), "author\\/name//docs/intro");
+    let parsed = FieldRef::from_string(&field_ref.to_string()).unwrap();
+    assert_eq!(parsed, field_ref);
+
+    let field_ref = FieldRef::new("1".into(), "back\\slash".into());
+    let parsed = FieldRef::from_string(&field_ref.to_string()).unwrap();
+    assert_eq!(parsed, field_ref);
+
+    assert!(FieldRef::from_string("title").is_err());
+    assert!(FieldRef::from_string("title\\").is_err());
+}
+
+#[test]
+fn test_lunr_field_ref() {
+    let field_names = ["author", "author/name", "title"];
+    let field_ref = FieldRef::new("/docs/intro".into(), "author/name".into());
+    assert_eq!(field_ref.to_lunr_string(), "author/name//docs/intro");
+    let parsed = FieldRef::from_lunr_string(&field_ref.to_lunr_string(), field_names).unwrap();
+    assert_eq!(parsed, field_ref);
+
+    let parsed = FieldRef::from_lunr_string("author/nameless", field_names).unwrap();
+    assert_eq!(parsed.field_name(), "author");
+    assert_eq!(parsed.doc_ref(), "nameless");
+
+    assert!(FieldRef::from_lunr_string("body/1", field_names).is_err());
+}
@@ -0,0 +1,68 @@
+extern crate sagume;
+
+use sagume::fst::Fst;
+use sagume::token::TokenSet;
+
+fn words() -> Vec<String> {
+    let mut words: Vec<String> = [
+        "bar", "barn", "bat", "car", "card", "care", "cart", "cat", "für", "plant", "planted",
+    ]
+    .iter()
+    .map(|w| w.to_string())
+    .collect();
+    words.extend((0..500).map(|i| format!("term{}", i)));
+    words.sort();
+    words
+}
+
+#[test]
+fn test_lookup() {
+    let words = words();
+    let bytes = TokenSet::from_array(&words).to_bytes();
+    let fst = Fst::new(&bytes).unwrap();
+
+    assert_eq!(fst.to_vec(), words);
+    for word in words.iter() {
+        assert!(fst.contains(word));
+    }
+    assert!(fst.contains("für"));
+    assert!(!fst.contains("ca"));
+    assert!(!fst.contains("cards"));
+    assert!(!fst.contains(""));
+    assert_eq!(fst.prefix("car"), vec!["car", "card", "care", "cart"]);
+    assert_eq!(fst.prefix("term49").len(), 11);
+    assert!(fst.prefix("dog").is_empty());
+
+    assert!(Fst::new(b"nope").is_err());
+    assert!(Fst::new(&bytes[..2]).is_err());
+}
+
+#[test]
+fn test_intersect() {
+    let words = words();
+    let set = TokenSet::from_array(&words);
+    let bytes = set.to_bytes();
+    let fst = Fst::new(&bytes).unwrap();
+
+    for query in ["ca*", "*t", "b*n", "plant", "c*r*", "nothing"] {
+        let q = TokenSet::from_string(query);
+        let mut expected = set.intersect(&q).to_vec();
+        expected.sort();
+        assert_eq!(fst.intersect(&q), expected, "{}", query);
+    }
+
+    let q = TokenSet::from_fuzzy_string("cat", 1);
+    assert_eq!(fst.intersect(&q), vec!["bat", "car", "cart", "cat"]);
+}
+
+#[test]
+fn test_cyclic_token_set() {
+    // the wildcard is a looping edge, so the encoding is not a vocabulary
+    let bytes = TokenSet::from_string("ab*c").to_bytes();
+    assert!(Fst::new(&bytes).is_err());
+
+    // the root has an edge "a" back to itself
+    assert!(Fst::new(b"SGTS\x03\x61\x04").is_err());
+    // "a" leads to a node whose edge "b" goes back to it
+    assert!(Fst::new(b"SGTS\x02\x61\x07\x03\x62\x07").is_err());
+}
@@ -2,7 +2,8 @@ use sagume::builder::Builder;
 use sagume::document::Document;
 use sagume::field::Field;
 use sagume::index::Index;
-use sagume::query::{Clause, Query};
+use sagume::query::{Clause, Presence, Query};
+use sagume::token::FuzzyOptions;
 
 fn get_index() -> Index {
     let mut doc1 = Document::new("a".into());
@@ -31,7 +32,7 @@ fn get_index() -> Index {
     let mut builder = Builder::new();
     builder.add_field("title".into());
     builder.add_field("body".into());
-    for doc in vec![doc1, doc2, doc3] {
+    for doc in [doc1, doc2, doc3] {
         builder.add_document(doc);
     }
     builder.build()
@@ -60,3 +61,271 @@ fn test_search_result_order() {
 
     assert_eq!(results.first().unwrap().doc_ref(), "b");
 }
+
+#[test]
+fn test_search_with_required_term() {
+    let mut q = Query::new();
+    q.add_clause(Clause::new("plant".into()));
+    let mut c = Clause::new("office".into());
+    c.set_presence(Presence::Required);
+    q.add_clause(c);
+
+    let index = get_index();
+    let results = index.query(&q);
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results.first().unwrap().doc_ref(), "c");
+}
+
+#[test]
+fn test_document_boost() {
+    let mut builder = Builder::new();
+    builder.add_field("title".into());
+    for (doc_ref, boost) in [("a", 1.0), ("b", 10.0)].iter() {
+        let mut doc = Document::new(doc_ref.to_string());
+        doc.add_field(Field::new_text("title".into(), "green plant".into()));
+        builder.add_document_with_boost(doc, *boost);
+    }
+    let index = builder.build();
+
+    let mut q = Query::new();
+    q.add_clause(Clause::new("green".into()));
+    let results = index.query(&q);
+    let a = results.iter().find(|r| r.doc_ref() == "a").unwrap();
+    let b = results.iter().find(|r| r.doc_ref() == "b").unwrap();
+    assert!(a.score() > 0.0);
+    assert!(b.score() > a.score());
+}
+
+fn get_large_index() -> Index {
+    let words = [
+        "green",
+        "plant",
+        "study",
+        "professor",
+        "colonel",
+        "mustard",
+        "office",
+        "week",
+    ];
+    let mut builder = Builder::new();
+    builder.add_field("title".into());
+    builder.add_field("body".into());
+    for i in 0..500usize {
+        let title: Vec<&str> = (0..3)
+            .map(|j| words[(i * 7 + j * 3) % words.len()])
+            .collect();
+        let body: Vec<&str> = (0..(i % 13 + 3))
+            .map(|j| words[(i / 3 + j * (i % 3 * 2 + 1)) % words.len()])
+            .collect();
+        let mut doc = Document::new(i.to_string());
+        doc.add_field(Field::new_text("title".into(), title.join(" ")));
+        doc.add_field(Field::new_text("body".into(), body.join(" ")));
+        builder.add_document(doc);
+    }
+    builder.build()
+}
+
+fn assert_top_k(index: &Index, q: &Query, k: usize) {
+    let mut expected: Vec<f64> = index.query(q).iter().map(|r| r.score()).collect();
+    expected.sort_by(|a, b| b.partial_cmp(a).unwrap());
+    expected.truncate(k);
+
+    let results = index.query_top_k(q, k);
+    let mut scores: Vec<f64> = results.iter().map(|r| r.score()).collect();
+    scores.sort_by(|a, b| b.partial_cmp(a).unwrap());
+    assert_eq!(scores.len(), expected.len());
+    for (score, expected) in scores.iter().zip(expected.iter()) {
+        assert!((score - expected).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_query_top_k() {
+    let index = get_large_index();
+    let mut q = Query::new();
+    q.add_clause(Clause::new("green".into()));
+    q.add_clause(Clause::new("office".into()));
+    let mut c = Clause::new("mustard".into());
+    c.set_boost(3);
+    q.add_clause(c);
+    for k in [0, 1, 5, 20, 1000] {
+        assert_top_k(&index, &q, k);
+    }
+
+    let mut q = Query::new();
+    q.add_clause(Clause::new("plant".into()));
+    let mut c = Clause::new("week".into());
+    c.set_presence(Presence::Required);
+    q.add_clause(c);
+    let mut c = Clause::new("colonel".into());
+    c.set_presence(Presence::Prohibited);
+    q.add_clause(c);
+    assert_top_k(&index, &q, 10);
+    let results = index.query_top_k(&q, 10);
+    assert_eq!(results.len(), 10);
+    for result in results {
+        assert!(result.match_data().terms().contains(&"week"));
+        assert!(!result.match_data().terms().contains(&"colonel"));
+    }
+
+    // a negated query scores every document it lets through zero, and keeps
+    // the first documents as the WAND path breaks ties
+    let mut q = Query::new();
+    let mut c = Clause::new("colonel".into());
+    c.set_presence(Presence::Prohibited);
+    q.add_clause(c);
+    assert_top_k(&index, &q, 10);
+    let expected: Vec<String> = index
+        .query(&q)
+        .iter()
+        .map(|r| r.doc_ref().to_string())
+        .filter_map(|doc_ref| doc_ref.parse::<usize>().ok())
+        .collect::<std::collections::BTreeSet<usize>>()
+        .into_iter()
+        .take(10)
+        .map(|i| i.to_string())
+        .collect();
+    let mut doc_refs: Vec<String> = index
+        .query_top_k(&q, 10)
+        .iter()
+        .map(|r| r.doc_ref().to_string())
+        .collect();
+    doc_refs.sort_by_key(|doc_ref| doc_ref.parse::<usize>().unwrap());
+    assert_eq!(doc_refs, expected);
+}
+
+#[test]
+fn test_max_weight() {
+    let index = get_index();
+    let max_weight = index.max_weight("green", "body").unwrap();
+    let doc_id = index.doc_refs().id("a").unwrap();
+    let term_index = index.inverted_index()["green"].index as usize;
+    let field_id = index.field_id("body").unwrap();
+    let weight = index.field_vectors()[&(doc_id, field_id)]
+        .get(term_index)
+        .unwrap();
+    assert!(max_weight >= weight);
+    assert!(index.max_weight("scarlett", "title").is_some());
+    assert!(index.max_weight("scarlett", "memo").is_none());
+    assert!(index.max_weight("missing", "title").is_none());
+}
+
+#[test]
+fn test_field_ids() {
+    let index = get_index();
+    // field ids are positions in the sorted field names
+    assert_eq!(index.field_id("body"), Some(0));
+    assert_eq!(index.field_id("title"), Some(1));
+    assert_eq!(index.field_id("memo"), None);
+    assert_eq!(index.field_name(1), "title");
+    for (_, field_id) in index.field_vectors().keys() {
+        assert!(*field_id < 2);
+    }
+}
+
+#[test]
+fn test_query_from_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Index>();
+
+    let index = std::sync::Arc::new(get_large_index());
+    let mut q = Query::new();
+    q.add_clause(Clause::new("green".into()));
+    let expected = index.query(&q).len();
+
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let index = std::sync::Arc::clone(&index);
+            std::thread::spawn(move || {
+                let mut q = Query::new();
+                q.add_clause(Clause::new("green".into()));
+                let terms = index.token_set().to_vec().len();
+                (index.query(&q).len(), terms)
+            })
+        })
+        .collect();
+    for handle in handles {
+        let (len, terms) = handle.join().unwrap();
+        assert_eq!(len, expected);
+        assert_eq!(terms, index.inverted_index().len());
+    }
+}
+
+fn fuzzy_query(term: &str, options: FuzzyOptions, max_expansions: usize) -> Query {
+    let mut clause = Clause::new(term.into());
+    clause.set_fuzzy(options);
+    clause.set_max_expansions(max_expansions);
+    let mut query = Query::new();
+    query.add_clause(clause);
+    query
+}
+
+#[test]
+fn test_fuzzy_query() {
+    let mut builder = Builder::new();
+    builder.add_field("title".into());
+    for (doc_ref, title) in [
+        ("a", "plant"),
+        ("b", "plans"),
+        ("c", "plans"),
+        ("d", "planet"),
+        ("e", "plane"),
+        ("f", "plane"),
+        ("g", "plane"),
+    ] {
+        let mut doc = Document::new(doc_ref.into());
+        let title = format!("{} in the garden", title);
+        doc.add_field(Field::new_text("title".into(), title));
+        builder.add_document(doc);
+    }
+    let index = builder.build();
+    let doc_refs = |query: &Query| {
+        let mut doc_refs: Vec<String> = index
+            .query(query)
+            .iter()
+            .map(|r| r.doc_ref().to_string())
+            .collect();
+        doc_refs.sort();
+        doc_refs
+    };
+
+    // plant matches exactly, while plane, plans and planet are one edit away
+    // and plane is the most frequent of them
+    let query = fuzzy_query("plant", FuzzyOptions::new(1), 1024);
+    assert_eq!(doc_refs(&query), vec!["a", "b", "c", "d", "e", "f", "g"]);
+    let query = fuzzy_query("plant", FuzzyOptions::new(1), 1);
+    assert_eq!(doc_refs(&query), vec!["a"]);
+    let query = fuzzy_query("plant", FuzzyOptions::new(1), 2);
+    assert_eq!(doc_refs(&query), vec!["a", "e", "f", "g"]);
+
+    let mut options = FuzzyOptions::new(1);
+    options.substitutions = false;
+    let query = fuzzy_query("plant", options, 1024);
+    assert_eq!(doc_refs(&query), vec!["a", "d"]);
+
+    let query = fuzzy_query("palnt", FuzzyOptions::new(1), 1024);
+    assert_eq!(doc_refs(&query), vec!["a"]);
+    let mut options = FuzzyOptions::new(1);
+    options.prefix_length = 2;
+    let query = fuzzy_query("palnt", options, 1024);
+    assert!(doc_refs(&query).is_empty());
+}
+
+#[test]
+fn test_repeated_term() {
+    let index = get_index();
+    let mut once = Query::new();
+    once.add_clause(Clause::new("green".into()));
+    let mut twice = Query::new();
+    twice.add_clause(Clause::new("green".into()));
+    twice.add_clause(Clause::new("green".into()));
+
+    let once = index.query(&once);
+    let twice = index.query(&twice);
+    assert_eq!(once.len(), twice.len());
+    for (a, b) in once.iter().zip(twice.iter()) {
+        assert_eq!(a.doc_ref(), b.doc_ref());
+        assert!((a.score() - b.score()).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,233 @@
+extern crate sagume;
+
+use sagume::builder::Builder;
+use sagume::document::Document;
+use sagume::field::{Field, FieldRef};
+use sagume::index::Index;
+use sagume::json::{LoadError, VERSION};
+use sagume::pipeline::Pipeline;
+use sagume::query::{Clause, Query};
+use sagume::token::{Token, TokenSet};
+
+fn get_index() -> Index {
+    let mut doc1 = Document::new("a".into());
+    doc1.add_field(Field::new_text("title".into(), "green plant".into()));
+    doc1.add_field(Field::new_text("body".into(), "plumb".into()));
+
+    let mut doc2 = Document::new("b".into());
+    doc2.add_field(Field::new_text("title".into(), "plant".into()));
+    doc2.add_field(Field::new_text("body".into(), "green".into()));
+
+    let mut builder = Builder::new();
+    builder.add_field("title".into());
+    builder.add_field("body".into());
+    builder.pipeline().add("identity", Some);
+    builder.add_document(doc1);
+    builder.add_document(doc2);
+    builder.build()
+}
+
+#[test]
+fn test_to_json() {
+    let json = get_index().to_json();
+
+    assert_eq!(json["version"], VERSION);
+    assert_eq!(json["fields"], serde_json::json!(["body", "title"]));
+    assert_eq!(json["pipeline"], serde_json::json!(["identity"]));
+
+    let field_vectors = json["fieldVectors"].as_array().unwrap();
+    let refs: Vec<&str> = field_vectors
+        .iter()
+        .map(|v| v[0].as_str().unwrap())
+        .collect();
+    assert_eq!(refs, vec!["body/a", "body/b", "title/a", "title/b"]);
+    for v in field_vectors {
+        assert_eq!(v[1].as_array().unwrap().len() % 2, 0);
+    }
+
+    let inverted_index = json["invertedIndex"].as_array().unwrap();
+    let terms: Vec<&str> = inverted_index
+        .iter()
+        .map(|v| v[0].as_str().unwrap())
+        .collect();
+    assert_eq!(terms, vec!["green", "plant", "plumb"]);
+
+    let green = &inverted_index[0][1];
+    assert!(green["_index"].is_u64());
+    assert_eq!(green["title"], serde_json::json!({"a": {}}));
+    assert_eq!(green["body"], serde_json::json!({"b": {}}));
+
+    let plumb = &inverted_index[2][1];
+    assert_eq!(plumb["title"], serde_json::json!({}));
+}
+
+#[test]
+fn test_pipeline_labels() {
+    let mut builder = Builder::new();
+    builder.pipeline().add("trimmer", Some);
+    builder
+        .pipeline()
+        .add("stopWordFilter", |token: Token| match token.value() {
+            "the" => None,
+            _ => Some(token),
+        });
+    let index = builder.build();
+    assert_eq!(index.pipeline(), &vec!["trimmer", "stopWordFilter"]);
+}
+
+#[test]
+fn test_load() {
+    let index = get_index();
+    let mut pipeline = Pipeline::new();
+    pipeline.add("identity", Some);
+
+    let loaded = Index::load(&index.to_json_string(), &pipeline).unwrap();
+    assert_eq!(loaded.to_json(), index.to_json());
+    assert_eq!(
+        loaded
+            .token_set()
+            .intersect(&TokenSet::from_string("pl*"))
+            .to_vec()
+            .len(),
+        2
+    );
+
+    let mut q = Query::new();
+    q.add_clause(Clause::new("green".into()));
+    let mut expected: Vec<String> = index
+        .query(&q)
+        .iter()
+        .map(|r| r.doc_ref().to_string())
+        .collect();
+    let mut actual: Vec<String> = loaded
+        .query(&q)
+        .iter()
+        .map(|r| r.doc_ref().to_string())
+        .collect();
+    expected.sort();
+    actual.sort();
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn test_load_lunr_index() {
+    let source = r#"{
+        "version": "2.3.9",
+        "fields": ["title"],
+        "fieldVectors": [["title/1", [0, 0.288, 1, 0.288]]],
+        "invertedIndex": [
+            ["action", {"_index": 1, "title": {"1": {}}}],
+            ["lucene", {"_index": 0, "title": {"1": {}}}]
+        ],
+        "pipeline": []
+    }"#;
+    let index = Index::load(source, &Pipeline::new()).unwrap();
+    let doc_id = index.doc_refs().id("1").unwrap();
+
+    assert_eq!(index.inverted_index().get("action").unwrap().index, 1);
+    assert!(index
+        .inverted_index()
+        .get("lucene")
+        .unwrap()
+        .documents
+        .get("title")
+        .unwrap()
+        .contains(doc_id));
+    assert!(index
+        .field_vector(&FieldRef::new("1".into(), "title".into()))
+        .is_some());
+}
+
+// LUNR_URL_REFS is an index serialized by lunr.js 2.3.9 whose doc refs are
+// URL paths
+const LUNR_URL_REFS: &str = r#"{
+    "version": "2.3.9",
+    "fields": ["title", "body"],
+    "fieldVectors": [
+        ["title//docs/intro", [0, 0.61, 1, 0.61]],
+        ["body//docs/intro", [2, 0.693, 3, 0.693]],
+        ["title//docs/search", [4, 0.803]],
+        ["body//docs/search", [5, 0.693, 6, 0.693]]
+    ],
+    "invertedIndex": [
+        ["crate", {"_index": 3, "title": {}, "body": {"/docs/intro": {}}}],
+        ["get", {"_index": 0, "title": {"/docs/intro": {}}, "body": {}}],
+        ["index", {"_index": 6, "title": {}, "body": {"/docs/search": {}}}],
+        ["instal", {"_index": 2, "title": {}, "body": {"/docs/intro": {}}}],
+        ["queri", {"_index": 5, "title": {}, "body": {"/docs/search": {}}}],
+        ["search", {"_index": 4, "title": {"/docs/search": {}}, "body": {}}],
+        ["start", {"_index": 1, "title": {"/docs/intro": {}}, "body": {}}]
+    ],
+    "pipeline": ["stemmer"]
+}"#;
+
+#[test]
+fn test_load_url_doc_refs() {
+    let mut pipeline = Pipeline::new();
+    pipeline.add("stemmer", Some);
+    let index = Index::load(LUNR_URL_REFS, &pipeline).unwrap();
+
+    let vector = index
+        .field_vector(&FieldRef::new("/docs/intro".into(), "title".into()))
+        .unwrap();
+    assert_eq!(vector.to_flat_vec(), vec![0.0, 0.61, 1.0, 0.61]);
+    assert!(index
+        .field_vector(&FieldRef::new("/docs/search".into(), "body".into()))
+        .is_some());
+
+    let mut q = Query::new();
+    q.add_clause(Clause::new("search".into()));
+    let results = index.query(&q);
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].doc_ref(), "/docs/search");
+
+    let reloaded = Index::load(&index.to_json_string(), &pipeline).unwrap();
+    assert_eq!(reloaded.to_json(), index.to_json());
+}
+
+#[test]
+fn test_load_errors() {
+    let source = r#"{
+        "version": "1.2.1",
+        "fields": [],
+        "fieldVectors": [],
+        "invertedIndex": [],
+        "pipeline": []
+    }"#;
+    assert_eq!(
+        Index::load(source, &Pipeline::new()).err(),
+        Some(LoadError::VersionMismatch {
+            expected: VERSION.into(),
+            actual: "1.2.1".into()
+        })
+    );
+
+    let source = r#"{
+        "version": "2.3.8",
+        "fields": [],
+        "fieldVectors": [],
+        "invertedIndex": [],
+        "pipeline": ["stemmer"]
+    }"#;
+    assert_eq!(
+        Index::load(source, &Pipeline::new()).err(),
+        Some(LoadError::UnknownPipelineFunction("stemmer".into()))
+    );
+
+    let source = r#"{
+        "version": "2.3.9",
+        "fields": ["title"],
+        "fieldVectors": [["body//docs/intro", []]],
+        "invertedIndex": [],
+        "pipeline": []
+    }"#;
+    assert_eq!(
+        Index::load(source, &Pipeline::new()).err(),
+        Some(LoadError::Malformed("unknown field in field ref".into()))
+    );
+
+    match Index::load("{", &Pipeline::new()) {
+        Err(LoadError::Parse(_)) => {}
+        _ => panic!("expected a parse error"),
+    }
+}
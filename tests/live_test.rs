@@ -0,0 +1,237 @@
+extern crate sagume;
+
+use sagume::builder::Builder;
+use sagume::document::Document;
+use sagume::field::{Field, FieldRef};
+use sagume::index::Index;
+use sagume::live::LiveIndex;
+use sagume::query::{Clause, Query};
+use sagume::token::TokenSet;
+
+fn document(doc_ref: &str, title: &str) -> Document {
+    let mut doc = Document::new(doc_ref.into());
+    doc.add_field(Field::new_text("title".into(), title.into()));
+    doc
+}
+
+fn get_index() -> LiveIndex {
+    let mut builder = Builder::new();
+    builder.add_field("title".into());
+    builder.add_document(document("a", "green plant"));
+    builder.add_document(document("b", "plumb waters plant"));
+    LiveIndex::new(builder)
+}
+
+fn search(index: &LiveIndex, term: &str) -> Vec<String> {
+    let mut q = Query::new();
+    q.add_clause(Clause::new(term.into()));
+    let mut doc_refs: Vec<String> = index
+        .query(&q)
+        .iter()
+        .map(|r| r.doc_ref().to_string())
+        .collect();
+    doc_refs.sort();
+    doc_refs
+}
+
+#[test]
+fn test_add() {
+    let mut index = get_index();
+    index.add(document("c", "scarlett plant"));
+    assert!(index.has_pending_changes());
+    assert_eq!(search(&index, "plant"), vec!["a", "b"]);
+
+    index.commit();
+    assert!(!index.has_pending_changes());
+    assert_eq!(search(&index, "plant"), vec!["a", "b", "c"]);
+    assert_eq!(
+        index
+            .index()
+            .token_set()
+            .intersect(&TokenSet::from_string("scarlett"))
+            .to_vec(),
+        vec!["scarlett"]
+    );
+}
+
+#[test]
+fn test_update() {
+    let mut index = get_index();
+    let before = index
+        .index()
+        .field_vector(&FieldRef::new("b".into(), "title".into()))
+        .unwrap()
+        .to_flat_vec();
+
+    assert!(index.update(document("a", "green")));
+    assert!(!index.update(document("z", "green")));
+    index.commit();
+    assert_eq!(index.index().doc_refs().len(), 2);
+    assert_eq!(
+        index
+            .index()
+            .inverted_index()
+            .get("plant")
+            .unwrap()
+            .documents
+            .get("title")
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // "plant" became rarer, so its weight in "b" is rescored
+    let after = index
+        .index()
+        .field_vector(&FieldRef::new("b".into(), "title".into()))
+        .unwrap()
+        .to_flat_vec();
+    assert_ne!(before, after);
+}
+
+#[test]
+fn test_delete() {
+    let mut index = get_index();
+    assert!(index.delete("b"));
+    assert!(!index.delete("b"));
+    assert_eq!(search(&index, "plumb"), vec!["b"]);
+
+    index.commit();
+    assert!(index.index().inverted_index().get("plumb").is_none());
+    assert!(index
+        .index()
+        .token_set()
+        .intersect(&TokenSet::from_string("plumb"))
+        .to_vec()
+        .is_empty());
+    assert!(index
+        .index()
+        .field_vector(&FieldRef::new("b".into(), "title".into()))
+        .is_none());
+    assert_eq!(search(&index, "plant"), vec!["a"]);
+}
+
+// postings lists the doc refs of every term and field in the index
+fn postings(index: &Index) -> Vec<(String, String, Vec<String>)> {
+    let mut postings = Vec::new();
+    for (term, ri) in index.inverted_index().iter() {
+        for (field_name, doc_ids) in ri.documents.iter() {
+            let mut doc_refs: Vec<String> = doc_ids
+                .iter()
+                .map(|doc_id| index.doc_refs().doc_ref(doc_id).to_string())
+                .collect();
+            doc_refs.sort();
+            postings.push((term.to_string(), field_name.to_string(), doc_refs));
+        }
+    }
+    postings.sort();
+    postings
+}
+
+// weights lists the term weights in the title vector of the document
+fn weights(index: &Index, doc_ref: &str) -> Vec<(String, String)> {
+    let field_ref = FieldRef::new(doc_ref.into(), "title".into());
+    let vector = index.field_vector(&field_ref).unwrap();
+    let mut weights: Vec<(String, String)> = index
+        .inverted_index()
+        .iter()
+        .filter_map(|(term, ri)| {
+            vector
+                .iter()
+                .find(|(i, _)| *i == ri.index as usize)
+                .map(|(_, w)| (term.to_string(), format!("{:.3}", w)))
+        })
+        .collect();
+    weights.sort();
+    weights
+}
+
+#[test]
+fn test_commit() {
+    let mut index = get_index();
+    index.add(document("c", "scarlett plant"));
+    index.commit();
+    index.delete("a");
+    index.update(document("b", "plumb waters scarlett"));
+    index.add(document("d", "green candlestick"));
+    index.add(document("a", "colonel plant"));
+    index.commit();
+
+    let mut builder = Builder::new();
+    builder.add_field("title".into());
+    for (doc_ref, title) in [
+        ("b", "plumb waters scarlett"),
+        ("c", "scarlett plant"),
+        ("d", "green candlestick"),
+        ("a", "colonel plant"),
+    ] {
+        builder.add_document(document(doc_ref, title));
+    }
+    let expected = builder.build();
+
+    assert_eq!(postings(index.index()), postings(&expected));
+    assert_eq!(
+        index.index().token_set().to_vec(),
+        expected.token_set().to_vec()
+    );
+    for term in ["plant", "scarlett", "green", "plumb", "colonel", "mustard"] {
+        let mut q = Query::new();
+        q.add_clause(Clause::new(term.into()));
+        let mut doc_refs: Vec<String> = expected
+            .query(&q)
+            .iter()
+            .map(|r| r.doc_ref().to_string())
+            .collect();
+        doc_refs.sort();
+        assert_eq!(search(&index, term), doc_refs);
+    }
+
+    // rebuild weighs every document as the fresh build does
+    index.rebuild();
+    for doc_ref in ["a", "b", "c", "d"] {
+        assert_eq!(weights(index.index(), doc_ref), weights(&expected, doc_ref));
+    }
+    for term in ["plant", "scarlett", "green", "plumb", "colonel"] {
+        assert_eq!(scores(index.index(), term), scores(&expected, term));
+    }
+}
+
+#[test]
+fn test_token_set_stays_minimal() {
+    let mut index = get_index();
+    for i in 0..20 {
+        index.add(document(&format!("t{}", i), &format!("term{} plant", i)));
+        index.commit();
+        if i % 2 == 0 {
+            index.delete(&format!("t{}", i));
+            index.commit();
+        }
+    }
+
+    let mut builder = Builder::new();
+    builder.add_field("title".into());
+    builder.add_document(document("a", "green plant"));
+    builder.add_document(document("b", "plumb waters plant"));
+    for i in (1..20).step_by(2) {
+        builder.add_document(document(&format!("t{}", i), &format!("term{} plant", i)));
+    }
+    let expected = builder.build();
+
+    // the committed token set is the one a full build minimizes
+    assert_eq!(
+        index.index().token_set().to_bytes(),
+        expected.token_set().to_bytes()
+    );
+}
+
+fn scores(index: &Index, term: &str) -> Vec<(String, f64)> {
+    let mut q = Query::new();
+    q.add_clause(Clause::new(term.into()));
+    let mut scores: Vec<(String, f64)> = index
+        .query(&q)
+        .iter()
+        .map(|r| (r.doc_ref().to_string(), r.score()))
+        .collect();
+    scores.sort_by(|a, b| a.0.cmp(&b.0));
+    scores
+}
@@ -0,0 +1,133 @@
+extern crate sagume;
+
+use sagume::field::FieldValue;
+use sagume::loader::{FieldType, Loader, RecordError};
+
+use std::io::Cursor;
+
+#[test]
+fn test_json_lines() {
+    let source = r#"{"id": "a", "title": "Green plant", "year": 2001, "delta": -3}
+
+{"id": 7, "title": "Scarlett", "tags": ["a", "b"], "note": null}
+{"title": "no id"}
+[1, 2]
+{"id": "c", broken
+"#;
+    let loader = Loader::new("id");
+    let results: Vec<_> = loader.json_lines(Cursor::new(source)).collect();
+    assert_eq!(results.len(), 5);
+
+    let doc = results[0].as_ref().unwrap();
+    assert_eq!(doc.doc_ref(), "a");
+    assert_eq!(
+        doc.get("title"),
+        Some(&FieldValue::Text("Green plant".into()))
+    );
+    assert_eq!(doc.get("year"), Some(&FieldValue::U64(2001)));
+    assert_eq!(doc.get("delta"), Some(&FieldValue::I64(-3)));
+    assert!(doc.get("id").is_none());
+
+    let doc = results[1].as_ref().unwrap();
+    assert_eq!(doc.doc_ref(), "7");
+    assert!(
+        doc.get("tags")
+            == Some(&FieldValue::Array(vec![
+                FieldValue::Text("a".into()),
+                FieldValue::Text("b".into())
+            ]))
+    );
+    assert!(doc.get("note").is_none());
+
+    let lines: Vec<usize> = results
+        .iter()
+        .filter_map(|r| r.as_ref().err())
+        .map(|e| e.line)
+        .collect();
+    assert_eq!(lines, vec![4, 5, 6]);
+    assert_eq!(
+        results[2].as_ref().err().unwrap(),
+        &RecordError {
+            line: 4,
+            message: "missing doc ref id".into()
+        }
+    );
+}
+
+#[test]
+fn test_csv() {
+    let source = "\
+id,title,year,rank
+a,Green plant,2001,-1
+b,\"Plumb, Professor\",,3
+c,Scarlett
+,no id,1,1
+";
+    let loader = Loader::new("id");
+    let results: Vec<_> = loader.csv(Cursor::new(source)).collect();
+    assert_eq!(results.len(), 4);
+
+    let doc = results[0].as_ref().unwrap();
+    assert_eq!(doc.doc_ref(), "a");
+    assert_eq!(
+        doc.get("title"),
+        Some(&FieldValue::Text("Green plant".into()))
+    );
+    assert_eq!(doc.get("year"), Some(&FieldValue::U64(2001)));
+    assert_eq!(doc.get("rank"), Some(&FieldValue::I64(-1)));
+
+    let doc = results[1].as_ref().unwrap();
+    assert_eq!(
+        doc.get("title"),
+        Some(&FieldValue::Text("Plumb, Professor".into()))
+    );
+    assert!(doc.get("year").is_none());
+
+    assert_eq!(results[2].as_ref().err().unwrap().line, 4);
+    assert_eq!(results[3].as_ref().err().unwrap().line, 5);
+}
+
+#[test]
+fn test_mapping_and_schema() {
+    let source = "\
+id,name,zip,body
+a,Green,01234,ignored
+b,Plumb,x1,ignored
+";
+    let mut loader = Loader::new("id");
+    loader.map_field("name", "title");
+    loader.map_field("zip", "code");
+    loader.set_type("code", FieldType::Text);
+    let results: Vec<_> = loader.csv(Cursor::new(source)).collect();
+
+    let doc = results[0].as_ref().unwrap();
+    assert_eq!(doc.get("title"), Some(&FieldValue::Text("Green".into())));
+    assert_eq!(doc.get("code"), Some(&FieldValue::Text("01234".into())));
+    assert!(doc.get("body").is_none());
+
+    let mut loader = Loader::new("id");
+    loader.set_type("zip", FieldType::U64);
+    let results: Vec<_> = loader.csv(Cursor::new(source)).collect();
+    assert_eq!(
+        results[0].as_ref().unwrap().get("zip"),
+        Some(&FieldValue::U64(1234))
+    );
+    assert_eq!(results[1].as_ref().err().unwrap().line, 3);
+
+    let results: Vec<_> = Loader::new("id")
+        .csv(Cursor::new("id,zip\n007,01234\n"))
+        .collect();
+    let doc = results[0].as_ref().unwrap();
+    assert_eq!(doc.doc_ref(), "007");
+    assert_eq!(doc.get("zip"), Some(&FieldValue::U64(1234)));
+
+    let mut loader = Loader::new("id");
+    loader.set_type("year", FieldType::U64);
+    let results: Vec<_> = loader
+        .json_lines(Cursor::new(r#"{"id": "a", "year": "2001"}"#))
+        .collect();
+    assert_eq!(
+        results[0].as_ref().unwrap().get("year"),
+        Some(&FieldValue::U64(2001))
+    );
+}
@@ -0,0 +1,128 @@
+extern crate sagume;
+
+use sagume::binary::MappedIndex;
+use sagume::builder::Builder;
+use sagume::document::Document;
+use sagume::field::{Field, FieldValue};
+use sagume::index::{Index, MatchResult};
+use sagume::pipeline::Pipeline;
+use sagume::query::{Clause, Query};
+
+fn position(start: u64, len: u64) -> FieldValue {
+    FieldValue::Array(vec![FieldValue::U64(start), FieldValue::U64(len)])
+}
+
+fn get_index() -> Index {
+    let mut builder = Builder::new();
+    builder.add_field("title".into());
+    builder.add_field("body".into());
+    builder.metadata_whitelist(vec!["position".into(), "shout".into()]);
+    builder.pipeline().add("shout", |mut token| {
+        if token.value().ends_with('!') {
+            token.value = token.value().trim_end_matches('!').to_string();
+            token.set_metadata("shout", FieldValue::U64(1));
+        }
+        Some(token)
+    });
+
+    let mut doc = Document::new("a".into());
+    doc.add_field(Field::new_text("title".into(), "green plant".into()));
+    doc.add_field(Field::new_text(
+        "body".into(),
+        "a green! plant is green".into(),
+    ));
+    builder.add_document(doc);
+
+    let mut doc = Document::new("b".into());
+    doc.add_field(Field::new_text("title".into(), "professor plumb".into()));
+    builder.add_document(doc);
+
+    builder.build()
+}
+
+fn query(index: &Index, term: &str) -> Vec<MatchResult> {
+    let mut q = Query::new();
+    q.add_clause(Clause::new(term.into()));
+    index.query(&q)
+}
+
+fn assert_match_data(results: &[MatchResult]) {
+    assert_eq!(results.len(), 1);
+    let match_data = results[0].match_data();
+    assert_eq!(match_data.terms(), vec!["green"]);
+    let mut fields = match_data.fields("green");
+    fields.sort();
+    assert_eq!(fields, vec!["body", "title"]);
+
+    let title = match_data.get("green", "title").unwrap();
+    assert_eq!(title["position"], vec![position(0, 5)]);
+    assert!(!title.contains_key("shout"));
+
+    let body = match_data.get("green", "body").unwrap();
+    let mut positions = body["position"].clone();
+    positions.sort();
+    assert_eq!(positions, vec![position(2, 6), position(18, 5)]);
+    assert_eq!(body["shout"], vec![FieldValue::U64(1)]);
+}
+
+#[test]
+fn test_token_metadata() {
+    let index = get_index();
+    assert_match_data(&query(&index, "green"));
+
+    let results = query(&index, "plumb");
+    let metadata = results[0].match_data().get("plumb", "title").unwrap();
+    assert_eq!(metadata["position"], vec![position(10, 5)]);
+}
+
+#[test]
+fn test_not_whitelisted() {
+    let mut builder = Builder::new();
+    builder.add_field("title".into());
+    let mut doc = Document::new("a".into());
+    doc.add_field(Field::new_text("title".into(), "green plant".into()));
+    builder.add_document(doc);
+
+    let results = query(&builder.build(), "green");
+    let metadata = results[0].match_data().get("green", "title").unwrap();
+    assert!(metadata.is_empty());
+}
+
+#[test]
+fn test_remove_document() {
+    let mut builder = Builder::new();
+    builder.add_field("title".into());
+    builder.metadata_whitelist(vec!["index".into()]);
+    for doc_ref in ["a", "b"].iter() {
+        let mut doc = Document::new(doc_ref.to_string());
+        doc.add_field(Field::new_text("title".into(), "green plant".into()));
+        builder.add_document(doc);
+    }
+    builder.remove_document("a");
+
+    let index = builder.build();
+    let ri = &index.inverted_index()["green"];
+    let doc_id = index.doc_refs().id("b").unwrap();
+    assert!(index.doc_refs().id("a").is_none());
+    assert_eq!(ri.metadata["title"].len(), 1);
+    assert_eq!(
+        ri.get_metadata("title", doc_id).unwrap()["index"],
+        vec![FieldValue::U64(0)]
+    );
+}
+
+#[test]
+fn test_serialization() {
+    let index = get_index();
+
+    let mut pipeline = Pipeline::new();
+    pipeline.add("shout", Some);
+    let loaded = Index::load(&index.to_json_string(), &pipeline).unwrap();
+    assert_match_data(&query(&loaded, "green"));
+
+    let mapped = MappedIndex::from_bytes(index.to_bytes().unwrap()).unwrap();
+    let mut q = Query::new();
+    q.add_clause(Clause::new("green".into()));
+    assert_match_data(&mapped.query(&q));
+    assert_match_data(&query(&mapped.to_index().unwrap(), "green"));
+}
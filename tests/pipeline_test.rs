@@ -0,0 +1,27 @@
+extern crate sagume;
+
+use sagume::pipeline::Pipeline;
+use sagume::tokenizer::Tokenizer;
+
+#[test]
+fn test_run() {
+    let mut pipeline = Pipeline::new();
+    pipeline.add("stopWordFilter", |t| {
+        if t.value == "the" {
+            None
+        } else {
+            Some(t)
+        }
+    });
+    pipeline.add("upcase", |mut t| {
+        t.value = t.value.to_uppercase();
+        Some(t)
+    });
+
+    let tokens = pipeline.run(Tokenizer::new().tokenize("the quick fox"));
+    assert_eq!(
+        tokens.iter().map(|t| &t.value).collect::<Vec<&String>>(),
+        vec!["QUICK", "FOX"]
+    );
+    assert_eq!(pipeline.labels(), vec!["stopWordFilter", "upcase"]);
+}
@@ -0,0 +1,148 @@
+extern crate sagume;
+
+use sagume::binary::MappedIndex;
+use sagume::builder::Builder;
+use sagume::document::Document;
+use sagume::field::Field;
+use sagume::postings::{Posting, PostingList, PostingsFormat, PostingsIter, BLOCK_SIZE};
+use std::collections::HashSet;
+
+#[test]
+fn test_insert_and_remove() {
+    let mut list: PostingList = (0..1000).map(|i| i * 3).collect();
+    assert_eq!(list.len(), 1000);
+    assert!(list.contains(999));
+    assert!(!list.contains(1000));
+    assert!(list.size() < 1000 * 2);
+
+    assert!(list.insert(1000));
+    assert!(!list.insert(1000));
+    assert!(list.insert(1));
+    assert!(list.remove(3));
+    assert!(!list.remove(4));
+    for i in 0..BLOCK_SIZE as u32 {
+        list.remove(i * 3 + 600);
+    }
+
+    let mut expected: Vec<u32> = (0..1000).map(|i| i * 3).collect();
+    expected.push(1000);
+    expected.push(1);
+    expected.retain(|d| *d != 3 && !(600..600 + BLOCK_SIZE as u32 * 3).contains(d));
+    expected.sort_unstable();
+    assert_eq!(list.iter().collect::<Vec<_>>(), expected);
+    assert_eq!(list.len(), expected.len());
+    assert!(list == expected.into_iter().collect());
+}
+
+#[test]
+fn test_advance() {
+    let list: PostingList = (0..1000).map(|i| i * 2).collect();
+    let mut iter = list.iter();
+    assert_eq!(iter.next(), Some(0));
+    assert_eq!(iter.advance(0), Some(2));
+    assert_eq!(iter.advance(701), Some(702));
+    assert_eq!(iter.next(), Some(704));
+    assert_eq!(iter.advance(1998), Some(1998));
+    assert_eq!(iter.advance(1999), None);
+    assert_eq!(iter.next(), None);
+
+    let mut iter = list.iter();
+    assert_eq!(iter.advance(5000), None);
+}
+
+#[test]
+fn test_positions() {
+    let mut list = PostingList::new(PostingsFormat::Positions);
+    list.insert_posting(Posting {
+        doc_id: 7,
+        term_freq: 2,
+        positions: vec![3, 10],
+    });
+    list.insert_posting(Posting {
+        doc_id: 2,
+        term_freq: 1,
+        positions: vec![0],
+    });
+    assert!(list.insert(5));
+
+    let mut iter = list.iter();
+    assert_eq!(iter.advance(6), Some(7));
+    assert_eq!(iter.term_freq(), 2);
+    assert_eq!(iter.positions(), &[3, 10]);
+    assert_eq!(list.get(2).unwrap().positions, vec![0]);
+    assert_eq!(list.get(5).unwrap().term_freq, 1);
+    assert!(list.get(6).is_none());
+
+    let loaded = PostingList::from_bytes(&list.to_bytes()).unwrap();
+    assert!(loaded == list);
+    let bytes = list.to_bytes();
+    let mut iter = PostingsIter::from_bytes(&bytes).unwrap();
+    assert_eq!(iter.advance(3), Some(5));
+    assert_eq!(iter.next(), Some(7));
+    assert_eq!(iter.positions(), &[3, 10]);
+}
+
+#[test]
+fn test_corrupted_bytes() {
+    let mut list = PostingList::new(PostingsFormat::Positions);
+    for doc_id in 0..300 {
+        list.insert_posting(Posting {
+            doc_id: doc_id * 2,
+            term_freq: 2,
+            positions: vec![1, 5],
+        });
+    }
+    let bytes = list.to_bytes();
+    assert!(PostingList::from_bytes(&bytes).is_ok());
+    for len in 0..bytes.len() {
+        assert!(PostingList::from_bytes(&bytes[..len]).is_err());
+        assert!(PostingsIter::from_bytes(&bytes[..len]).is_err());
+    }
+
+    let mut invalid_format = bytes.clone();
+    invalid_format[0] = 7;
+    assert!(PostingList::from_bytes(&invalid_format).is_err());
+    let mut overlong = bytes.clone();
+    overlong.truncate(1);
+    overlong.extend_from_slice(&[0xff; 11]);
+    assert!(PostingList::from_bytes(&overlong).is_err());
+}
+
+// a posting list is compared with the HashSet of doc ids it replaced, counting
+// only the elements and control bytes of the set
+#[test]
+fn test_size_against_hash_set() {
+    for step in [1u32, 3, 10] {
+        let doc_ids: Vec<u32> = (0..10000).map(|i| i * step).collect();
+        let list: PostingList = doc_ids.iter().cloned().collect();
+        let set: HashSet<u32> = doc_ids.into_iter().collect();
+        let set_size = set.capacity() * (std::mem::size_of::<u32>() + 1);
+        assert!(list.size() * 5 <= set_size);
+    }
+}
+
+#[test]
+fn test_builder_postings_format() {
+    let mut builder = Builder::new();
+    builder.add_field("title".into());
+    builder.set_postings_format(PostingsFormat::Positions);
+    for (doc_ref, title) in [("a", "green plant green"), ("b", "plant")].iter() {
+        let mut doc = Document::new(doc_ref.to_string());
+        doc.add_field(Field::new_text("title".into(), title.to_string()));
+        builder.add_document(doc);
+    }
+    let index = builder.build();
+
+    let docs = &index.inverted_index()["green"].documents["title"];
+    let doc_id = index.doc_refs().id("a").unwrap();
+    let posting = docs.get(doc_id).unwrap();
+    assert_eq!(posting.term_freq, 2);
+    assert_eq!(posting.positions, vec![0, 2]);
+
+    let mapped = MappedIndex::from_bytes(index.to_bytes().unwrap()).unwrap();
+    let mut iter = mapped.postings_iter("plant", "title").unwrap();
+    assert_eq!(mapped.doc_ref(iter.next().unwrap() as usize), "a");
+    assert_eq!(iter.positions(), &[1]);
+    assert_eq!(mapped.doc_ref(iter.next().unwrap() as usize), "b");
+    assert!(mapped.postings_iter("plant", "body").is_none());
+}
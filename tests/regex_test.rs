@@ -0,0 +1,192 @@
+extern crate sagume;
+
+use sagume::binary::MappedIndex;
+use sagume::builder::Builder;
+use sagume::document::Document;
+use sagume::field::Field;
+use sagume::fst::Fst;
+use sagume::index::Index;
+use sagume::query::{Clause, Presence, Query};
+use sagume::regex::{Regex, RegexError};
+use sagume::token::TokenSet;
+
+fn words() -> Vec<String> {
+    let mut words: Vec<String> = [
+        "bar", "barn", "bat", "car", "card", "care", "cart", "cat", "color", "colour", "colours",
+        "für",
+    ]
+    .iter()
+    .map(|w| w.to_string())
+    .collect();
+    words.extend((0..100).map(|i| format!("term{}", i)));
+    words.sort();
+    words
+}
+
+fn get_index() -> Index {
+    let mut builder = Builder::new();
+    builder.add_field("title".into());
+    for (doc_ref, title) in [
+        ("a", "the color of the sky"),
+        ("b", "a colour chart"),
+        ("c", "colours and shapes"),
+        ("d", "a red car"),
+    ] {
+        let mut doc = Document::new(doc_ref.into());
+        doc.add_field(Field::new_text("title".into(), title.into()));
+        builder.add_document(doc);
+    }
+    builder.build()
+}
+
+fn doc_refs(results: &[sagume::index::MatchResult]) -> Vec<String> {
+    let mut doc_refs: Vec<String> = results.iter().map(|r| r.doc_ref().to_string()).collect();
+    doc_refs.sort();
+    doc_refs
+}
+
+#[test]
+fn test_parse_errors() {
+    assert_eq!(Regex::new("ab(c").unwrap_err(), RegexError::UnexpectedEnd);
+    assert_eq!(Regex::new("[ab").unwrap_err(), RegexError::UnexpectedEnd);
+    assert_eq!(
+        Regex::new("ab)").unwrap_err(),
+        RegexError::UnexpectedChar(')', 2)
+    );
+    assert_eq!(
+        Regex::new("*a").unwrap_err(),
+        RegexError::UnexpectedChar('*', 0)
+    );
+    assert_eq!(
+        Regex::new("a{2}").unwrap_err(),
+        RegexError::Unsupported('{', 1)
+    );
+    assert_eq!(Regex::new("x[]").unwrap_err(), RegexError::EmptyClass(1));
+    assert_eq!(
+        Regex::new("[z-a]").unwrap_err(),
+        RegexError::UnexpectedChar('a', 3)
+    );
+    assert!(Clause::new_regex("^a").is_err());
+}
+
+#[test]
+fn test_is_match() {
+    let regex = Regex::new("colou?r").unwrap();
+    assert_eq!(regex.pattern(), "colou?r");
+    assert!(regex.is_match("color"));
+    assert!(regex.is_match("colour"));
+    assert!(!regex.is_match("colours"));
+    assert!(!regex.is_match("colr"));
+
+    let regex = Regex::new("(ca|ba)[rt]+").unwrap();
+    assert!(regex.is_match("car"));
+    assert!(regex.is_match("batt"));
+    assert!(!regex.is_match("ca"));
+    assert!(!regex.is_match("cab"));
+
+    let regex = Regex::new("[^0-9]*.").unwrap();
+    assert!(regex.is_match("a"));
+    assert!(regex.is_match("ab9"));
+    assert!(!regex.is_match("a9b"));
+    assert!(!regex.is_match(""));
+
+    let regex = Regex::new("a\\.b|").unwrap();
+    assert!(regex.is_match("a.b"));
+    assert!(regex.is_match(""));
+    assert!(!regex.is_match("axb"));
+}
+
+#[test]
+fn test_intersect_regex() {
+    let words = words();
+    let set = TokenSet::from_array(&words);
+    let bytes = set.to_bytes();
+    let fst = Fst::new(&bytes).unwrap();
+
+    for pattern in [
+        "colou?r",
+        "ca.*",
+        "(b|c)a[rt]",
+        "term[1-3]0?",
+        "f.r",
+        "x+",
+        ".*",
+    ] {
+        let regex = Regex::new(pattern).unwrap();
+        let expected: Vec<String> = words
+            .iter()
+            .filter(|w| regex.is_match(w))
+            .cloned()
+            .collect();
+        assert_eq!(
+            set.intersect_regex(&regex, usize::MAX),
+            expected,
+            "{}",
+            pattern
+        );
+        assert_eq!(
+            fst.intersect_regex(&regex, usize::MAX),
+            expected,
+            "{}",
+            pattern
+        );
+    }
+
+    let regex = Regex::new("term.*").unwrap();
+    let first: Vec<String> = words
+        .iter()
+        .filter(|w| regex.is_match(w))
+        .take(5)
+        .cloned()
+        .collect();
+    assert_eq!(set.intersect_regex(&regex, 5), first);
+    assert_eq!(fst.intersect_regex(&regex, 5), first);
+    assert!(set.intersect_regex(&regex, 0).is_empty());
+}
+
+#[test]
+fn test_query() {
+    let index = get_index();
+
+    let mut query = Query::new();
+    query.add_clause(Clause::new_regex("colou?r").unwrap());
+    assert_eq!(doc_refs(&index.query(&query)), vec!["a", "b"]);
+
+    let mut query = Query::new();
+    query.add_clause(Clause::new_regex("colou?rs?").unwrap());
+    let mut clause = Clause::new_regex("car").unwrap();
+    clause.set_presence(Presence::Prohibited);
+    query.add_clause(clause);
+    assert_eq!(doc_refs(&index.query(&query)), vec!["a", "b", "c"]);
+
+    let mut query = Query::new();
+    let mut clause = Clause::new_regex("colou?rs?").unwrap();
+    clause.set_max_expansions(1);
+    assert_eq!(clause.max_expansions(), 1);
+    query.add_clause(clause);
+    assert_eq!(doc_refs(&index.query(&query)), vec!["a"]);
+
+    let mut query = Query::new();
+    let mut clause = Clause::new_regex("z.*").unwrap();
+    clause.set_presence(Presence::Required);
+    query.add_clause(clause);
+    query.add_clause(Clause::new("car".into()));
+    assert!(index.query(&query).is_empty());
+}
+
+#[test]
+fn test_mapped_query() {
+    let index = get_index();
+    let mapped = MappedIndex::from_bytes(index.to_bytes().unwrap()).unwrap();
+
+    for pattern in ["colou?r", "colou?rs?", "co.*", "nothing"] {
+        let mut query = Query::new();
+        query.add_clause(Clause::new_regex(pattern).unwrap());
+        assert_eq!(
+            doc_refs(&mapped.query(&query)),
+            doc_refs(&index.query(&query)),
+            "{}",
+            pattern
+        );
+    }
+}
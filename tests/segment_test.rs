@@ -0,0 +1,346 @@
+extern crate sagume;
+
+use sagume::builder::Builder;
+use sagume::document::Document;
+use sagume::field::Field;
+use sagume::query::{Clause, Presence, Query};
+use sagume::segment::{MergePolicy, SegmentStats, SegmentedIndex};
+use sagume::token::FuzzyOptions;
+
+fn documents() -> Vec<Document> {
+    let data = vec![
+        (
+            "a",
+            "Mr. Green kills Colonel Mustard",
+            "Mr. Green killed Colonel Mustard in the study with the candlestick",
+        ),
+        (
+            "b",
+            "Plumb waters plant",
+            "Professor Plumb has a green plant in his study",
+        ),
+        (
+            "c",
+            "Scarlett helps Professor",
+            "Miss Scarlett watered Professor Plumbs green plant while he was away",
+        ),
+    ];
+    data.into_iter()
+        .map(|(doc_ref, title, body)| {
+            let mut doc = Document::new(doc_ref.into());
+            doc.add_field(Field::new_text("title".into(), title.into()));
+            doc.add_field(Field::new_text("body".into(), body.into()));
+            doc
+        })
+        .collect()
+}
+
+fn new_builder() -> Builder {
+    let mut builder = Builder::new();
+    builder.add_field("title".into());
+    builder.add_field("body".into());
+    builder
+}
+
+fn single_segment_index() -> SegmentedIndex {
+    let mut index = SegmentedIndex::new(new_builder);
+    for doc in documents() {
+        index.add(doc);
+    }
+    index.flush();
+    index
+}
+
+fn multi_segment_index() -> SegmentedIndex {
+    let mut index = SegmentedIndex::new(new_builder);
+    index.set_flush_threshold(1);
+    for doc in documents() {
+        index.add(doc);
+    }
+    index
+}
+
+fn scores(index: &SegmentedIndex, terms: &str) -> Vec<(String, f64)> {
+    let mut q = Query::new();
+    for term in terms.split(' ') {
+        q.add_clause(Clause::new(term.into()));
+    }
+    let mut results: Vec<(String, f64)> = index
+        .query(&q)
+        .iter()
+        .map(|r| (r.doc_ref().to_string(), r.score()))
+        .collect();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    results
+}
+
+#[test]
+fn test_global_statistics() {
+    let single = single_segment_index();
+    let multi = multi_segment_index();
+    assert_eq!(single.segments().len(), 1);
+    assert_eq!(multi.segments().len(), 3);
+
+    // terms found in several segments weigh the same as in one segment
+    for term in [
+        "green",
+        "plant",
+        "professor",
+        "scarlett",
+        "green scarlett",
+        "plant mustard study",
+    ]
+    .iter()
+    {
+        let expected = scores(&single, term);
+        let actual = scores(&multi, term);
+        assert!(!expected.is_empty());
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert_eq!(a.0, e.0);
+            assert!((a.1 - e.1).abs() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn test_presence() {
+    let index = multi_segment_index();
+
+    let mut q = Query::new();
+    let mut c = Clause::new("green".into());
+    c.set_presence(Presence::Required);
+    q.add_clause(c);
+    let mut c = Clause::new("professor".into());
+    c.set_presence(Presence::Required);
+    q.add_clause(c);
+    let mut results: Vec<String> = index
+        .query(&q)
+        .iter()
+        .map(|r| r.doc_ref().to_string())
+        .collect();
+    results.sort();
+    assert_eq!(results, vec!["b", "c"]);
+
+    let mut q = Query::new();
+    let mut c = Clause::new("scarlett".into());
+    c.set_presence(Presence::Prohibited);
+    q.add_clause(c);
+    let mut results: Vec<String> = index
+        .query(&q)
+        .iter()
+        .map(|r| r.doc_ref().to_string())
+        .collect();
+    results.sort();
+    assert_eq!(results, vec!["a", "b"]);
+}
+
+#[test]
+fn test_delete() {
+    let mut index = multi_segment_index();
+    assert!(index.delete("c"));
+    assert!(!index.delete("c"));
+    assert_eq!(scores(&index, "scarlett"), vec![]);
+    assert_eq!(
+        scores(&index, "plant")
+            .into_iter()
+            .map(|r| r.0)
+            .collect::<Vec<String>>(),
+        vec!["b"]
+    );
+
+    index.set_merge_policy(MergePolicy {
+        merge_factor: 10,
+        max_segment_docs: usize::MAX,
+        max_deleted_ratio: 1.0,
+    });
+    assert!(index.delete("b"));
+    assert_eq!(
+        index.segments(),
+        vec![
+            SegmentStats {
+                documents: 1,
+                deleted: 0
+            },
+            SegmentStats {
+                documents: 0,
+                deleted: 1
+            },
+            SegmentStats {
+                documents: 0,
+                deleted: 1
+            },
+        ]
+    );
+
+    index.force_merge();
+    assert_eq!(
+        index.segments(),
+        vec![SegmentStats {
+            documents: 1,
+            deleted: 0
+        }]
+    );
+}
+
+#[test]
+fn test_update() {
+    let mut index = multi_segment_index();
+    let mut doc = Document::new("a".into());
+    doc.add_field(Field::new_text("title".into(), "Scarlett".into()));
+    index.add(doc);
+
+    let doc_refs: Vec<String> = scores(&index, "scarlett")
+        .into_iter()
+        .map(|r| r.0)
+        .collect();
+    assert_eq!(doc_refs, vec!["a", "c"]);
+    assert!(scores(&index, "mustard").is_empty());
+}
+
+#[test]
+fn test_merge_policy() {
+    let mut index = SegmentedIndex::new(new_builder);
+    index.set_flush_threshold(1);
+    index.set_merge_policy(MergePolicy {
+        merge_factor: 2,
+        max_segment_docs: usize::MAX,
+        max_deleted_ratio: 0.5,
+    });
+    for doc in documents() {
+        index.add(doc);
+    }
+    index.wait_for_merges();
+    let stats = index.segments();
+    assert_eq!(stats.iter().map(|s| s.documents).sum::<usize>(), 3);
+    assert!(stats.len() < 3);
+
+    let multi = multi_segment_index();
+    for term in ["green", "plant"].iter() {
+        let expected = scores(&multi, term);
+        let actual = scores(&index, term);
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert_eq!(a.0, e.0);
+            assert!((a.1 - e.1).abs() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn test_document_boost() {
+    let mut index = multi_segment_index();
+    let mut doc = Document::new("d".into());
+    doc.add_field(Field::new_text("title".into(), "Plumb waters plant".into()));
+    doc.add_field(Field::new_text(
+        "body".into(),
+        "Professor Plumb has a green plant in his study".into(),
+    ));
+    index.add_with_boost(doc, 2.0);
+
+    let results = scores(&index, "plumb");
+    assert_eq!(results[1].0, "d");
+    assert!((results[1].1 - results[0].1 * 2.0).abs() < 0.01);
+}
+
+#[test]
+fn test_fuzzy_expansion() {
+    let mut index = SegmentedIndex::new(new_builder);
+    index.set_flush_threshold(2);
+    for (doc_ref, title) in [
+        ("a", "plant"),
+        ("b", "plant"),
+        ("c", "plane"),
+        ("d", "plane"),
+        ("e", "plane"),
+    ] {
+        let mut doc = Document::new(doc_ref.into());
+        doc.add_field(Field::new_text("title".into(), title.into()));
+        index.add(doc);
+    }
+    index.flush();
+    assert_eq!(index.segments().len(), 3);
+
+    // plant is the more frequent term in the first segment, but plane is in
+    // more documents of the whole index
+    let mut clause = Clause::new("plank".into());
+    clause.set_fuzzy(FuzzyOptions::new(1));
+    clause.set_max_expansions(1);
+    let mut q = Query::new();
+    q.add_clause(clause);
+    let mut results: Vec<String> = index
+        .query(&q)
+        .iter()
+        .map(|r| r.doc_ref().to_string())
+        .collect();
+    results.sort();
+    assert_eq!(results, vec!["c", "d", "e"]);
+}
+
+#[test]
+fn test_regex_expansion() {
+    let mut index = SegmentedIndex::new(new_builder);
+    index.set_flush_threshold(1);
+    for (doc_ref, title) in [("a", "plumb"), ("b", "plane"), ("c", "plant")] {
+        let mut doc = Document::new(doc_ref.into());
+        doc.add_field(Field::new_text("title".into(), title.into()));
+        index.add(doc);
+    }
+    assert_eq!(index.segments().len(), 3);
+
+    // every segment has a match, but only the first two terms of the whole
+    // index are expanded
+    let mut clause = Clause::new_regex("pl.*").unwrap();
+    clause.set_max_expansions(2);
+    let mut q = Query::new();
+    q.add_clause(clause);
+    let mut results: Vec<String> = index
+        .query(&q)
+        .iter()
+        .map(|r| r.doc_ref().to_string())
+        .collect();
+    results.sort();
+    assert_eq!(results, vec!["b", "c"]);
+}
+
+#[test]
+fn test_delete_during_merge() {
+    let mut index = SegmentedIndex::new(new_builder);
+    index.set_flush_threshold(1);
+    index.set_merge_policy(MergePolicy {
+        merge_factor: 2,
+        max_segment_docs: usize::MAX,
+        max_deleted_ratio: 1.0,
+    });
+    let mut docs = documents().into_iter();
+    index.add(docs.next().unwrap());
+    // the second flush starts merging the first two segments
+    index.add(docs.next().unwrap());
+    assert!(index.delete("a"));
+    index.add(docs.next().unwrap());
+
+    // the index moves to another thread with its merge running
+    let index = std::thread::spawn(move || {
+        index.wait_for_merges();
+        index
+    })
+    .join()
+    .unwrap();
+    let stats = index.segments();
+    assert_eq!(stats.iter().map(|s| s.documents).sum::<usize>(), 2);
+    assert!(scores(&index, "mustard").is_empty());
+
+    let mut expected = SegmentedIndex::new(new_builder);
+    for doc in documents().into_iter().skip(1) {
+        expected.add(doc);
+    }
+    expected.flush();
+    for term in ["green", "plant", "scarlett"].iter() {
+        let expected = scores(&expected, term);
+        let actual = scores(&index, term);
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert_eq!(a.0, e.0);
+            assert!((a.1 - e.1).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+extern crate sagume;
+
+use sagume::builder::{Builder, FieldOptions};
+use sagume::document::Document;
+use sagume::field::{Field, FieldRef};
+use sagume::index::Index;
+use sagume::query::{Clause, Query};
+use sagume::segment::SegmentedIndex;
+use sagume::similarity::{Bm25, Bm25F, Bm25Plus, Dirichlet, Similarity, TermStats, TfIdf};
+
+fn stats(term_freq: usize, field_length: usize) -> TermStats {
+    TermStats {
+        term_freq,
+        field_length,
+        average_field_length: 10.0,
+        documents_with_term: 2,
+        doc_count: 10,
+        collection_term_freq: 4,
+        collection_length: 100,
+        boost: 1.0,
+    }
+}
+
+fn documents() -> Vec<Document> {
+    let data = vec![
+        (
+            "a",
+            "Green",
+            "Mr. Green killed Colonel Mustard in the study",
+        ),
+        (
+            "b",
+            "Plumb waters plant",
+            "Professor Plumb has a green plant",
+        ),
+        (
+            "c",
+            "Scarlett",
+            "Miss Scarlett watered the plant while he was away",
+        ),
+    ];
+    data.into_iter()
+        .map(|(doc_ref, title, body)| {
+            let mut doc = Document::new(doc_ref.into());
+            doc.add_field(Field::new_text("title".into(), title.into()));
+            doc.add_field(Field::new_text("body".into(), body.into()));
+            doc
+        })
+        .collect()
+}
+
+fn new_builder<S: Similarity + 'static>(similarity: S) -> Builder {
+    let mut builder = Builder::new();
+    let mut options = FieldOptions::new();
+    options.set_boost(2.0);
+    builder.add_field_with("title".into(), options);
+    builder.add_field("body".into());
+    builder.set_similarity(similarity);
+    builder
+}
+
+fn scores(index: &Index, term: &str) -> Vec<(String, f64)> {
+    let mut q = Query::new();
+    q.add_clause(Clause::new(term.into()));
+    let mut results: Vec<(String, f64)> = index
+        .query(&q)
+        .iter()
+        .map(|r| (r.doc_ref().to_string(), r.score()))
+        .collect();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    results
+}
+
+#[test]
+fn test_models() {
+    let bm25 = Bm25::default();
+    assert!(bm25.score(&stats(2, 10)) > bm25.score(&stats(1, 10)));
+    assert!(bm25.score(&stats(1, 5)) > bm25.score(&stats(1, 20)));
+
+    let plus = Bm25Plus::default();
+    assert!(plus.score(&stats(1, 1000)) > bm25.score(&stats(1, 1000)));
+
+    let tfidf = TfIdf;
+    assert!(tfidf.score(&stats(4, 10)) > tfidf.score(&stats(1, 10)));
+    assert!(tfidf.score(&stats(1, 4)) > tfidf.score(&stats(1, 16)));
+
+    let dirichlet = Dirichlet { mu: 10.0 };
+    assert!(dirichlet.score(&stats(3, 10)) > dirichlet.score(&stats(1, 10)));
+    assert!(dirichlet.score(&stats(0, 10)) >= 0.0);
+
+    // a field on its own is weighed the same as by BM25, except for the
+    // length normalization of k1
+    let bm25f = Bm25F { k1: 1.2, b: 0.0 };
+    let bm25 = Bm25 { k1: 1.2, b: 0.0 };
+    assert!((bm25f.score(&stats(2, 10)) - bm25.score(&stats(2, 10))).abs() < 1e-9);
+
+    // fields are saturated together
+    let mut title = stats(1, 10);
+    title.boost = 2.0;
+    let scores = bm25f.score_fields(&[title.clone(), stats(1, 10)]);
+    assert!((scores[0] - scores[1] * 2.0).abs() < 1e-9);
+    assert!(scores[0] + scores[1] < bm25.score(&title) + bm25.score(&stats(1, 10)));
+}
+
+#[test]
+fn test_default_similarity() {
+    let mut builder = Builder::new();
+    builder.add_field("title".into());
+    builder.add_field("body".into());
+    let mut with_bm25 = Builder::new();
+    with_bm25.add_field("title".into());
+    with_bm25.add_field("body".into());
+    with_bm25.set_similarity(Bm25::default());
+    for doc in documents() {
+        builder.add_document(doc);
+    }
+    for doc in documents() {
+        with_bm25.add_document(doc);
+    }
+    let (index, with_bm25) = (builder.build(), with_bm25.build());
+    for (term, ri) in index.inverted_index() {
+        let other = &with_bm25.inverted_index()[term];
+        for (field_ref, vector) in index.field_vectors() {
+            assert_eq!(
+                vector.get(ri.index as usize),
+                with_bm25.field_vectors()[field_ref].get(other.index as usize)
+            );
+        }
+    }
+    assert_eq!(scores(&index, "plant"), scores(&with_bm25, "plant"));
+}
+
+#[test]
+fn test_parameters_of_similarity() {
+    let mut builder = new_builder(Bm25::default());
+    builder.b(0.2);
+    builder.k1(2.0);
+    let mut with_bm25 = new_builder(Bm25 { k1: 2.0, b: 0.2 });
+    let mut with_bm25f = new_builder(Bm25F::default());
+    with_bm25f.b(0.2);
+    with_bm25f.k1(2.0);
+    let mut with_own = new_builder(Bm25F { k1: 2.0, b: 0.2 });
+    for doc in documents() {
+        builder.add_document(doc);
+    }
+    for doc in documents() {
+        with_bm25.add_document(doc);
+    }
+    for doc in documents() {
+        with_bm25f.add_document(doc);
+    }
+    for doc in documents() {
+        with_own.add_document(doc);
+    }
+    assert_eq!(
+        scores(&builder.build(), "plant"),
+        scores(&with_bm25.build(), "plant")
+    );
+    assert_eq!(
+        scores(&with_bm25f.build(), "plant"),
+        scores(&with_own.build(), "plant")
+    );
+}
+
+#[test]
+fn test_set_similarity() {
+    let mut bm25 = new_builder(Bm25::default());
+    let mut tfidf = new_builder(TfIdf);
+    for doc in documents() {
+        bm25.add_document(doc);
+    }
+    for doc in documents() {
+        tfidf.add_document(doc);
+    }
+    let bm25 = bm25.build();
+    let tfidf = tfidf.build();
+    assert_eq!(bm25.field_vectors().len(), tfidf.field_vectors().len());
+
+    let field_ref = FieldRef::new("b".into(), "body".into());
+    let weight = |index: &Index| {
+        let ri = &index.inverted_index()["plant"];
+        index
+            .field_vector(&field_ref)
+            .unwrap()
+            .get(ri.index as usize)
+    };
+    assert!(weight(&bm25).unwrap() > 0.0);
+    assert!(weight(&tfidf).unwrap() > 0.0);
+    assert_ne!(weight(&bm25), weight(&tfidf));
+}
+
+struct Constant;
+
+impl Similarity for Constant {
+    fn score(&self, stats: &TermStats) -> f64 {
+        stats.boost
+    }
+}
+
+#[test]
+fn test_custom_similarity() {
+    let mut builder = new_builder(Constant);
+    for doc in documents() {
+        builder.add_document(doc);
+    }
+    let index = builder.build();
+    let ri = &index.inverted_index()["plant"];
+    let vector = index
+        .field_vector(&FieldRef::new("b".into(), "title".into()))
+        .unwrap();
+    assert_eq!(vector.get(ri.index as usize), Some(2.0));
+}
+
+#[test]
+fn test_segments() {
+    for similarity in 0..3 {
+        let new_builder = move || match similarity {
+            0 => new_builder(Bm25Plus::default()),
+            1 => new_builder(Bm25F::default()),
+            _ => new_builder(Dirichlet { mu: 10.0 }),
+        };
+        let mut single = SegmentedIndex::new(new_builder);
+        let mut multi = SegmentedIndex::new(new_builder);
+        multi.set_flush_threshold(1);
+        for doc in documents() {
+            single.add(doc);
+        }
+        for doc in documents() {
+            multi.add(doc);
+        }
+        single.flush();
+
+        for term in ["green", "plant", "scarlett"].iter() {
+            let mut q = Query::new();
+            q.add_clause(Clause::new(term.to_string()));
+            let mut expected: Vec<(String, f64)> = single
+                .query(&q)
+                .iter()
+                .map(|r| (r.doc_ref().to_string(), r.score()))
+                .collect();
+            let mut actual: Vec<(String, f64)> = multi
+                .query(&q)
+                .iter()
+                .map(|r| (r.doc_ref().to_string(), r.score()))
+                .collect();
+            expected.sort_by(|a, b| a.0.cmp(&b.0));
+            actual.sort_by(|a, b| a.0.cmp(&b.0));
+            assert_eq!(actual.len(), expected.len());
+            for (a, e) in actual.iter().zip(expected.iter()) {
+                assert_eq!(a.0, e.0);
+                assert!((a.1 - e.1).abs() < 1e-9);
+            }
+        }
+    }
+}
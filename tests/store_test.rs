@@ -0,0 +1,146 @@
+extern crate sagume;
+
+use sagume::binary::MappedIndex;
+use sagume::builder::{Builder, FieldOptions};
+use sagume::document::Document;
+use sagume::field::{Field, FieldValue};
+use sagume::index::Index;
+use sagume::query::{Clause, Query};
+use sagume::store::DocumentStore;
+
+use std::collections::BTreeMap;
+
+fn get_index() -> Index {
+    let mut builder = Builder::new();
+    let mut options = FieldOptions::new();
+    options.set_stored(true);
+    builder.add_field_with("title".into(), options);
+    builder.add_field("body".into());
+    let mut options = FieldOptions::new();
+    options.set_stored(true);
+    options.set_extractor(|doc| doc.get("author").map(|v| v.to_string().to_uppercase()));
+    builder.add_field_with("author".into(), options);
+
+    let mut doc = Document::new("a".into());
+    doc.add_field(Field::new_text(
+        "title".into(),
+        "Mr. Green kills Colonel Mustard".into(),
+    ));
+    doc.add_field(Field::new_text(
+        "body".into(),
+        "Mr. Green killed Colonel Mustard in the study with the candlestick".into(),
+    ));
+    doc.add_field(Field::new_text("author".into(), "plumb".into()));
+    builder.add_document(doc);
+
+    let mut doc = Document::new("b".into());
+    doc.add_field(Field::new_text("title".into(), "Plumb waters plant".into()));
+    doc.add_field(Field::new_text(
+        "body".into(),
+        "Professor Plumb has a green plant in his study".into(),
+    ));
+    builder.add_document(doc);
+
+    builder.build()
+}
+
+#[test]
+fn test_document_store() {
+    let mut map = BTreeMap::new();
+    map.insert("name".to_string(), FieldValue::Text("plumb".into()));
+    map.insert("age".to_string(), FieldValue::I64(-42));
+    let fields = vec![
+        Field::new_u64("id".into(), 300),
+        Field::new(
+            "tags".into(),
+            FieldValue::Array(vec![
+                FieldValue::Text("green".into()),
+                FieldValue::Object(map),
+            ]),
+        ),
+    ];
+
+    let mut store = DocumentStore::new();
+    assert!(store.is_empty());
+    store.insert("a", &fields);
+    assert_eq!(store.len(), 1);
+    assert!(store.contains("a"));
+
+    let doc = store.get("a").unwrap();
+    assert_eq!(doc.doc_ref(), "a");
+    let actual: Vec<&Field> = doc.get_all_fields();
+    assert_eq!(actual.len(), 2);
+    for (a, e) in actual.iter().zip(fields.iter()) {
+        assert_eq!(a.name(), e.name());
+        assert_eq!(a.value(), e.value());
+    }
+
+    assert!(store.remove("a"));
+    assert!(store.get("a").is_none());
+}
+
+#[test]
+fn test_stored_fields() {
+    let index = get_index();
+
+    let doc = index.doc("a").unwrap();
+    assert_eq!(
+        doc.get("title"),
+        Some(&FieldValue::Text("Mr. Green kills Colonel Mustard".into()))
+    );
+    assert_eq!(doc.get("author"), Some(&FieldValue::Text("PLUMB".into())));
+    assert!(doc.get("body").is_none());
+
+    let doc = index.doc("b").unwrap();
+    assert!(doc.get("author").is_none());
+    assert!(index.doc("c").is_none());
+}
+
+#[test]
+fn test_query_with_fields() {
+    let index = get_index();
+    let mut q = Query::new();
+    q.add_clause(Clause::new("plant".into()));
+
+    let results = index.query_with_fields(&q, &["title"]);
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].fields().len(), 1);
+    assert_eq!(
+        results[0].get("title"),
+        Some(&FieldValue::Text("Plumb waters plant".into()))
+    );
+
+    let results = index.query(&q);
+    assert!(results[0].fields().is_empty());
+}
+
+#[test]
+fn test_remove_document() {
+    let mut builder = Builder::new();
+    let mut options = FieldOptions::new();
+    options.set_stored(true);
+    builder.add_field_with("title".into(), options);
+    let mut doc = Document::new("a".into());
+    doc.add_field(Field::new_text("title".into(), "green".into()));
+    builder.add_document(doc);
+    builder.remove_document("a");
+
+    assert!(builder.build().doc("a").is_none());
+}
+
+#[test]
+fn test_binary() {
+    let index = get_index();
+    let mapped = MappedIndex::from_bytes(index.to_bytes().unwrap()).unwrap();
+
+    let doc = mapped.doc("a").unwrap();
+    assert_eq!(doc.get("author"), Some(&FieldValue::Text("PLUMB".into())));
+    assert!(mapped.doc("c").is_none());
+
+    let index = mapped.to_index().unwrap();
+    let doc = index.doc("b").unwrap();
+    assert_eq!(
+        doc.get("title"),
+        Some(&FieldValue::Text("Plumb waters plant".into()))
+    );
+}
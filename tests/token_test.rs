@@ -1,132 +1,132 @@
 extern crate sagume;
 
-use sagume::token::TokenSet;
+use sagume::token::{FuzzyOptions, TokenSet};
 
 #[test]
 fn test_to_vec() {
-    let set = TokenSet::from_string("bat".into());
+    let set = TokenSet::from_string("bat");
     assert_eq!(set.to_vec(), vec!["bat"]);
 }
 
 #[test]
 fn test_intersect() {
-    let x = TokenSet::from_string("cat".into());
-    let y = TokenSet::from_string("dog".into());
+    let x = TokenSet::from_string("cat");
+    let y = TokenSet::from_string("dog");
     let z = x.intersect(&y);
     assert!(z.to_vec().is_empty());
 
-    let x = TokenSet::from_string("cat".into());
-    let y = TokenSet::from_string("cat".into());
+    let x = TokenSet::from_string("cat");
+    let y = TokenSet::from_string("cat");
     let z = x.intersect(&y);
     assert_eq!(z.to_vec(), vec!["cat"]);
 
-    let x = TokenSet::from_string("cat".into());
-    let y = TokenSet::from_string("c*".into());
+    let x = TokenSet::from_string("cat");
+    let y = TokenSet::from_string("c*");
     let z = x.intersect(&y);
     assert_eq!(z.to_vec(), vec!["cat"]);
 
-    let x = TokenSet::from_string("cat".into());
-    let y = TokenSet::from_string("d*".into());
+    let x = TokenSet::from_string("cat");
+    let y = TokenSet::from_string("d*");
     let z = x.intersect(&y);
     assert!(z.to_vec().is_empty());
 
-    let x = TokenSet::from_string("cat".into());
-    let y = TokenSet::from_string("*t".into());
+    let x = TokenSet::from_string("cat");
+    let y = TokenSet::from_string("*t");
     let z = x.intersect(&y);
     assert_eq!(z.to_vec(), vec!["cat"]);
 
-    let x = TokenSet::from_string("aaacbab".into());
-    let y = TokenSet::from_string("*ab".into());
+    let x = TokenSet::from_string("aaacbab");
+    let y = TokenSet::from_string("*ab");
     let z = x.intersect(&y);
     assert_eq!(z.to_vec(), vec!["aaacbab"]);
 
-    let x = TokenSet::from_string("cat".into());
-    let y = TokenSet::from_string("*r".into());
+    let x = TokenSet::from_string("cat");
+    let y = TokenSet::from_string("*r");
     let z = x.intersect(&y);
     assert!(z.to_vec().is_empty());
 
-    let x = TokenSet::from_string("aaabdcbc".into());
-    let y = TokenSet::from_string("*abc".into());
+    let x = TokenSet::from_string("aaabdcbc");
+    let y = TokenSet::from_string("*abc");
     let z = x.intersect(&y);
     assert!(z.to_vec().is_empty());
 
-    let x = TokenSet::from_string("foo".into());
-    let y = TokenSet::from_string("f*o".into());
+    let x = TokenSet::from_string("foo");
+    let y = TokenSet::from_string("f*o");
     let z = x.intersect(&y);
     assert_eq!(z.to_vec(), vec!["foo"]);
 
-    let x = TokenSet::from_string("ababc".into());
-    let y = TokenSet::from_string("a*bc".into());
+    let x = TokenSet::from_string("ababc");
+    let y = TokenSet::from_string("a*bc");
     let z = x.intersect(&y);
     assert_eq!(z.to_vec(), vec!["ababc"]);
 
-    let x = TokenSet::from_string("foo".into());
-    let y = TokenSet::from_string("b*r".into());
+    let x = TokenSet::from_string("foo");
+    let y = TokenSet::from_string("b*r");
     let z = x.intersect(&y);
     assert!(z.to_vec().is_empty());
 
-    let x = TokenSet::from_string("ababc".into());
-    let y = TokenSet::from_string("a*ac".into());
+    let x = TokenSet::from_string("ababc");
+    let y = TokenSet::from_string("a*ac");
     let z = x.intersect(&y);
     assert!(z.to_vec().is_empty());
 
-    let x = TokenSet::from_string("foo".into());
-    let y = TokenSet::from_string("foo*".into());
+    let x = TokenSet::from_string("foo");
+    let y = TokenSet::from_string("foo*");
     let z = x.intersect(&y);
     assert_eq!(z.to_vec(), vec!["foo"]);
 
     let x = TokenSet::from_string(
-        "fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff".into(),
+        "fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
     );
-    let y = TokenSet::from_string("*ff".into());
+    let y = TokenSet::from_string("*ff");
     let z = x.intersect(&y);
     assert_eq!(z.to_vec().len(), 1);
 
-    let x = TokenSet::from_string("acbaabab".into());
-    let y = TokenSet::from_string("*ab*".into());
+    let x = TokenSet::from_string("acbaabab");
+    let y = TokenSet::from_string("*ab*");
     let z = x.intersect(&y);
     assert_eq!(z.to_vec(), vec!["acbaabab"]);
 
-    let x = TokenSet::from_string("acbaabab".into());
-    let y = TokenSet::from_string("a*ba*b".into());
+    let x = TokenSet::from_string("acbaabab");
+    let y = TokenSet::from_string("a*ba*b");
     let z = x.intersect(&y);
     assert_eq!(z.to_vec(), vec!["acbaabab"]);
 }
 
 #[test]
 fn test_intersect_with_fuzzy_string() {
-    let x1 = TokenSet::from_string("bar".into());
-    let x2 = TokenSet::from_string("cur".into());
-    let x3 = TokenSet::from_string("cat".into());
-    let x4 = TokenSet::from_string("car".into());
-    let x5 = TokenSet::from_string("for".into());
-    let y = TokenSet::from_fuzzy_string("car".into(), 1);
+    let x1 = TokenSet::from_string("bar");
+    let x2 = TokenSet::from_string("cur");
+    let x3 = TokenSet::from_string("cat");
+    let x4 = TokenSet::from_string("car");
+    let x5 = TokenSet::from_string("for");
+    let y = TokenSet::from_fuzzy_string("car", 1);
     assert_eq!(x1.intersect(&y).to_vec(), vec!["bar"]);
     assert_eq!(x2.intersect(&y).to_vec(), vec!["cur"]);
     assert_eq!(x3.intersect(&y).to_vec(), vec!["cat"]);
     assert_eq!(x4.intersect(&y).to_vec(), vec!["car"]);
     assert!(x5.intersect(&y).to_vec().is_empty());
 
-    let x1 = TokenSet::from_string("ar".into());
-    let x2 = TokenSet::from_string("br".into());
-    let x3 = TokenSet::from_string("ba".into());
-    let x4 = TokenSet::from_string("bar".into());
-    let x5 = TokenSet::from_string("foo".into());
-    let y = TokenSet::from_fuzzy_string("bar".into(), 1);
+    let x1 = TokenSet::from_string("ar");
+    let x2 = TokenSet::from_string("br");
+    let x3 = TokenSet::from_string("ba");
+    let x4 = TokenSet::from_string("bar");
+    let x5 = TokenSet::from_string("foo");
+    let y = TokenSet::from_fuzzy_string("bar", 1);
     assert_eq!(x1.intersect(&y).to_vec(), vec!["ar"]);
     assert_eq!(x2.intersect(&y).to_vec(), vec!["br"]);
     assert_eq!(x3.intersect(&y).to_vec(), vec!["ba"]);
     assert_eq!(x4.intersect(&y).to_vec(), vec!["bar"]);
     assert!(x5.intersect(&y).to_vec().is_empty());
 
-    let x1 = TokenSet::from_string("bbar".into());
-    let x2 = TokenSet::from_string("baar".into());
-    let x3 = TokenSet::from_string("barr".into());
-    let x4 = TokenSet::from_string("bar".into());
-    let x5 = TokenSet::from_string("ba".into());
-    let x6 = TokenSet::from_string("foo".into());
-    let x7 = TokenSet::from_string("bara".into());
-    let y = TokenSet::from_fuzzy_string("bar".into(), 1);
+    let x1 = TokenSet::from_string("bbar");
+    let x2 = TokenSet::from_string("baar");
+    let x3 = TokenSet::from_string("barr");
+    let x4 = TokenSet::from_string("bar");
+    let x5 = TokenSet::from_string("ba");
+    let x6 = TokenSet::from_string("foo");
+    let x7 = TokenSet::from_string("bara");
+    let y = TokenSet::from_fuzzy_string("bar", 1);
     assert_eq!(x1.intersect(&y).to_vec(), vec!["bbar"]);
     assert_eq!(x2.intersect(&y).to_vec(), vec!["baar"]);
     assert_eq!(x3.intersect(&y).to_vec(), vec!["barr"]);
@@ -135,28 +135,28 @@ fn test_intersect_with_fuzzy_string() {
     assert!(x6.intersect(&y).to_vec().is_empty());
     assert_eq!(x7.intersect(&y).to_vec(), vec!["bara"]);
 
-    let x1 = TokenSet::from_string("abr".into());
-    let x2 = TokenSet::from_string("bra".into());
-    let x3 = TokenSet::from_string("foo".into());
-    let y = TokenSet::from_fuzzy_string("bar".into(), 1);
+    let x1 = TokenSet::from_string("abr");
+    let x2 = TokenSet::from_string("bra");
+    let x3 = TokenSet::from_string("foo");
+    let y = TokenSet::from_fuzzy_string("bar", 1);
     assert_eq!(x1.intersect(&y).to_vec(), vec!["abr"]);
     assert_eq!(x2.intersect(&y).to_vec(), vec!["bra"]);
     assert!(x3.intersect(&y).to_vec().is_empty());
 
-    let x = TokenSet::from_string("abcxx".into());
-    let y = TokenSet::from_fuzzy_string("abc".into(), 2);
+    let x = TokenSet::from_string("abcxx");
+    let y = TokenSet::from_fuzzy_string("abc", 2);
     assert_eq!(x.intersect(&y).to_vec(), vec!["abcxx"]);
 
-    let x = TokenSet::from_string("axx".into());
-    let y = TokenSet::from_fuzzy_string("abc".into(), 2);
+    let x = TokenSet::from_string("axx");
+    let y = TokenSet::from_fuzzy_string("abc", 2);
     assert_eq!(x.intersect(&y).to_vec(), vec!["axx"]);
 
-    let x = TokenSet::from_string("a".into());
-    let y = TokenSet::from_fuzzy_string("abc".into(), 2);
+    let x = TokenSet::from_string("a");
+    let y = TokenSet::from_fuzzy_string("abc", 2);
     assert_eq!(x.intersect(&y).to_vec(), vec!["a"]);
 
-    let x = TokenSet::from_string("bca".into());
-    let y = TokenSet::from_fuzzy_string("abc".into(), 2);
+    let x = TokenSet::from_string("bca");
+    let y = TokenSet::from_fuzzy_string("abc", 2);
     assert_eq!(x.intersect(&y).to_vec(), vec!["bca"]);
 }
 
@@ -174,3 +174,156 @@ fn test_from_array() {
     let v2 = vec!["a", "z"];
     assert_eq!(v1, v2);
 }
+
+#[test]
+fn test_from_array_to_vec() {
+    let mut words: Vec<String> = (0..2000).map(|i| format!("w{}x{}", i % 37, i)).collect();
+    words.push("é".into());
+    words.sort();
+    let set = TokenSet::from_array(&words);
+    assert_eq!(set.to_vec(), words);
+
+    let prefix = TokenSet::from_string("w3x3*");
+    let expected: Vec<&str> = words
+        .iter()
+        .filter(|w| w.starts_with("w3x3"))
+        .map(|w| w.as_str())
+        .collect();
+    assert_eq!(set.intersect(&prefix).to_vec(), expected);
+}
+
+fn set(words: &[&str]) -> TokenSet {
+    let mut words: Vec<String> = words.iter().map(|w| w.to_string()).collect();
+    words.sort();
+    TokenSet::from_array(&words)
+}
+
+#[test]
+fn test_union() {
+    let x = set(&["apple", "apply", "banana"]);
+    let y = set(&["apple", "apricot", "cherry"]);
+    assert_eq!(
+        x.union(&y).to_vec(),
+        vec!["apple", "apply", "apricot", "banana", "cherry"]
+    );
+    assert_eq!(x.union(&set(&[])).to_vec(), x.to_vec());
+
+    let q = TokenSet::from_string("ch*").union(&TokenSet::from_string("ban*"));
+    let z = x.union(&y).intersect(&q);
+    assert_eq!(z.to_vec(), vec!["banana", "cherry"]);
+}
+
+#[test]
+fn test_difference() {
+    let x = set(&["apple", "apply", "banana", "band"]);
+    let y = set(&["apple", "band", "cherry"]);
+    let z = x.difference(&y);
+    assert_eq!(z.to_vec(), vec!["apply", "banana"]);
+    assert!(x.difference(&x).to_vec().is_empty());
+    assert_eq!(x.difference(&set(&[])).to_vec(), x.to_vec());
+    assert_eq!(
+        z.to_nodes().len(),
+        set(&["apply", "banana"]).to_nodes().len()
+    );
+}
+
+#[test]
+fn test_range() {
+    let x = set(&["apple", "applet", "apply", "apq", "apricot", "ap", "b"]);
+    let mut iter = x.iter();
+    assert_eq!(iter.next().unwrap(), "ap");
+    assert_eq!(iter.next().unwrap(), "apple");
+
+    assert_eq!(
+        x.range("app".."apq").collect::<Vec<_>>(),
+        vec!["apple", "applet", "apply"]
+    );
+    assert_eq!(
+        x.range("apple"..="apq").collect::<Vec<_>>(),
+        vec!["apple", "applet", "apply", "apq"]
+    );
+    assert_eq!(
+        x.range((
+            std::ops::Bound::Excluded("apple"),
+            std::ops::Bound::Unbounded
+        ))
+        .collect::<Vec<_>>(),
+        vec!["applet", "apply", "apq", "apricot", "b"]
+    );
+    assert_eq!(x.range(.."apple").collect::<Vec<_>>(), vec!["ap"]);
+    assert!(x.range("c"..).next().is_none());
+
+    // a wildcard set is infinite, but can be walked lazily
+    let y = TokenSet::from_string("a*");
+    assert_eq!(y.iter().take(3).collect::<Vec<_>>(), vec!["a", "a*", "a**"]);
+}
+
+#[test]
+fn test_fuzzy_distance() {
+    let options = FuzzyOptions::new(2);
+    assert_eq!(options.distance("receive", "receive"), Some(0));
+    assert_eq!(options.distance("recieve", "receive"), Some(1));
+    assert_eq!(options.distance("car", "cart"), Some(1));
+    assert_eq!(options.distance("cart", "car"), Some(1));
+    assert_eq!(options.distance("car", "bat"), Some(2));
+    assert_eq!(options.distance("car", "bats"), None);
+
+    let mut options = FuzzyOptions::new(2);
+    options.transpositions = false;
+    assert_eq!(options.distance("recieve", "receive"), Some(2));
+    options.substitutions = false;
+    assert_eq!(options.distance("car", "cat"), Some(2));
+    options.insertions = false;
+    assert_eq!(options.distance("car", "cart"), None);
+    assert_eq!(options.distance("cart", "car"), Some(1));
+
+    let mut options = FuzzyOptions::new(1);
+    options.prefix_length = 2;
+    assert_eq!(options.distance("card", "cart"), Some(1));
+    assert_eq!(options.distance("card", "bard"), None);
+    assert_eq!(options.distance("card", "c"), None);
+}
+
+#[test]
+fn test_fuzzy_options() {
+    let words: Vec<String> = [
+        "a", "ab", "abc", "acb", "bac", "bar", "bra", "car", "card", "care", "cart", "cat", "crab",
+        "rac", "racecar", "receive", "recieve", "relieve",
+    ]
+    .iter()
+    .map(|w| w.to_string())
+    .collect();
+    let set = TokenSet::from_array(&words);
+
+    let mut all_options = Vec::new();
+    for edit_distance in 0..3 {
+        for prefix_length in 0..3 {
+            for ops in 0..16 {
+                all_options.push(FuzzyOptions {
+                    edit_distance,
+                    prefix_length,
+                    insertions: ops & 1 != 0,
+                    deletions: ops & 2 != 0,
+                    substitutions: ops & 4 != 0,
+                    transpositions: ops & 8 != 0,
+                });
+            }
+        }
+    }
+    for options in all_options.iter() {
+        for source in ["car", "recieve", "abc", "ab"] {
+            let expected: Vec<String> = words
+                .iter()
+                .filter(|w| options.distance(source, w).is_some())
+                .cloned()
+                .collect();
+            let found: Vec<String> = set
+                .intersect(&TokenSet::from_fuzzy(source, options))
+                .to_vec()
+                .into_iter()
+                .filter(|w| options.distance(source, w).is_some())
+                .collect();
+            assert_eq!(found, expected, "{} {:?}", source, options);
+        }
+    }
+}
@@ -5,25 +5,25 @@ use sagume::tokenizer::Tokenizer;
 #[test]
 fn test_splitting_into_tokens() {
     let tokenizer = Tokenizer::new();
-    let tokens = tokenizer.tokenize("foo bar baz".into());
+    let tokens = tokenizer.tokenize("foo bar baz");
     assert_eq!(
         tokens.iter().map(|t| &t.value).collect::<Vec<&String>>(),
         vec!["foo", "bar", "baz"]
     );
 
-    let tokens = tokenizer.tokenize("Foo Bar BAZ".into());
+    let tokens = tokenizer.tokenize("Foo Bar BAZ");
     assert_eq!(
         tokens.iter().map(|t| &t.value).collect::<Vec<&String>>(),
         vec!["foo", "bar", "baz"]
     );
 
-    let tokens = tokenizer.tokenize("foo    bar - baz".into());
+    let tokens = tokenizer.tokenize("foo    bar - baz");
     assert_eq!(
         tokens.iter().map(|t| &t.value).collect::<Vec<&String>>(),
         vec!["foo", "bar", "baz"]
     );
 
-    let tokens = tokenizer.tokenize("foo--bar-baz".into());
+    let tokens = tokenizer.tokenize("foo--bar-baz");
     assert_eq!(
         tokens.iter().map(|t| &t.value).collect::<Vec<&String>>(),
         vec!["foo", "bar", "baz"]
@@ -33,9 +33,19 @@ fn test_splitting_into_tokens() {
 #[test]
 fn test_token_index() {
     let tokenizer = Tokenizer::new();
-    let tokens = tokenizer.tokenize("foo bar".into());
+    let tokens = tokenizer.tokenize("foo bar");
     assert_eq!(tokens[0].index, 0);
     assert_eq!(tokens[1].index, 1);
     assert_eq!(tokens[0].start, 0);
     assert_eq!(tokens[1].start, 4);
 }
+
+#[test]
+fn test_with_separator() {
+    let tokenizer = Tokenizer::with_separator(|c| c == ',');
+    let tokens = tokenizer.tokenize("foo,bar baz");
+    assert_eq!(
+        tokens.iter().map(|t| &t.value).collect::<Vec<&String>>(),
+        vec!["foo", "bar baz"]
+    );
+}
@@ -15,6 +15,14 @@ fn test_dot() {
     assert_eq!(v2.dot(&v1), 3.0);
 }
 
+#[test]
+fn test_sparse_dot() {
+    let v1 = Vector::from_flat_vec(vec![1.0, 2.0, 4.0, 3.0, 7.0, 5.0]);
+    let v2 = Vector::from_flat_vec(vec![4.0, 10.0]);
+    assert_eq!(v1.dot(&v2), 30.0);
+    assert_eq!(v2.dot(&v1), 30.0);
+}
+
 #[test]
 fn test_similarity() {
     let v1 = Vector::from(vec![1.0, 3.0, -5.0]);
@@ -50,3 +58,52 @@ fn test_insert_panic() {
     v1.insert(1, 20.0);
     v1.insert(1, 30.0);
 }
+
+#[test]
+fn test_upsert() {
+    let mut v = Vector::new();
+    v.upsert(5, 1.0);
+    v.upsert(1, 2.0);
+    v.upsert(3, 3.0);
+    assert_eq!(v.magnitude(), 14.0f64.sqrt());
+    v.upsert(5, 4.0);
+    assert_eq!(v.len(), 3);
+    assert_eq!(v.to_flat_vec(), vec![1.0, 2.0, 3.0, 3.0, 5.0, 4.0]);
+    assert_eq!(v.get(5), Some(4.0));
+    assert_eq!(v.get(2), None);
+    assert_eq!(v.magnitude(), 29.0f64.sqrt());
+}
+
+#[test]
+fn test_from_flat_vec() {
+    let v = Vector::from_flat_vec(vec![3.0, 1.0, 1.0, 2.0, 3.0, 5.0]);
+    assert_eq!(v.to_flat_vec(), vec![1.0, 2.0, 3.0, 5.0]);
+    assert_eq!(v.get(3), Some(5.0));
+}
+
+#[test]
+fn test_add_scale() {
+    let v1 = Vector::from_flat_vec(vec![0.0, 1.0, 2.0, 2.0, 7.0, 3.0]);
+    let v2 = Vector::from_flat_vec(vec![2.0, -2.0, 4.0, 1.0]);
+    assert_eq!(
+        v1.add(&v2).to_flat_vec(),
+        vec![0.0, 1.0, 2.0, 0.0, 4.0, 1.0, 7.0, 3.0]
+    );
+    assert_eq!(v2.add(&Vector::new()).to_flat_vec(), v2.to_flat_vec());
+    assert_eq!(v2.scale(2.0).to_flat_vec(), vec![2.0, -4.0, 4.0, 2.0]);
+    assert_eq!(v2.scale(2.0).magnitude(), 2.0 * v2.magnitude());
+}
+
+#[test]
+fn test_cosine_similarity() {
+    let v1 = Vector::from(vec![1.0, 3.0, -5.0]);
+    let v2 = Vector::from(vec![4.0, -2.0, -1.0]);
+    let expected = 3.0 / (35.0f64.sqrt() * 21.0f64.sqrt());
+    assert!((v1.similarity(&v2) - expected).abs() < 1e-12);
+    assert!((v1.similarity(&v2.scale(10.0)) - expected).abs() < 1e-12);
+    assert!((v1.similarity(&v1) - 1.0).abs() < 1e-12);
+
+    // score keeps the magnitude of the other vector
+    assert!((v1.score(&v2) - 3.0 / 35.0f64.sqrt()).abs() < 1e-12);
+    assert!((v1.score(&v2.scale(10.0)) - 30.0 / 35.0f64.sqrt()).abs() < 1e-12);
+}